@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::io::{Cursor, Read};
+
+/// Archive formats sources can be published in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    /// gzip, either a `.tar.gz` or a single compressed file
+    Gzip,
+}
+
+/// Recognizes an archive by its magic bytes.
+pub fn detect(bytes: &[u8]) -> Option<ArchiveKind> {
+    if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+        Some(ArchiveKind::Zip)
+    } else if bytes.starts_with(&[0x1f, 0x8b]) {
+        Some(ArchiveKind::Gzip)
+    } else if is_tar(bytes) {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+/// A file extracted from an archive, or why it couldn't be.
+pub struct Entry {
+    pub name: String,
+    pub data: Result<Vec<u8>>,
+}
+
+/// Extracts every regular file of an archive in memory.
+///
+/// Entries are read at most `max_size` bytes deep, so a compression bomb fails
+/// that entry instead of exhausting memory.
+pub fn extract(bytes: &[u8], kind: ArchiveKind, max_size: u64) -> Result<Vec<Entry>> {
+    match kind {
+        ArchiveKind::Zip => extract_zip(bytes, max_size),
+        ArchiveKind::Tar => extract_tar(bytes, max_size),
+        ArchiveKind::Gzip => {
+            let data = read_limited(GzDecoder::new(bytes), max_size)?;
+            if is_tar(&data) {
+                extract_tar(&data, max_size)
+            } else {
+                Ok(vec![Entry {
+                    name: String::new(),
+                    data: Ok(data),
+                }])
+            }
+        }
+    }
+}
+
+fn extract_zip(bytes: &[u8], max_size: u64) -> Result<Vec<Entry>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).context("Invalid zip archive")?;
+
+    let mut entries = Vec::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if !file.is_file() {
+            continue;
+        }
+
+        let name = file.name()?.into_owned();
+        let data = read_limited(file, max_size);
+        entries.push(Entry { name, data });
+    }
+
+    Ok(entries)
+}
+
+fn extract_tar(bytes: &[u8], max_size: u64) -> Result<Vec<Entry>> {
+    let mut archive = tar::Archive::new(bytes);
+
+    let mut entries = Vec::new();
+    for entry in archive.entries().context("Invalid tar archive")? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let name = entry.path()?.display().to_string();
+        let data = read_limited(entry, max_size);
+        entries.push(Entry { name, data });
+    }
+
+    Ok(entries)
+}
+
+fn read_limited(reader: impl Read, max_size: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(max_size + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max_size {
+        anyhow::bail!("Too large (over {} bytes uncompressed)", max_size);
+    }
+    Ok(data)
+}
+
+fn is_tar(bytes: &[u8]) -> bool {
+    bytes.get(257..262) == Some(b"ustar")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn tar_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, content) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn names_and_data(entries: Vec<Entry>) -> Vec<(String, String)> {
+        entries
+            .into_iter()
+            .map(|e| (e.name, String::from_utf8(e.data.unwrap()).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let bytes = zip_archive(&[("a.txt", "ss://a"), ("b.txt", "ss://b")]);
+        assert_eq!(detect(&bytes), Some(ArchiveKind::Zip));
+
+        let entries = names_and_data(extract(&bytes, ArchiveKind::Zip, 1024).unwrap());
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), "ss://a".to_string()),
+                ("b.txt".to_string(), "ss://b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_tar_gz() {
+        let tar = tar_archive(&[("daily/a.txt", "ss://a")]);
+        assert_eq!(detect(&tar), Some(ArchiveKind::Tar));
+
+        let bytes = gzip(&tar);
+        assert_eq!(detect(&bytes), Some(ArchiveKind::Gzip));
+
+        let entries = names_and_data(extract(&bytes, ArchiveKind::Gzip, 4096).unwrap());
+        assert_eq!(
+            entries,
+            vec![("daily/a.txt".to_string(), "ss://a".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_extract_limits_entry_size() {
+        let bytes = zip_archive(&[("big.txt", &"a".repeat(2048)), ("small.txt", "ss://a")]);
+
+        let entries = extract(&bytes, ArchiveKind::Zip, 1024).unwrap();
+        assert!(entries[0].data.is_err());
+        assert!(entries[1].data.is_ok());
+
+        assert_eq!(detect(b"ss://a"), None);
+    }
+}
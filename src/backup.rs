@@ -0,0 +1,504 @@
+use crate::convert::{encode_query, server_to_url};
+use crate::parser::{ServerConfig, format_host};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde_json::{Value, json};
+use urlencoding::encode;
+
+/// True for a v2rayNG backup: the legacy `angconfig` (`{"vmess": [...]}`)
+/// or an array or id-keyed map of newer profile items.
+pub fn is_v2rayng(json: &Value) -> bool {
+    v2rayng_items(json).next().is_some()
+}
+
+/// True for NekoBox profiles: `{"type": ..., "bean": {...}}`, an array of
+/// them, or `{"profiles": [...]}`.
+pub fn is_nekobox(json: &Value) -> bool {
+    nekobox_items(json).next().is_some()
+}
+
+/// Converts the servers of a v2rayNG backup to share URLs. Custom (raw
+/// JSON) configs are skipped.
+pub fn v2rayng_urls(json: &Value) -> Vec<String> {
+    v2rayng_items(json)
+        .filter_map(|item| {
+            let profile = if item["configType"].is_number() {
+                Profile::from_angconfig(item)
+            } else {
+                Profile::from_v2rayng(item)
+            };
+            profile.to_url()
+        })
+        .collect()
+}
+
+/// Converts NekoBox profiles to share URLs.
+pub fn nekobox_urls(json: &Value) -> Vec<String> {
+    nekobox_items(json)
+        .filter_map(|item| Profile::from_nekobox(item).to_url())
+        .collect()
+}
+
+fn v2rayng_items(json: &Value) -> impl Iterator<Item = &Value> {
+    let items: Vec<&Value> = match json {
+        Value::Object(fields) if fields.contains_key("vmess") => {
+            json["vmess"].as_array().into_iter().flatten().collect()
+        }
+        Value::Object(fields) => fields.values().collect(),
+        Value::Array(items) => items.iter().collect(),
+        _ => Vec::new(),
+    };
+    items
+        .into_iter()
+        .filter(|item| item.get("configType").is_some())
+}
+
+fn nekobox_items(json: &Value) -> impl Iterator<Item = &Value> {
+    let items: Vec<&Value> = match json {
+        Value::Object(fields) if fields.contains_key("bean") => vec![json],
+        Value::Object(fields) => fields
+            .get("profiles")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .collect(),
+        Value::Array(items) => items.iter().collect(),
+        _ => Vec::new(),
+    };
+    items
+        .into_iter()
+        .filter(|item| item["bean"].is_object() && item["type"].is_string())
+}
+
+/// First non-empty value of `keys` as text; numbers are written out.
+fn text(value: &Value, keys: &[&str]) -> String {
+    keys.iter()
+        .find_map(|key| match &value[key] {
+            Value::String(s) if !s.is_empty() => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn flag(value: &Value, keys: &[&str]) -> bool {
+    keys.iter().any(|key| match &value[key] {
+        Value::Bool(b) => *b,
+        Value::String(s) => s == "true" || s == "1",
+        Value::Number(n) => n.as_u64() == Some(1),
+        _ => false,
+    })
+}
+
+/// One server as the client apps store it, with field names unified.
+#[derive(Debug, Default)]
+struct Profile {
+    /// Upper-case protocol, e.g. `VLESS`
+    kind: String,
+    remarks: String,
+    server: String,
+    port: String,
+    /// Password, or the UUID for vmess/vless
+    password: String,
+    /// Shadowsocks method, vmess security or vless encryption
+    method: String,
+    alter_id: String,
+    username: String,
+    flow: String,
+    network: String,
+    header_type: String,
+    host: String,
+    path: String,
+    service_name: String,
+    security: String,
+    sni: String,
+    alpn: String,
+    fingerprint: String,
+    insecure: bool,
+    public_key: String,
+    short_id: String,
+    spider_x: String,
+    obfs_password: String,
+    secret_key: String,
+    pre_shared_key: String,
+    local_address: String,
+    reserved: String,
+    mtu: String,
+}
+
+impl Profile {
+    /// Legacy v2rayNG `angconfig` entry, with a numeric `configType`.
+    fn from_angconfig(item: &Value) -> Self {
+        let kind = match item["configType"].as_u64() {
+            Some(1) => "VMESS",
+            Some(3) => "SHADOWSOCKS",
+            Some(4) => "SOCKS",
+            Some(5) => "VLESS",
+            Some(6) => "TROJAN",
+            _ => "CUSTOM",
+        };
+        let network = text(item, &["network"]);
+        let path = text(item, &["path"]);
+        Self {
+            kind: kind.to_string(),
+            remarks: text(item, &["remarks"]),
+            server: text(item, &["address"]),
+            port: text(item, &["port"]),
+            password: text(item, &["id"]),
+            method: text(item, &["security"]),
+            alter_id: text(item, &["alterId"]),
+            flow: text(item, &["flow"]),
+            header_type: text(item, &["headerType"]),
+            host: text(item, &["requestHost"]),
+            // gRPC service names were kept in `path`
+            service_name: if network == "grpc" {
+                path.clone()
+            } else {
+                String::new()
+            },
+            path,
+            network,
+            security: text(item, &["streamSecurity"]),
+            sni: text(item, &["sni"]),
+            fingerprint: text(item, &["fingerprint"]),
+            insecure: flag(item, &["allowInsecure"]),
+            public_key: text(item, &["publicKey"]),
+            short_id: text(item, &["shortId"]),
+            spider_x: text(item, &["spiderX"]),
+            ..Default::default()
+        }
+    }
+
+    /// Newer v2rayNG profile item, with `configType` names.
+    fn from_v2rayng(item: &Value) -> Self {
+        Self {
+            kind: text(item, &["configType"]).to_uppercase(),
+            remarks: text(item, &["remarks"]),
+            server: text(item, &["server"]),
+            port: text(item, &["serverPort"]),
+            password: text(item, &["password"]),
+            method: text(item, &["method"]),
+            username: text(item, &["username"]),
+            flow: text(item, &["flow"]),
+            network: text(item, &["network"]),
+            header_type: text(item, &["headerType"]),
+            host: text(item, &["host"]),
+            path: text(item, &["path"]),
+            service_name: text(item, &["serviceName"]),
+            security: text(item, &["security"]),
+            sni: text(item, &["sni"]),
+            alpn: text(item, &["alpn"]),
+            fingerprint: text(item, &["fingerPrint"]),
+            insecure: flag(item, &["insecure"]),
+            public_key: text(item, &["publicKey"]),
+            short_id: text(item, &["shortId"]),
+            spider_x: text(item, &["spiderX"]),
+            obfs_password: text(item, &["obfsPassword"]),
+            secret_key: text(item, &["secretKey"]),
+            pre_shared_key: text(item, &["preSharedKey"]),
+            local_address: text(item, &["localAddress"]),
+            reserved: text(item, &["reserved"]),
+            mtu: text(item, &["mtu"]),
+            ..Default::default()
+        }
+    }
+
+    /// NekoBox profile: a `type` and a `bean` with the server settings.
+    fn from_nekobox(item: &Value) -> Self {
+        let bean = &item["bean"];
+        let stream = &bean["stream"];
+        let network = text(stream, &["net"]);
+        let path = text(stream, &["path"]);
+        Self {
+            kind: text(item, &["type"]).to_uppercase(),
+            remarks: text(bean, &["name"]),
+            server: text(bean, &["addr"]),
+            port: text(bean, &["port"]),
+            password: text(bean, &["id", "pass", "password"]),
+            method: text(bean, &["method", "sec", "encryption"]),
+            alter_id: text(bean, &["aid"]),
+            username: text(bean, &["username"]),
+            flow: text(bean, &["flow"]),
+            header_type: text(stream, &["h_type"]),
+            host: text(stream, &["host"]),
+            service_name: if network == "grpc" {
+                path.clone()
+            } else {
+                String::new()
+            },
+            path,
+            network,
+            security: text(stream, &["sec"]),
+            sni: Some(text(stream, &["sni"]))
+                .filter(|sni| !sni.is_empty())
+                .unwrap_or_else(|| text(bean, &["sni"])),
+            alpn: text(stream, &["alpn"]),
+            fingerprint: text(stream, &["utls"]),
+            insecure: flag(stream, &["insecure"]) || flag(bean, &["allowInsecure"]),
+            public_key: text(stream, &["pbk"]),
+            short_id: text(stream, &["sid"]),
+            spider_x: text(stream, &["spx"]),
+            obfs_password: text(bean, &["obfsPassword"]),
+            ..Default::default()
+        }
+    }
+
+    fn to_url(&self) -> Option<String> {
+        if self.server.is_empty() {
+            return None;
+        }
+        let port: u16 = self.port.parse().ok()?;
+        let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+
+        let url = match self.kind.as_str() {
+            "VMESS" => {
+                let mut config = json!({
+                    "v": "2",
+                    "ps": self.remarks,
+                    "add": self.server,
+                    "port": self.port,
+                    "id": self.password,
+                    "aid": optional(&self.alter_id).unwrap_or_else(|| "0".to_string()),
+                    "scy": optional(&self.method).unwrap_or_else(|| "auto".to_string()),
+                    "net": optional(&self.network).unwrap_or_else(|| "tcp".to_string()),
+                    "type": self.header_type,
+                    "host": self.host,
+                    "path": if self.network == "grpc" { &self.service_name } else { &self.path },
+                    "tls": self.security,
+                    "sni": self.sni,
+                    "alpn": self.alpn,
+                    "fp": self.fingerprint,
+                });
+                if self.insecure {
+                    config["insecure"] = json!("1");
+                }
+                format!("vmess://{}", BASE64_STANDARD.encode(config.to_string()))
+            }
+            "VLESS" => format!(
+                "vless://{}@{}:{}?{}#{}",
+                self.password,
+                format_host(&self.server),
+                port,
+                encode_query(&self.stream_params(Some(
+                    optional(&self.method).unwrap_or_else(|| "none".to_string())
+                ))),
+                encode(&self.remarks)
+            ),
+            "TROJAN" => format!(
+                "trojan://{}@{}:{}?{}#{}",
+                encode(&self.password),
+                format_host(&self.server),
+                port,
+                encode_query(&self.stream_params(None)),
+                encode(&self.remarks)
+            ),
+            "HYSTERIA2" => {
+                let mut params = vec![("sni", self.sni.clone())];
+                if !self.obfs_password.is_empty() {
+                    params.push(("obfs", "salamander".to_string()));
+                    params.push(("obfs-password", self.obfs_password.clone()));
+                }
+                params.push((
+                    "insecure",
+                    if self.insecure { "1" } else { "0" }.to_string(),
+                ));
+                params.retain(|(_, value)| !value.is_empty());
+                format!(
+                    "hysteria2://{}@{}:{}?{}#{}",
+                    encode(&self.password),
+                    format_host(&self.server),
+                    port,
+                    encode_query(&params),
+                    encode(&self.remarks)
+                )
+            }
+            "WIREGUARD" => {
+                let mut params = vec![
+                    ("publickey", self.public_key.clone()),
+                    ("presharedkey", self.pre_shared_key.clone()),
+                    ("address", self.local_address.clone()),
+                    ("reserved", self.reserved.clone()),
+                    ("mtu", self.mtu.clone()),
+                ];
+                params.retain(|(_, value)| !value.is_empty());
+                format!(
+                    "wireguard://{}@{}:{}?{}#{}",
+                    encode(&self.secret_key),
+                    format_host(&self.server),
+                    port,
+                    encode_query(&params),
+                    encode(&self.remarks)
+                )
+            }
+            "SHADOWSOCKS" => server_to_url(&ServerConfig::Shadowsocks {
+                tag: self.remarks.clone(),
+                address: self.server.clone(),
+                port,
+                method: self.method.clone(),
+                password: self.password.clone(),
+                udp_over_tcp: false,
+                plugin: None,
+            })?,
+            "SOCKS" => server_to_url(&ServerConfig::Socks {
+                tag: self.remarks.clone(),
+                address: self.server.clone(),
+                port,
+                username: optional(&self.username),
+                password: optional(&self.password),
+            })?,
+            "HTTP" => server_to_url(&ServerConfig::Http {
+                tag: self.remarks.clone(),
+                address: self.server.clone(),
+                port,
+                username: optional(&self.username),
+                password: optional(&self.password),
+            })?,
+            "NAIVE" => server_to_url(&ServerConfig::Naive {
+                tag: self.remarks.clone(),
+                address: self.server.clone(),
+                port,
+                username: optional(&self.username),
+                password: optional(&self.password),
+                padding: false,
+            })?,
+            kind => {
+                log::debug!("Skipping {} profile {}", kind, self.remarks);
+                return None;
+            }
+        };
+        Some(url)
+    }
+
+    /// Transport and TLS query parameters of vless/trojan links.
+    fn stream_params(&self, encryption: Option<String>) -> Vec<(&'static str, String)> {
+        let network = if self.network.is_empty() {
+            "tcp"
+        } else {
+            &self.network
+        };
+        let security = if self.security.is_empty() {
+            "none"
+        } else {
+            &self.security
+        };
+
+        let mut params = vec![
+            ("encryption", encryption.unwrap_or_default()),
+            ("type", network.to_string()),
+            ("security", security.to_string()),
+            ("flow", self.flow.clone()),
+            ("sni", self.sni.clone()),
+            ("fp", self.fingerprint.clone()),
+            ("alpn", self.alpn.clone()),
+            ("pbk", self.public_key.clone()),
+            ("sid", self.short_id.clone()),
+            ("spx", self.spider_x.clone()),
+            ("host", self.host.clone()),
+            ("path", self.path.clone()),
+            ("serviceName", self.service_name.clone()),
+            ("headerType", self.header_type.clone()),
+        ];
+        if security != "none" {
+            params.push((
+                "allowInsecure",
+                if self.insecure { "1" } else { "0" }.to_string(),
+            ));
+        }
+        params.retain(|(_, value)| !value.is_empty());
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_servers;
+
+    #[test]
+    fn test_v2rayng_urls() {
+        let angconfig = json!({
+            "index": 0,
+            "vmess": [
+                {
+                    "configType": 1, "remarks": "vmess-ws", "address": "example.com",
+                    "port": 443, "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "alterId": 0,
+                    "security": "auto", "network": "ws", "requestHost": "cdn.example.com",
+                    "path": "/ws", "streamSecurity": "tls", "sni": "example.com"
+                },
+                { "configType": 3, "remarks": "ss", "address": "1.2.3.4", "port": 8388,
+                  "id": "secret", "security": "aes-256-gcm" },
+                { "configType": 2, "remarks": "custom", "address": "", "port": 0 }
+            ]
+        });
+        assert!(is_v2rayng(&angconfig));
+        let servers = parse_servers(&v2rayng_urls(&angconfig).join("\n")).unwrap();
+        assert_eq!(servers.len(), 2);
+        assert!(matches!(
+            &servers[0],
+            ServerConfig::Vmess { tag, address, port: 443, network, .. }
+                if tag == "vmess-ws" && address == "example.com" && network == "ws"
+        ));
+        assert!(matches!(
+            &servers[1],
+            ServerConfig::Shadowsocks { method, password, .. }
+                if method == "aes-256-gcm" && password == "secret"
+        ));
+
+        let items = json!({
+            "5f0e": {
+                "configType": "VLESS", "remarks": "reality", "server": "1.2.3.4",
+                "serverPort": "443", "password": "uuid", "flow": "xtls-rprx-vision",
+                "network": "tcp", "security": "reality", "sni": "www.example.com",
+                "fingerPrint": "chrome", "publicKey": "pbk", "shortId": "ab"
+            }
+        });
+        assert!(is_v2rayng(&items));
+        let servers = parse_servers(&v2rayng_urls(&items).join("\n")).unwrap();
+        match &servers[0] {
+            ServerConfig::Vless {
+                flow,
+                security,
+                tls_settings,
+                ..
+            } => {
+                assert_eq!(flow, "xtls-rprx-vision");
+                assert_eq!(security, "reality");
+                let tls = tls_settings.as_ref().as_ref().unwrap();
+                assert_eq!(tls.public_key.as_deref(), Some("pbk"));
+                assert!(!tls.allow_insecure);
+            }
+            other => panic!("Expected vless server, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nekobox_urls() {
+        let profiles = json!([
+            {
+                "type": "trojan", "id": 1, "gid": 0,
+                "bean": {
+                    "name": "trojan-grpc", "addr": "example.com", "port": 443, "pass": "secret",
+                    "stream": { "net": "grpc", "path": "svc", "sec": "tls", "sni": "example.com" }
+                }
+            },
+            {
+                "type": "socks", "id": 2, "gid": 0,
+                "bean": { "name": "socks", "addr": "1.2.3.4", "port": 1080 }
+            },
+            { "type": "tuic", "bean": { "addr": "1.2.3.4", "port": 443 } }
+        ]);
+        assert!(is_nekobox(&profiles));
+        assert!(!is_v2rayng(&profiles));
+
+        let servers = parse_servers(&nekobox_urls(&profiles).join("\n")).unwrap();
+        assert_eq!(servers.len(), 2);
+        assert!(matches!(
+            &servers[0],
+            ServerConfig::Trojan { password, network, .. } if password == "secret" && network == "grpc"
+        ));
+        assert!(matches!(
+            &servers[1],
+            ServerConfig::Socks { username: None, .. }
+        ));
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Local end of outgoing sockets. On multi-WAN routers this keeps fetches
+/// and probes on the intended uplink instead of the default route, which
+/// may well go through the proxy being generated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bind {
+    /// Source address, as reqwest's `local_address`
+    Address(IpAddr),
+    /// Interface name, bound with `SO_BINDTODEVICE` (Linux only)
+    Interface(String),
+}
+
+impl FromStr for Bind {
+    type Err = anyhow::Error;
+
+    /// An IP address, or anything else as an interface name.
+    fn from_str(value: &str) -> Result<Self> {
+        anyhow::ensure!(!value.is_empty(), "Empty address or interface");
+        Ok(match value.parse() {
+            Ok(ip) => Bind::Address(ip),
+            Err(_) => Bind::Interface(value.to_string()),
+        })
+    }
+}
+
+impl Bind {
+    /// Binds every connection of a reqwest client.
+    pub fn apply(
+        &self,
+        builder: reqwest::blocking::ClientBuilder,
+    ) -> Result<reqwest::blocking::ClientBuilder> {
+        match self {
+            Bind::Address(ip) => Ok(builder.local_address(*ip)),
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            Bind::Interface(name) => Ok(builder.interface(name)),
+            #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+            Bind::Interface(name) => {
+                anyhow::bail!("Binding to interface {} needs Linux", name)
+            }
+        }
+    }
+
+    /// Opens a TCP connection to `addr` from the bound address or interface.
+    pub fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> Result<TcpStream> {
+        let socket = Socket::new(
+            Domain::for_address(*addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        match self {
+            Bind::Address(ip) => socket
+                .bind(&SocketAddr::new(*ip, 0).into())
+                .with_context(|| format!("Failed to bind to {}", ip))?,
+            #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+            Bind::Interface(name) => socket
+                .bind_device(Some(name.as_bytes()))
+                .with_context(|| format!("Failed to bind to interface {}", name))?,
+            #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+            Bind::Interface(name) => anyhow::bail!("Binding to interface {} needs Linux", name),
+        }
+        socket.connect_timeout(&(*addr).into(), timeout)?;
+        Ok(socket.into())
+    }
+}
+
+/// [`TcpStream::connect_timeout`], from `bind` when there is one.
+pub fn connect_timeout(
+    addr: &SocketAddr,
+    timeout: Duration,
+    bind: Option<&Bind>,
+) -> Result<TcpStream> {
+    match bind {
+        Some(bind) => bind.connect_timeout(addr, timeout),
+        None => Ok(TcpStream::connect_timeout(addr, timeout)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_bind() {
+        assert_eq!(
+            "192.0.2.1".parse::<Bind>().unwrap(),
+            Bind::Address("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            "2001:db8::1".parse::<Bind>().unwrap(),
+            Bind::Address("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(
+            "wan2".parse::<Bind>().unwrap(),
+            Bind::Interface("wan2".to_string())
+        );
+        assert!("".parse::<Bind>().is_err());
+    }
+
+    #[test]
+    fn test_connect_from_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bind = Bind::Address("127.0.0.1".parse().unwrap());
+
+        let stream = connect_timeout(&addr, Duration::from_secs(1), Some(&bind)).unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), addr.ip());
+
+        // An address of the wrong family can't reach the target
+        let bind = Bind::Address("::1".parse().unwrap());
+        assert!(connect_timeout(&addr, Duration::from_secs(1), Some(&bind)).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use super::{WriteOptions, write_config_file};
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where an outbound came from, shown in `--annotate` comments.
+#[derive(Debug, Clone, Default)]
+pub struct Origin {
+    /// Source the share URL was read from
+    pub source: Option<String>,
+    /// Measured connect time of the address the outbound uses
+    pub latency: Option<Duration>,
+    /// ISO country code from the flag emoji in the server's remark
+    pub country: Option<String>,
+    /// The run probed the address; `latency` is `None` if nothing answered
+    pub checked: bool,
+}
+
+/// Path in `dir` of the annotated variant of a generated file, e.g.
+/// `annotated/04_outbounds.jsonc`. Xray's confdir loader reads `.jsonc`
+/// files too, so `dir` must not be the confdir itself.
+pub fn jsonc_path(dir: &Path, path: &Path) -> PathBuf {
+    dir.join(path.file_name().unwrap_or_default())
+        .with_extension("jsonc")
+}
+
+/// Renders an outbounds document as JSONC, with a comment above every
+/// outbound naming its protocol, source, balancer and measured latency.
+pub fn annotate_outbounds(
+    outbounds: &Value,
+    routing: &Value,
+    origins: &HashMap<String, Origin>,
+) -> String {
+    let balancers = balancers_by_member(routing);
+
+    to_jsonc(outbounds, &|value| {
+        let tag = value.get("tag")?.as_str()?;
+        let protocol = value.get("protocol")?.as_str()?;
+
+        match protocol {
+            "freedom" => return Some(format!("{}: sends traffic out without a proxy", tag)),
+            "blackhole" => return Some(format!("{}: drops traffic", tag)),
+            _ => {}
+        }
+
+        let mut comment = format!("{}: {}", tag, protocol);
+        let origin = origins.get(tag);
+        if let Some(source) = origin.and_then(|o| o.source.as_deref()) {
+            comment.push_str(&format!(" from {}", source));
+        }
+        match balancers.get(tag) {
+            Some(balancer) => comment.push_str(&format!(", balanced in {}", balancer)),
+            None => comment.push_str(", not in any balancer"),
+        }
+        match origin {
+            Some(Origin {
+                latency: Some(latency),
+                ..
+            }) => comment.push_str(&format!(", {} ms to connect", latency.as_millis())),
+            Some(Origin { checked: true, .. }) => comment.push_str(", no answer to the probe"),
+            _ => {}
+        }
+        Some(comment)
+    })
+}
+
+/// Renders a routing document as JSONC, with a comment above every rule and
+/// balancer explaining what it does.
+pub fn annotate_routing(routing: &Value) -> String {
+    to_jsonc(routing, &|value| {
+        if let Some(selector) = value.get("selector").and_then(Value::as_array) {
+            let strategy = value["strategy"]["type"].as_str().unwrap_or("random");
+            return Some(format!(
+                "{}: picks one of {} outbounds by {}",
+                value["tag"].as_str().unwrap_or_default(),
+                selector.len(),
+                strategy
+            ));
+        }
+
+        let target = match (value.get("outboundTag"), value.get("balancerTag")) {
+            (Some(tag), _) => format!("outbound {}", tag.as_str().unwrap_or_default()),
+            (None, Some(tag)) => format!("balancer {}", tag.as_str().unwrap_or_default()),
+            (None, None) => return None,
+        };
+        Some(format!("{} -> {}", describe_matchers(value), target))
+    })
+}
+
+/// Writes an annotated document to `path`, see [`jsonc_path`].
+pub fn write_annotated(path: &Path, text: &str, options: &WriteOptions) -> Result<()> {
+    write_config_file(path, options, |file| Ok(file.write_all(text.as_bytes())?))
+}
+
+/// Maps every balanced outbound tag to its balancer tag.
+fn balancers_by_member(routing: &Value) -> HashMap<&str, &str> {
+    let mut members = HashMap::new();
+    for balancer in routing["routing"]["balancers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let Some(tag) = balancer["tag"].as_str() else {
+            continue;
+        };
+        for member in balancer["selector"].as_array().into_iter().flatten() {
+            if let Some(member) = member.as_str() {
+                members.entry(member).or_insert(tag);
+            }
+        }
+    }
+    members
+}
+
+/// Summarizes the conditions of a routing rule, e.g. `network udp; port 53`.
+fn describe_matchers(rule: &Value) -> String {
+    let Some(fields) = rule.as_object() else {
+        return String::new();
+    };
+
+    let matchers: Vec<String> = fields
+        .iter()
+        .filter(|(key, _)| {
+            !matches!(
+                key.as_str(),
+                "type" | "inboundTag" | "outboundTag" | "balancerTag"
+            )
+        })
+        .map(|(key, value)| match value {
+            Value::Array(items) if items.len() > 3 => format!("{} ({} entries)", key, items.len()),
+            Value::Array(items) if items.is_empty() => format!("{} (none)", key),
+            Value::Array(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| {
+                        item.as_str()
+                            .map_or_else(|| item.to_string(), str::to_string)
+                    })
+                    .collect();
+                format!("{} {}", key, items.join(", "))
+            }
+            Value::String(value) => format!("{} {}", key, value),
+            other => format!("{} {}", key, other),
+        })
+        .collect();
+
+    if matchers.is_empty() {
+        "all traffic".to_string()
+    } else {
+        matchers.join("; ")
+    }
+}
+
+/// Pretty-prints `value` like `serde_json::to_string_pretty`, putting the
+/// comment returned by `comment` above each array element that has one.
+fn to_jsonc(value: &Value, comment: &dyn Fn(&Value) -> Option<String>) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, 0, comment);
+    out.push('\n');
+    out
+}
+
+fn write_value(
+    out: &mut String,
+    value: &Value,
+    depth: usize,
+    comment: &dyn Fn(&Value) -> Option<String>,
+) {
+    let indent = |out: &mut String, depth: usize| out.push_str(&"  ".repeat(depth));
+
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for (idx, item) in items.iter().enumerate() {
+                if let Some(text) = comment(item) {
+                    indent(out, depth + 1);
+                    out.push_str("// ");
+                    out.push_str(&text.replace(['\n', '\r'], " "));
+                    out.push('\n');
+                }
+                indent(out, depth + 1);
+                write_value(out, item, depth + 1, comment);
+                if idx + 1 < items.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            indent(out, depth);
+            out.push(']');
+        }
+        Value::Object(fields) if !fields.is_empty() => {
+            out.push_str("{\n");
+            for (idx, (key, field)) in fields.iter().enumerate() {
+                indent(out, depth + 1);
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push_str(": ");
+                write_value(out, field, depth + 1, comment);
+                if idx + 1 < fields.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Strips `//` comment lines, leaving plain JSON.
+    fn strip_comments(jsonc: &str) -> String {
+        jsonc
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_to_jsonc_matches_pretty_json() {
+        let value = json!({ "a": [1, { "b": [] }], "c": {}, "d": "x\"y" });
+        let jsonc = to_jsonc(&value, &|_| Some("note".to_string()));
+
+        assert!(jsonc.contains("    // note\n    1,"));
+        assert_eq!(
+            strip_comments(&jsonc),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_annotate_outbounds_and_routing() {
+        let outbounds = json!({ "outbounds": [
+            { "tag": "de-1", "protocol": "trojan" },
+            { "tag": "direct", "protocol": "freedom" }
+        ]});
+        let routing = json!({ "routing": {
+            "rules": [
+                { "type": "field", "inboundTag": ["redirect"], "outboundTag": "direct", "port": "53" },
+                { "type": "field", "balancerTag": "proxy-balance", "domain": [] }
+            ],
+            "balancers": [
+                { "tag": "proxy-balance", "selector": ["de-1"], "strategy": { "type": "leastping" } }
+            ]
+        }});
+        let origins = HashMap::from([(
+            "de-1".to_string(),
+            Origin {
+                source: Some("https://example.com/sub".to_string()),
+                latency: Some(Duration::from_millis(42)),
+                checked: true,
+                ..Default::default()
+            },
+        )]);
+
+        let jsonc = annotate_outbounds(&outbounds, &routing, &origins);
+        assert!(jsonc.contains(
+            "// de-1: trojan from https://example.com/sub, balanced in proxy-balance, 42 ms to connect"
+        ));
+        assert!(jsonc.contains("// direct: sends traffic out without a proxy"));
+
+        let jsonc = annotate_routing(&routing);
+        assert!(jsonc.contains("// port 53 -> outbound direct"));
+        assert!(jsonc.contains("// domain (none) -> balancer proxy-balance"));
+        assert!(jsonc.contains("// proxy-balance: picks one of 1 outbounds by leastping"));
+        let plain: Value = serde_json::from_str(&strip_comments(&jsonc)).unwrap();
+        assert_eq!(plain, routing);
+    }
+}
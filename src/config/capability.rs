@@ -0,0 +1,202 @@
+use super::GeneratorOptions;
+use super::outbound::Hysteria2Mode;
+use crate::parser::ServerConfig;
+use crate::report::SkippedServer;
+use std::collections::BTreeMap;
+
+/// Export targets the generator can write configs for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Xray,
+}
+
+impl Target {
+    pub const ALL: &'static [Target] = &[Target::Xray];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Xray => "xray",
+        }
+    }
+}
+
+/// Returns why a server can't be represented in the given target, or None if it can.
+pub fn unsupported_reason(target: Target, server: &ServerConfig) -> Option<String> {
+    unsupported_reason_with(target, server, &GeneratorOptions::default())
+}
+
+/// Like `unsupported_reason`, for servers generated with `options`, which can
+/// make up for a missing outbound (see [`Hysteria2Mode`]).
+pub fn unsupported_reason_with(
+    target: Target,
+    server: &ServerConfig,
+    options: &GeneratorOptions,
+) -> Option<String> {
+    match target {
+        Target::Xray => match server {
+            ServerConfig::Hysteria2 { tag, .. } => match &options.hysteria2 {
+                Hysteria2Mode::Skip => Some(format!(
+                    "{} protocol has no {} outbound; bridge it with --hysteria2-bridge or \
+                     describe it with --hysteria2-template",
+                    server.protocol(),
+                    target.name()
+                )),
+                Hysteria2Mode::Bridge(ports) if !ports.contains_key(tag) => {
+                    Some(format!("no bridge port assigned to {}", tag))
+                }
+                Hysteria2Mode::Bridge(_) | Hysteria2Mode::Template(_) => None,
+            },
+            ServerConfig::Shadowsocks {
+                plugin: Some(plugin),
+                ..
+            } if plugin.websocket().is_none() => Some(format!(
+                "shadowsocks plugin {} has no {} transport",
+                plugin.to_plugin_string(),
+                target.name()
+            )),
+            ServerConfig::ShadowsocksR { protocol, obfs, .. }
+                if protocol != "origin" || obfs != "plain" =>
+            {
+                Some(format!(
+                    "{} with protocol {} and obfs {} has no {} outbound",
+                    server.protocol(),
+                    protocol,
+                    obfs,
+                    target.name()
+                ))
+            }
+            ServerConfig::Naive { .. }
+            | ServerConfig::Hysteria { .. }
+            | ServerConfig::Brook { .. }
+            | ServerConfig::Mieru { .. }
+            | ServerConfig::Tuic { .. } => Some(format!(
+                "{} protocol has no {} outbound",
+                server.protocol(),
+                target.name()
+            )),
+            _ => None,
+        },
+    }
+}
+
+pub fn is_supported(target: Target, server: &ServerConfig) -> bool {
+    unsupported_reason(target, server).is_none()
+}
+
+pub fn is_supported_with(
+    target: Target,
+    server: &ServerConfig,
+    options: &GeneratorOptions,
+) -> bool {
+    unsupported_reason_with(target, server, options).is_none()
+}
+
+/// Collects a skip entry for every server the target can't represent.
+pub fn skipped_servers(target: Target, servers: &[ServerConfig]) -> Vec<SkippedServer> {
+    skipped_servers_with(target, servers, &GeneratorOptions::default())
+}
+
+pub fn skipped_servers_with(
+    target: Target,
+    servers: &[ServerConfig],
+    options: &GeneratorOptions,
+) -> Vec<SkippedServer> {
+    servers
+        .iter()
+        .filter_map(|server| {
+            unsupported_reason_with(target, server, options).map(|reason| SkippedServer {
+                tag: server.tag().to_string(),
+                protocol: server.protocol().to_string(),
+                target: target.name().to_string(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Renders a protocol x target table with the number of servers each target
+/// can export, e.g. `brook  2  0/2`.
+pub fn capability_matrix(servers: &[ServerConfig]) -> Vec<String> {
+    capability_matrix_with(servers, &GeneratorOptions::default())
+}
+
+pub fn capability_matrix_with(servers: &[ServerConfig], options: &GeneratorOptions) -> Vec<String> {
+    let mut by_protocol: BTreeMap<&str, Vec<&ServerConfig>> = BTreeMap::new();
+    for server in servers {
+        by_protocol
+            .entry(server.protocol())
+            .or_default()
+            .push(server);
+    }
+
+    let mut header = format!("{:<12} {:>8}", "protocol", "servers");
+    for target in Target::ALL {
+        header.push_str(&format!(" {:>8}", target.name()));
+    }
+
+    let mut lines = vec![header];
+    for (protocol, group) in by_protocol {
+        let mut line = format!("{:<12} {:>8}", protocol, group.len());
+        for target in Target::ALL {
+            let supported = group
+                .iter()
+                .filter(|s| is_supported_with(*target, s, options))
+                .count();
+            line.push_str(&format!(" {:>8}", format!("{}/{}", supported, group.len())));
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brook_server() -> ServerConfig {
+        ServerConfig::Brook {
+            tag: "brook-1".to_string(),
+            address: "example.com".to_string(),
+            port: 443,
+            password: "test".to_string(),
+            kind: "wssserver".to_string(),
+            path: "/ws".to_string(),
+        }
+    }
+
+    fn ss_server() -> ServerConfig {
+        ServerConfig::Shadowsocks {
+            tag: "ss-1".to_string(),
+            address: "1.2.3.4".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "test".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }
+    }
+
+    #[test]
+    fn test_skipped_servers_xray() {
+        let servers = vec![ss_server(), brook_server()];
+
+        let skipped = skipped_servers(Target::Xray, &servers);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].tag, "brook-1");
+        assert_eq!(skipped[0].protocol, "brook");
+        assert_eq!(skipped[0].target, "xray");
+        assert!(skipped[0].reason.contains("brook"));
+    }
+
+    #[test]
+    fn test_capability_matrix() {
+        let servers = vec![ss_server(), brook_server(), brook_server()];
+
+        let matrix = capability_matrix(&servers);
+        assert_eq!(matrix.len(), 3);
+        assert!(matrix[0].contains("xray"));
+        assert!(matrix[1].starts_with("brook") && matrix[1].ends_with("0/2"));
+        assert!(matrix[2].starts_with("shadowsocks") && matrix[2].ends_with("1/1"));
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+/// File Xray reads for plain `geosite:` references.
+const DEFAULT_GEOSITE_FILE: &str = "geosite.dat";
+
+/// Collects the geosite categories referenced by routing rules, grouped by
+/// the dat file they are looked up in.
+///
+/// Handles both `geosite:category` and `ext:file.dat:category` forms; an
+/// attribute suffix such as `@ads` is ignored.
+pub fn referenced_categories(routing: &Value) -> BTreeMap<String, BTreeSet<String>> {
+    let mut refs: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    let rules = routing["routing"]["rules"].as_array().into_iter().flatten();
+    for domain in rules.flat_map(|rule| rule["domain"].as_array().into_iter().flatten()) {
+        let Some(domain) = domain.as_str() else {
+            continue;
+        };
+
+        let (file, category) = if let Some(category) = domain.strip_prefix("geosite:") {
+            (DEFAULT_GEOSITE_FILE, category)
+        } else if let Some(rest) = domain.strip_prefix("ext:") {
+            match rest.split_once(':') {
+                Some((file, category)) => (file, category),
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+
+        let category = category.split('@').next().unwrap_or_default();
+        refs.entry(file.to_string())
+            .or_default()
+            .insert(category.to_lowercase());
+    }
+
+    refs
+}
+
+/// Checks that every geosite category referenced by `routing` exists in the
+/// dat files found in `asset_dir`, so a bad category fails here rather than
+/// when Xray starts.
+pub fn validate(routing: &Value, asset_dir: &Path) -> Result<()> {
+    let mut missing = Vec::new();
+
+    for (file, categories) in referenced_categories(routing) {
+        let path = asset_dir.join(&file);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("Failed to read geosite file {}", path.display()))?;
+        let available = parse_categories(&data)
+            .with_context(|| format!("Invalid geosite file {}", path.display()))?;
+
+        missing.extend(
+            categories
+                .into_iter()
+                .filter(|category| !available.contains(category))
+                .map(|category| format!("{}:{}", file, category)),
+        );
+    }
+
+    if !missing.is_empty() {
+        anyhow::bail!("Unknown geosite categories: {}", missing.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Reads the category names (lowercased) from a geosite dat file.
+///
+/// The file is a protobuf `GeoSiteList`: repeated field 1 holds `GeoSite`
+/// messages whose field 1 is the category name. Only that much is decoded.
+pub fn parse_categories(data: &[u8]) -> Result<HashSet<String>> {
+    let mut categories = HashSet::new();
+
+    for (field, value) in ProtoFields::new(data) {
+        let value = value?;
+        if field != 1 {
+            continue;
+        }
+        let Some(site) = value else {
+            continue;
+        };
+
+        for (site_field, site_value) in ProtoFields::new(site) {
+            if let (1, Some(name)) = (site_field, site_value?) {
+                categories.insert(String::from_utf8_lossy(name).to_lowercase());
+                break;
+            }
+        }
+    }
+
+    Ok(categories)
+}
+
+/// Iterates over the top-level fields of a protobuf message, yielding the
+/// payload of length-delimited fields and None for the other wire types.
+struct ProtoFields<'a> {
+    data: &'a [u8],
+    failed: bool,
+}
+
+impl<'a> ProtoFields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            failed: false,
+        }
+    }
+
+    fn next_field(&mut self) -> Result<(u64, Option<&'a [u8]>)> {
+        let key = read_varint(&mut self.data)?;
+        let field = key >> 3;
+
+        let value = match key & 0x7 {
+            0 => {
+                read_varint(&mut self.data)?;
+                None
+            }
+            1 => {
+                self.advance(8)?;
+                None
+            }
+            2 => {
+                let len = read_varint(&mut self.data)? as usize;
+                Some(self.advance(len)?)
+            }
+            5 => {
+                self.advance(4)?;
+                None
+            }
+            wire_type => anyhow::bail!("Unsupported protobuf wire type {}", wire_type),
+        };
+
+        Ok((field, value))
+    }
+
+    fn advance(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            anyhow::bail!("Truncated protobuf message");
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+}
+
+impl<'a> Iterator for ProtoFields<'a> {
+    type Item = (u64, Result<Option<&'a [u8]>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.data.is_empty() {
+            return None;
+        }
+
+        match self.next_field() {
+            Ok((field, value)) => Some((field, Ok(value))),
+            Err(e) => {
+                self.failed = true;
+                Some((0, Err(e)))
+            }
+        }
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().context("Truncated protobuf varint")?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    anyhow::bail!("Protobuf varint too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn length_delimited(field: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![(field << 3) | 2, payload.len() as u8];
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn geosite_dat(categories: &[&str]) -> Vec<u8> {
+        categories
+            .iter()
+            .flat_map(|name| {
+                // GeoSite { country_code = 1, domain = 2 }
+                let mut site = length_delimited(1, name.as_bytes());
+                site.extend(length_delimited(2, b"\x08\x02\x12\x07example"));
+                length_delimited(1, &site)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_categories() {
+        let data = geosite_dat(&["CATEGORY-ADS-ALL", "GOOGLE"]);
+
+        let categories = parse_categories(&data).unwrap();
+        assert_eq!(categories.len(), 2);
+        assert!(categories.contains("category-ads-all"));
+        assert!(categories.contains("google"));
+
+        assert!(parse_categories(&data[..data.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_validate_reports_missing_categories() {
+        let dir =
+            std::env::temp_dir().join(format!("proxy-harvest-geosite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("geosite_v2fly.dat"),
+            geosite_dat(&["CATEGORY-ADS-ALL"]),
+        )
+        .unwrap();
+
+        let routing = json!({
+            "routing": {
+                "rules": [
+                    { "domain": ["ext:geosite_v2fly.dat:category-ads-all@ads", "domain:example.com"] }
+                ]
+            }
+        });
+        assert!(validate(&routing, &dir).is_ok());
+
+        let routing = json!({
+            "routing": {
+                "rules": [
+                    { "domain": ["ext:geosite_v2fly.dat:category-nope"] }
+                ]
+            }
+        });
+        let err = validate(&routing, &dir).unwrap_err().to_string();
+        assert!(err.contains("geosite_v2fly.dat:category-nope"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+use super::annotate::Origin;
+use super::capability::{self, Target};
+use super::routing::Category;
+use super::{GeneratorOptions, WriteOptions, write_config_with};
+use crate::cache::fnv1a;
+use crate::parser::ServerConfig;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// What dashboards and scripts need to know about one outbound, keyed by
+/// its tag in the index (the key Xray stats use).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub protocol: String,
+    /// `tcp`, `ws`, `grpc`, ... see [`ServerConfig::transport`]
+    pub transport: String,
+    pub address: String,
+    /// ISO code from the flag emoji in the server's remark, if it had one
+    pub country: Option<String>,
+    /// `cloudflare`, `warp` or `proxy`
+    pub category: String,
+    pub balancer: String,
+    pub source: Option<String>,
+    /// Measured connect time, when the run probed the address
+    pub latency_ms: Option<u64>,
+    /// Hash of protocol, address, port and credential, see [`identity`].
+    /// Health only carries over to a server with the same identity.
+    #[serde(default)]
+    pub identity: Option<String>,
+    #[serde(flatten)]
+    pub health: Health,
+}
+
+/// Probe history of one outbound, carried over between runs through the
+/// previous `index.json` so watchdogs can tell a flaky server from a dead one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Health {
+    /// Unix time of the last run that probed the address
+    pub last_checked: Option<u64>,
+    /// Probes in a row that got no answer; reset by the first one that does
+    pub consecutive_failures: u32,
+}
+
+impl Health {
+    /// Folds this run's probe (if any) into the previous history.
+    fn update(previous: Option<&Health>, origin: Option<&Origin>, now: u64) -> Health {
+        let previous = previous.cloned().unwrap_or_default();
+        match origin {
+            Some(origin) if origin.checked => Health {
+                last_checked: Some(now),
+                consecutive_failures: if origin.latency.is_some() {
+                    0
+                } else {
+                    previous.consecutive_failures + 1
+                },
+            },
+            _ => previous,
+        }
+    }
+}
+
+/// Identifies the server behind an index entry independently of its tag,
+/// which a reordered subscription can hand to a different server. Hashed so
+/// the index doesn't spell out credentials.
+pub fn identity(server: &ServerConfig) -> String {
+    let key = format!(
+        "{}\0{}\0{}\0{}",
+        server.protocol(),
+        server.address(),
+        server.port(),
+        server.credential()
+    );
+    format!("{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// Builds the tag index of every server that gets an Xray outbound.
+/// `origins` is keyed by the final outbound tag; health is carried over from
+/// the `previous` index entry with the same [`identity`], whatever its tag,
+/// for servers this run didn't probe.
+pub fn generate_index(
+    servers: &[ServerConfig],
+    options: &GeneratorOptions,
+    origins: &HashMap<String, Origin>,
+    previous: &BTreeMap<String, IndexEntry>,
+    now: u64,
+) -> BTreeMap<String, IndexEntry> {
+    // Entries from before identities were recorded start over
+    let by_identity: HashMap<&str, &Health> = previous
+        .values()
+        .filter_map(|entry| Some((entry.identity.as_deref()?, &entry.health)))
+        .collect();
+
+    servers
+        .iter()
+        .filter(|s| capability::is_supported_with(Target::Xray, s, options))
+        .map(|server| {
+            let origin = origins.get(server.tag());
+            let category = Category::of(server, options);
+            let identity = identity(server);
+            let entry = IndexEntry {
+                protocol: server.protocol().to_string(),
+                transport: server.transport().to_string(),
+                address: server.address().to_string(),
+                country: origin.and_then(|o| o.country.clone()),
+                category: category.as_str().to_string(),
+                balancer: category.balancer_tag().to_string(),
+                source: origin.and_then(|o| o.source.clone()),
+                latency_ms: origin
+                    .and_then(|o| o.latency)
+                    .map(|latency| latency.as_millis() as u64),
+                health: Health::update(
+                    // The same tag first, in case duplicates share an identity
+                    previous
+                        .get(server.tag())
+                        .filter(|entry| entry.identity.as_ref() == Some(&identity))
+                        .map(|entry| &entry.health)
+                        .or_else(|| by_identity.get(identity.as_str()).copied()),
+                    origin,
+                    now,
+                ),
+                identity: Some(identity),
+            };
+            (server.tag().to_string(), entry)
+        })
+        .collect()
+}
+
+/// Reads an index written by an earlier run. A missing or unreadable file
+/// just means there is no history yet.
+pub fn read_index(path: &Path) -> BTreeMap<String, IndexEntry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Writes the tag index as JSON to `path`, carrying health over from the
+/// index at `previous`, and returns it. The two differ when the output is
+/// staged before being moved into place.
+pub fn write_index(
+    path: &Path,
+    previous: &Path,
+    servers: &[ServerConfig],
+    generator: &GeneratorOptions,
+    origins: &HashMap<String, Origin>,
+    now: u64,
+    options: &WriteOptions,
+) -> Result<BTreeMap<String, IndexEntry>> {
+    let previous = read_index(previous);
+    let index = generate_index(servers, generator, origins, &previous, now);
+    write_config_with(path, &serde_json::to_value(&index)?, options)?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_generate_index() {
+        let servers = vec![
+            ServerConfig::Shadowsocks {
+                tag: "de-1".to_string(),
+                address: "203.0.113.1".to_string(),
+                port: 8388,
+                method: "aes-256-gcm".to_string(),
+                password: "secret".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
+            },
+            ServerConfig::Brook {
+                tag: "brook-1".to_string(),
+                address: "203.0.113.2".to_string(),
+                port: 9999,
+                password: "secret".to_string(),
+                kind: "server".to_string(),
+                path: String::new(),
+            },
+        ];
+        let origins = HashMap::from([(
+            "de-1".to_string(),
+            Origin {
+                source: Some("https://example.com/sub".to_string()),
+                latency: Some(Duration::from_millis(42)),
+                country: Some("DE".to_string()),
+                checked: true,
+            },
+        )]);
+
+        let index = generate_index(
+            &servers,
+            &GeneratorOptions::default(),
+            &origins,
+            &BTreeMap::new(),
+            1_700_000_000,
+        );
+        // No Xray outbound, so nothing to look up
+        assert!(!index.contains_key("brook-1"));
+        assert_eq!(
+            serde_json::to_value(&index["de-1"]).unwrap(),
+            serde_json::json!({
+                "protocol": "shadowsocks",
+                "transport": "tcp",
+                "address": "203.0.113.1",
+                "country": "DE",
+                "category": "proxy",
+                "balancer": "proxy-balance",
+                "source": "https://example.com/sub",
+                "latency_ms": 42,
+                "identity": identity(&servers[0]),
+                "last_checked": 1_700_000_000,
+                "consecutive_failures": 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_index_carries_health() {
+        let server = |tag: &str| ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: "203.0.113.1".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        };
+        let servers = vec![server("failed"), server("unchecked")];
+        let origin = |checked| Origin {
+            source: None,
+            latency: None,
+            country: None,
+            checked,
+        };
+        let origins = HashMap::from([
+            ("failed".to_string(), origin(true)),
+            ("unchecked".to_string(), origin(false)),
+        ]);
+        let options = GeneratorOptions::default();
+
+        let first = generate_index(&servers, &options, &origins, &BTreeMap::new(), 100);
+        let previous: BTreeMap<String, IndexEntry> =
+            serde_json::from_value(serde_json::to_value(&first).unwrap()).unwrap();
+        let second = generate_index(&servers, &options, &origins, &previous, 200);
+        assert_eq!(
+            second["failed"].health,
+            Health {
+                last_checked: Some(200),
+                consecutive_failures: 2,
+            }
+        );
+        assert_eq!(second["unchecked"].health, Health::default());
+
+        // An answer resets the count
+        let mut origins = origins;
+        origins.get_mut("failed").unwrap().latency = Some(Duration::from_millis(10));
+        let third = generate_index(&servers, &options, &origins, &second, 300);
+        assert_eq!(third["failed"].health.consecutive_failures, 0);
+        assert_eq!(third["failed"].health.last_checked, Some(300));
+    }
+
+    #[test]
+    fn test_generate_index_follows_identity() {
+        let server = |tag: &str, address: &str| ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: address.to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        };
+        let failed = Origin {
+            source: None,
+            latency: None,
+            country: None,
+            checked: true,
+        };
+        let origins = HashMap::from([("de-1".to_string(), failed)]);
+        let options = GeneratorOptions::default();
+        let first = generate_index(
+            &[server("de-1", "203.0.113.1")],
+            &options,
+            &origins,
+            &BTreeMap::new(),
+            100,
+        );
+
+        // The subscription now lists another server first: its tag is taken,
+        // the failing server moved to de-2 and keeps its history
+        let servers = vec![server("de-1", "203.0.113.9"), server("de-2", "203.0.113.1")];
+        let second = generate_index(&servers, &options, &HashMap::new(), &first, 200);
+        assert_eq!(second["de-1"].health, Health::default());
+        assert_eq!(second["de-2"].health, first["de-1"].health);
+
+        // Without recorded identities nothing is carried over
+        let mut legacy = first.clone();
+        legacy.get_mut("de-1").unwrap().identity = None;
+        let third = generate_index(
+            &[server("de-1", "203.0.113.1")],
+            &options,
+            &HashMap::new(),
+            &legacy,
+            300,
+        );
+        assert_eq!(third["de-1"].health, Health::default());
+    }
+}
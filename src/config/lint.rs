@@ -0,0 +1,351 @@
+//! Checks on the final routing rule list, run before any file is written.
+//!
+//! Xray evaluates rules top to bottom and stops at the first match, so a rule
+//! that only matches traffic an earlier rule already takes never fires. Xray
+//! accepts such configs silently; so does a rule pointing at an outbound that
+//! was never generated, until the first connection through it fails.
+
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+/// Rule fields that pick where traffic goes rather than which traffic matches.
+const TARGET_FIELDS: &[&str] = &["type", "outboundTag", "balancerTag", "ruleTag"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Works, but probably not as intended
+    Warning,
+    /// Xray would reject the config or misroute traffic
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Lints `routing` and fails on errors; warnings are logged.
+///
+/// `outbounds` are the tags rules may send traffic to: the generated servers,
+/// `direct`/`block` and any hand-written outbounds (`--reserved-tag`). Other
+/// outbounds are only warned about, since a rules file written for an
+/// existing confdir may name outbounds from its other files.
+pub fn check_routing(routing: &Value, outbounds: &HashSet<String>) -> Result<()> {
+    let findings = lint_routing(routing, outbounds);
+
+    let mut errors = Vec::new();
+    for finding in findings {
+        match finding.severity {
+            Severity::Warning => log::warn!("Routing: {}", finding),
+            Severity::Error => errors.push(finding.message),
+        }
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("Invalid routing:\n  {}", errors.join("\n  "));
+    }
+    Ok(())
+}
+
+/// Finds unreachable, duplicate and dangling rules, balancers no rule uses,
+/// and balancer selectors that match nothing or outbounds they don't list.
+/// Rules are numbered from 1 in messages, in file order.
+pub fn lint_routing(routing: &Value, outbounds: &HashSet<String>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let empty = Vec::new();
+    let rules = routing["routing"]["rules"].as_array().unwrap_or(&empty);
+    let balancers = routing["routing"]["balancers"].as_array().unwrap_or(&empty);
+
+    let mut balancer_tags = HashSet::new();
+    for balancer in balancers {
+        let tag = balancer["tag"].as_str().unwrap_or_default();
+        balancer_tags.insert(tag);
+
+        let selector: Vec<&str> = balancer["selector"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        if selector.is_empty() {
+            findings.push(Finding::warning(format!(
+                "Balancer {} has an empty selector",
+                tag
+            )));
+        }
+        // Selectors are prefix matches
+        for prefix in &selector {
+            let mut matched: Vec<&String> = outbounds
+                .iter()
+                .filter(|outbound| outbound.starts_with(prefix))
+                .collect();
+            if matched.is_empty() {
+                findings.push(Finding::warning(format!(
+                    "Balancer {} selector {} matches no outbound",
+                    tag, prefix
+                )));
+            }
+            matched.sort();
+            for outbound in matched {
+                if !selector.contains(&outbound.as_str()) {
+                    findings.push(Finding::warning(format!(
+                        "Balancer {} selector {} also matches {}",
+                        tag, prefix, outbound
+                    )));
+                }
+            }
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    let mut parsed: Vec<(Conditions, Option<&str>)> = Vec::new();
+    for (idx, rule) in rules.iter().enumerate() {
+        let number = idx + 1;
+
+        let target = match (rule["outboundTag"].as_str(), rule["balancerTag"].as_str()) {
+            (Some(tag), None) => {
+                // May be defined in another file of the confdir
+                if !outbounds.contains(tag) {
+                    findings.push(Finding::warning(format!(
+                        "Rule {} sends traffic to unknown outbound {}; \
+                         list hand-written outbounds with --reserved-tag",
+                        number, tag
+                    )));
+                }
+                Some(tag)
+            }
+            (None, Some(tag)) => {
+                referenced.insert(tag);
+                if !balancer_tags.contains(tag) {
+                    findings.push(Finding::error(format!(
+                        "Rule {} sends traffic to unknown balancer {}",
+                        number, tag
+                    )));
+                }
+                Some(tag)
+            }
+            _ => {
+                findings.push(Finding::error(format!(
+                    "Rule {} needs exactly one of outboundTag and balancerTag",
+                    number
+                )));
+                None
+            }
+        };
+
+        let (conditions, empty_fields) = Conditions::of(rule);
+        for field in empty_fields {
+            findings.push(Finding::warning(format!(
+                "Rule {} has an empty {} list, which Xray ignores",
+                number, field
+            )));
+        }
+
+        if let Some((earlier, (_, earlier_target))) = parsed
+            .iter()
+            .enumerate()
+            .find(|(_, (earlier, _))| earlier.covers(&conditions))
+        {
+            let message = if *earlier_target == target && conditions.covers(&parsed[earlier].0) {
+                format!("Rule {} duplicates rule {}", number, earlier + 1)
+            } else {
+                format!(
+                    "Rule {} is unreachable: rule {} matches all of its traffic first",
+                    number,
+                    earlier + 1
+                )
+            };
+            findings.push(Finding::warning(message));
+        }
+
+        parsed.push((conditions, target));
+    }
+
+    for balancer in balancers {
+        let tag = balancer["tag"].as_str().unwrap_or_default();
+        if !referenced.contains(tag) {
+            findings.push(Finding::warning(format!(
+                "Balancer {} is referenced by no rule",
+                tag
+            )));
+        }
+    }
+
+    findings
+}
+
+/// What a rule matches on: every non-empty condition field with its values.
+/// Comma-separated strings (`port`, `network`, ...) are split into values.
+#[derive(Debug)]
+struct Conditions(Vec<(String, BTreeSet<String>)>);
+
+impl Conditions {
+    /// Also returns the fields given as empty lists, which Xray treats as
+    /// absent.
+    fn of(rule: &Value) -> (Self, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut empty = Vec::new();
+
+        for (field, value) in rule.as_object().into_iter().flatten() {
+            if TARGET_FIELDS.contains(&field.as_str()) {
+                continue;
+            }
+
+            let values: BTreeSet<String> = match value {
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| match item {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect(),
+                Value::String(s) => s
+                    .split(',')
+                    .map(|part| part.trim().to_string())
+                    .filter(|part| !part.is_empty())
+                    .collect(),
+                other => BTreeSet::from([other.to_string()]),
+            };
+
+            if values.is_empty() {
+                empty.push(field.clone());
+                continue;
+            }
+            // Both networks is every network
+            if field == "network" && values.contains("tcp") && values.contains("udp") {
+                continue;
+            }
+            conditions.push((field.clone(), values));
+        }
+
+        (Self(conditions), empty)
+    }
+
+    /// Whether every connection `other` matches is also matched by `self`.
+    ///
+    /// Values are compared literally, so overlapping port ranges or CIDRs
+    /// aren't recognized; this errs on the side of not reporting.
+    fn covers(&self, other: &Conditions) -> bool {
+        self.0.iter().all(|(field, values)| {
+            other
+                .0
+                .iter()
+                .find(|(other_field, _)| other_field == field)
+                .is_some_and(|(_, other_values)| other_values.is_subset(values))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn outbounds(tags: &[&str]) -> HashSet<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn messages(routing: &Value, tags: &[&str]) -> Vec<String> {
+        lint_routing(routing, &outbounds(tags))
+            .into_iter()
+            .map(|f| f.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_unreachable_and_duplicate_rules() {
+        let routing = json!({"routing": {"rules": [
+            { "inboundTag": ["tproxy"], "port": "53", "outboundTag": "direct" },
+            { "inboundTag": ["tproxy"], "port": "53", "outboundTag": "direct" },
+            { "inboundTag": ["tproxy"], "domain": [], "outboundTag": "de-1" },
+            { "inboundTag": ["tproxy"], "ip": ["10.0.0.0/8"], "outboundTag": "direct" },
+            { "inboundTag": ["tproxy", "redirect"], "network": "tcp,udp", "outboundTag": "block" }
+        ], "balancers": []}});
+
+        assert_eq!(
+            messages(&routing, &["direct", "block", "de-1"]),
+            vec![
+                "Rule 2 duplicates rule 1",
+                "Rule 3 has an empty domain list, which Xray ignores",
+                // Rule 5 still gets the redirect traffic rule 3 doesn't take
+                "Rule 4 is unreachable: rule 3 matches all of its traffic first",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_unknown_targets() {
+        let routing = json!({"routing": {"rules": [
+            { "user": ["tv@home"], "outboundTag": "my-paid-node" },
+            { "user": ["kid@home"], "balancerTag": "eu-balance" },
+            { "source": ["10.0.0.2"] }
+        ], "balancers": [
+            { "tag": "proxy-balance", "selector": ["de-1", "fr-"] },
+            { "tag": "claude-balance", "selector": ["de-10"] },
+            { "tag": "warp-balance", "selector": [] }
+        ]}});
+
+        let findings = lint_routing(&routing, &outbounds(&["direct", "de-1", "de-10"]));
+        let errors: Vec<&str> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Rule 2 sends traffic to unknown balancer eu-balance",
+                "Rule 3 needs exactly one of outboundTag and balancerTag",
+            ]
+        );
+        let warnings: Vec<&str> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Warning)
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "Balancer proxy-balance selector de-1 also matches de-10",
+                "Balancer proxy-balance selector fr- matches no outbound",
+                "Balancer warp-balance has an empty selector",
+                "Rule 1 sends traffic to unknown outbound my-paid-node; \
+                 list hand-written outbounds with --reserved-tag",
+                "Balancer proxy-balance is referenced by no rule",
+                "Balancer claude-balance is referenced by no rule",
+                "Balancer warp-balance is referenced by no rule",
+            ]
+        );
+
+        let error = check_routing(&routing, &outbounds(&["direct", "de-1"]))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown balancer eu-balance"));
+        assert!(!error.contains("my-paid-node"));
+    }
+}
@@ -1,12 +1,535 @@
+pub mod annotate;
+pub mod capability;
+pub mod geosite;
+pub mod index;
+pub mod lint;
 pub mod outbound;
 pub mod routing;
+pub mod wireguard;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
 use serde_json::Value;
-use std::path::Path;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{DirBuilder, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+/// How generated config files are written to disk.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    /// Print every file to stdout as an envelope line (see `write_envelope`)
+    /// instead of writing it to disk
+    pub stdout: bool,
+    /// fsync every written file and its parent directory
+    pub fsync: bool,
+    /// Permission bits of written files (unix only); configs contain credentials
+    pub file_mode: Option<u32>,
+    /// Write minified single-line JSON instead of pretty-printing it
+    pub compact: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            stdout: false,
+            fsync: false,
+            file_mode: Some(0o600),
+            compact: false,
+        }
+    }
+}
+
+/// How outbounds and routing are generated from parsed servers.
+#[derive(Debug, Clone, Default)]
+pub struct GeneratorOptions {
+    /// `level` set on every user/server entry
+    pub user_level: u32,
+    /// Set each user's `email` to the outbound tag, so Xray per-user stats
+    /// (statsUserUplink/Downlink) can tell outbounds apart
+    pub user_email: bool,
+    /// User rules (source IP, user email, source port) placed ahead of the
+    /// default rule
+    pub custom_rules: Vec<routing::CustomRule>,
+    /// `streamSettings.sockopt` dialer settings for every proxy outbound
+    pub dialer: DialerOptions,
+    /// Tags of servers known to be CDN-fronted beyond what their address
+    /// shows, e.g. from reverse DNS; balanced with the Cloudflare servers
+    pub cdn_tags: HashSet<String>,
+    /// Strategy of the generated balancers
+    pub balancer: routing::StrategyOptions,
+    /// How hysteria2 servers, which Xray can't dial itself, are exported
+    pub hysteria2: outbound::Hysteria2Mode,
+}
+
+/// How outbounds dial their server, for dual-stack networks where IPv6 is
+/// flaky. Emitted as Xray's `streamSettings.sockopt`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DialerOptions {
+    /// How the server's domain is resolved before dialing
+    pub domain_strategy: Option<DomainStrategy>,
+    /// Linux `TCP_USER_TIMEOUT` (`tcpUserTimeout`): drop the connection once
+    /// sent data stays unacknowledged for this many milliseconds
+    pub tcp_user_timeout_ms: Option<u32>,
+    /// Race IPv4 and IPv6 connections, starting the next one after this many
+    /// milliseconds
+    pub happy_eyeballs_delay_ms: Option<u32>,
+    /// Try IPv6 addresses first when racing connections
+    pub prefer_ipv6: bool,
+}
+
+impl DialerOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The `sockopt` object, or None if nothing is set.
+    pub fn sockopt(&self) -> Option<Value> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut sockopt = serde_json::Map::new();
+        // Happy eyeballs only kicks in when Xray resolves the domain itself
+        let domain_strategy = self
+            .domain_strategy
+            .or(self.happy_eyeballs_delay_ms.map(|_| DomainStrategy::UseIp));
+        if let Some(strategy) = domain_strategy {
+            sockopt.insert("domainStrategy".into(), strategy.as_str().into());
+        }
+        if let Some(timeout) = self.tcp_user_timeout_ms {
+            sockopt.insert("tcpUserTimeout".into(), timeout.into());
+        }
+        if let Some(delay) = self.happy_eyeballs_delay_ms {
+            sockopt.insert(
+                "happyEyeballs".into(),
+                serde_json::json!({
+                    "tryDelayMs": delay,
+                    "prioritizeIPv6": self.prefer_ipv6,
+                    "interleave": 1
+                }),
+            );
+        }
+        Some(Value::Object(sockopt))
+    }
+}
+
+/// Xray `sockopt.domainStrategy` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DomainStrategy {
+    /// Let the system resolve the domain
+    AsIs,
+    /// Resolve with Xray's DNS, either address family
+    #[value(name = "UseIP")]
+    UseIp,
+    /// Resolve with Xray's DNS, IPv4 only
+    #[value(name = "UseIPv4")]
+    UseIpv4,
+    /// Resolve with Xray's DNS, IPv6 only
+    #[value(name = "UseIPv6")]
+    UseIpv6,
+    /// Resolve with Xray's DNS, IPv4 first
+    #[value(name = "UseIPv4v6")]
+    UseIpv4v6,
+    /// Resolve with Xray's DNS, IPv6 first
+    #[value(name = "UseIPv6v4")]
+    UseIpv6v4,
+}
+
+impl DomainStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DomainStrategy::AsIs => "AsIs",
+            DomainStrategy::UseIp => "UseIP",
+            DomainStrategy::UseIpv4 => "UseIPv4",
+            DomainStrategy::UseIpv6 => "UseIPv6",
+            DomainStrategy::UseIpv4v6 => "UseIPv4v6",
+            DomainStrategy::UseIpv6v4 => "UseIPv6v4",
+        }
+    }
+}
 
 pub fn write_config(path: &Path, config: &Value) -> Result<()> {
-    let json = serde_json::to_string_pretty(config)?;
-    std::fs::write(path, json)?;
+    write_config_with(path, config, &WriteOptions::default())
+}
+
+pub fn write_config_with(path: &Path, config: &Value, options: &WriteOptions) -> Result<()> {
+    let json = if options.compact {
+        serde_json::to_string(config)?
+    } else {
+        serde_json::to_string_pretty(config)?
+    };
+    write_config_file(path, options, |file| Ok(file.write_all(json.as_bytes())?))
+}
+
+/// Creates the output directory (and its parents), applying `umask` to the
+/// directories it creates on unix.
+pub fn create_output_dir(path: &Path, umask: Option<u32>) -> Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    if let Some(umask) = umask {
+        builder.mode(0o777 & !umask);
+    }
+    #[cfg(not(unix))]
+    let _ = umask;
+
+    builder.create(path)?;
     Ok(())
 }
+
+/// Name of the lock file kept in the output directory.
+pub(crate) const LOCK_FILE_NAME: &str = ".proxy-harvest.lock";
+
+/// Advisory lock on an output directory, released when dropped.
+#[derive(Debug)]
+pub struct OutputLock {
+    _file: File,
+}
+
+/// Takes an exclusive advisory lock on the output directory, so two runs
+/// (e.g. one from cron and one started by hand) can't interleave writes.
+pub fn lock_output_dir(path: &Path) -> Result<OutputLock> {
+    let lock_path = path.join(LOCK_FILE_NAME);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(OutputLock { _file: file }),
+        Err(TryLockError::WouldBlock) => anyhow::bail!(
+            "Another run is already writing to {} (lock file {})",
+            path.display(),
+            lock_path.display()
+        ),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Name of the directory, inside the output directory, a run stages its
+/// files in.
+pub(crate) const STAGING_DIR_NAME: &str = ".proxy-harvest.staging";
+
+/// Files of one run, written to a staging directory and moved into the output
+/// directory only once every one of them is written. A failed or interrupted
+/// run leaves the previous set in place, never new outbounds next to old
+/// routing. Dropped without `commit`, it removes what was staged.
+///
+/// Moving the files in is one rename each, not one atomic step: a run
+/// killed in the middle of `commit` can still leave a mixed set.
+#[derive(Debug)]
+pub struct Staging {
+    dir: PathBuf,
+    output: PathBuf,
+    committed: bool,
+}
+
+impl Staging {
+    /// Creates an empty staging directory in `output`, which must be locked
+    /// with `lock_output_dir`.
+    pub fn new(output: &Path) -> Result<Self> {
+        let dir = output.join(STAGING_DIR_NAME);
+        // Left behind by a run that was killed
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        create_output_dir(&dir, Some(0o077))?;
+        Ok(Self {
+            dir,
+            output: output.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// Where files of this run are written.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves the staged files into the output directory in file name order,
+    /// so `05_routing.json` follows the outbounds it refers to. Returns their
+    /// new paths.
+    pub fn commit(mut self, options: &WriteOptions) -> Result<Vec<PathBuf>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name());
+            }
+        }
+        names.sort();
+
+        let mut moved = Vec::with_capacity(names.len());
+        for name in names {
+            let path = self.output.join(&name);
+            std::fs::rename(self.dir.join(&name), &path)
+                .with_context(|| format!("Failed to move {} into place", path.display()))?;
+            moved.push(path);
+        }
+        if options.fsync
+            && let Some(path) = moved.first()
+        {
+            sync_parent_dir(path)?;
+        }
+
+        std::fs::remove_dir_all(&self.dir)?;
+        self.committed = true;
+        Ok(moved)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Writes a config file atomically: `write` fills a temporary file next to
+/// `path`, which is then renamed over it. An interrupted or failed run leaves
+/// the previous file in place instead of a truncated one.
+///
+/// With `options.stdout` the file is printed as an envelope line instead.
+pub(crate) fn write_config_file(
+    path: &Path,
+    options: &WriteOptions,
+    write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    if options.stdout {
+        let mut content = Vec::new();
+        write(&mut content)?;
+        let mut stdout = std::io::stdout().lock();
+        write_envelope(&mut stdout, path, &content)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let temp_path = temp_path(path);
+
+    let result = create_config_file(&temp_path, options).and_then(|mut file| {
+        write(&mut file)?;
+        if options.fsync {
+            file.sync_all()?;
+        }
+        std::fs::rename(&temp_path, path)?;
+        if options.fsync {
+            sync_parent_dir(path)?;
+        }
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Writes a generated file as one JSON line, `{"content": "...", "file":
+/// "04_outbounds.json"}`, so a stream of files can be split apart with e.g.
+/// `jq -r 'select(.file == "05_routing.json").content'`.
+pub fn write_envelope(out: &mut dyn Write, path: &Path, content: &[u8]) -> Result<()> {
+    let envelope = serde_json::json!({
+        "file": path.file_name().unwrap_or_default().to_string_lossy(),
+        "content": String::from_utf8_lossy(content),
+    });
+    serde_json::to_writer(&mut *out, &envelope)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn create_config_file(path: &Path, options: &WriteOptions) -> Result<File> {
+    let mut open_options = OpenOptions::new();
+    open_options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    if let Some(mode) = options.file_mode {
+        open_options.mode(mode);
+    }
+
+    let file = open_options.open(path)?;
+
+    // The creation mode doesn't apply to files that already exist
+    #[cfg(unix)]
+    if let Some(mode) = options.file_mode {
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(file)
+}
+
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    // Directories can only be opened for syncing on unix
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let dir = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_config_with_restricts_permissions() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-write-{}", std::process::id()));
+        create_output_dir(&dir, Some(0o077)).unwrap();
+        let path = dir.join("config.json");
+
+        let options = WriteOptions {
+            stdout: false,
+            fsync: true,
+            file_mode: Some(0o600),
+            compact: true,
+        };
+        write_config_with(&path, &json!({"key": "value"}), &options).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let file_mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let dir_mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, r#"{"key":"value"}"#);
+        assert_eq!(file_mode & 0o777, 0o600);
+        assert_eq!(dir_mode & 0o777, 0o700);
+    }
+
+    #[test]
+    fn test_lock_output_dir_is_exclusive() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-lock-{}", std::process::id()));
+        create_output_dir(&dir, None).unwrap();
+
+        let lock = lock_output_dir(&dir).unwrap();
+        assert!(lock_output_dir(&dir).is_err());
+        drop(lock);
+        assert!(lock_output_dir(&dir).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_envelope() {
+        let mut out = Vec::new();
+        write_envelope(
+            &mut out,
+            Path::new("configs/04_outbounds.json"),
+            b"{\n  \"a\": 1\n}",
+        )
+        .unwrap();
+        write_envelope(&mut out, Path::new("configs/05_routing.json"), b"{}").unwrap();
+
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                json!({ "file": "04_outbounds.json", "content": "{\n  \"a\": 1\n}" }),
+                json!({ "file": "05_routing.json", "content": "{}" })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_config_file_keeps_old_file_on_failure() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-atomic-{}", std::process::id()));
+        create_output_dir(&dir, None).unwrap();
+        let path = dir.join("config.json");
+        write_config(&path, &json!({"version": 1})).unwrap();
+
+        let result = write_config_file(&path, &WriteOptions::default(), |file| {
+            file.write_all(b"{\"version\": ")?;
+            anyhow::bail!("interrupted")
+        });
+        assert!(result.is_err());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            content,
+            serde_json::to_string_pretty(&json!({"version": 1})).unwrap()
+        );
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn test_staging_moves_files_together() {
+        let dir =
+            std::env::temp_dir().join(format!("proxy-harvest-staging-{}", std::process::id()));
+        create_output_dir(&dir, None).unwrap();
+        write_config(&dir.join("05_routing.json"), &json!({"version": 1})).unwrap();
+
+        // Dropped without commit: the output directory is untouched
+        let staging = Staging::new(&dir).unwrap();
+        write_config(
+            &staging.dir().join("05_routing.json"),
+            &json!({"version": 2}),
+        )
+        .unwrap();
+        drop(staging);
+        let names_after_drop: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        let content_after_drop = std::fs::read_to_string(dir.join("05_routing.json")).unwrap();
+
+        let staging = Staging::new(&dir).unwrap();
+        write_config(
+            &staging.dir().join("05_routing.json"),
+            &json!({"version": 3}),
+        )
+        .unwrap();
+        write_config(&staging.dir().join("04_outbounds.json"), &json!([])).unwrap();
+        let moved = staging.commit(&WriteOptions::default()).unwrap();
+        let content = std::fs::read_to_string(dir.join("05_routing.json")).unwrap();
+        let staged_left = dir.join(STAGING_DIR_NAME).exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            names_after_drop,
+            vec![std::ffi::OsString::from("05_routing.json")]
+        );
+        assert_eq!(
+            content_after_drop,
+            serde_json::to_string_pretty(&json!({"version": 1})).unwrap()
+        );
+        assert_eq!(
+            moved,
+            vec![dir.join("04_outbounds.json"), dir.join("05_routing.json")]
+        );
+        assert_eq!(
+            content,
+            serde_json::to_string_pretty(&json!({"version": 3})).unwrap()
+        );
+        assert!(!staged_left);
+    }
+}
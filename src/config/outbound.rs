@@ -1,402 +1,1188 @@
-use crate::parser::{NetworkSettings, ServerConfig};
-use anyhow::Result;
+use super::capability::{self, Target};
+use super::{GeneratorOptions, WriteOptions, write_config_file};
+use crate::convert::server_to_url;
+use crate::parser::{NetworkSettings, ServerConfig, format_host};
+use crate::progress::{Cancelled, Hooks, Stage};
+use crate::report::{PanickedInput, catch_panic, redact_input};
+use anyhow::{Context, Result};
+use serde::ser::{Error as _, Serialize, SerializeMap, SerializeSeq, Serializer};
 use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Chunk of servers generated in parallel before being streamed to the writer.
+const STREAM_CHUNK_SIZE: usize = 1024;
 
 pub fn generate_outbounds(servers: &[ServerConfig]) -> Result<Value> {
-    let mut outbounds = Vec::new();
-
-    // Add all parsed servers
-    for server in servers {
-        let outbound = match server {
-            ServerConfig::Shadowsocks {
-                tag,
-                address,
-                port,
-                method,
-                password,
-            } => {
-                json!({
-                    "tag": tag,
-                    "protocol": "shadowsocks",
-                    "settings": {
-                        "servers": [
-                            {
-                                "address": address,
-                                "port": port,
-                                "method": method,
-                                "password": password
-                            }
-                        ]
-                    }
-                })
-            }
-            ServerConfig::Vless {
-                tag,
-                address,
-                port,
-                id,
-                encryption,
-                flow,
-                network,
-                security,
-                tls_settings,
-                network_settings,
-            } => {
-                let mut outbound = json!({
-                    "tag": tag,
-                    "protocol": "vless",
-                    "settings": {
-                        "vnext": [
-                            {
-                                "address": address,
-                                "port": port,
-                                "users": [
-                                    {
-                                        "id": id,
-                                        "flow": flow,
-                                        "encryption": encryption,
-                                        "level": 0
-                                    }
-                                ]
-                            }
-                        ]
-                    }
-                });
+    generate_outbounds_with(servers, &GeneratorOptions::default())
+}
 
-                // Build stream settings
-                let mut stream_settings = json!({
-                    "network": network,
-                    "security": security
-                });
+pub fn generate_outbounds_with(
+    servers: &[ServerConfig],
+    options: &GeneratorOptions,
+) -> Result<Value> {
+    let mut outbounds: Vec<Value> = servers
+        .iter()
+        .filter_map(|server| generate_outbound_with(server, options))
+        .collect();
 
-                // Add TLS/Reality settings
-                if let Some(tls) = &**tls_settings {
-                    if security == "reality" {
-                        let mut reality_settings = json!({
-                            "fingerprint": tls.fingerprint,
-                            "serverName": tls.server_name
-                        });
+    // Add standard outbounds
+    outbounds.extend(standard_outbounds());
 
-                        if let Some(ref pk) = tls.public_key {
-                            reality_settings["publicKey"] = json!(pk);
-                        }
-                        if let Some(ref sid) = tls.short_id {
-                            reality_settings["shortId"] = json!(sid);
-                        }
-                        if let Some(ref spx) = tls.spider_x {
-                            reality_settings["spiderX"] = json!(spx);
-                        }
+    Ok(json!({
+        "outbounds": outbounds
+    }))
+}
 
-                        stream_settings["realitySettings"] = reality_settings;
-                    } else if security == "tls" {
-                        let mut tls_settings_json = json!({
-                            "fingerprint": tls.fingerprint,
-                            "serverName": tls.server_name,
-                            "allowInsecure": tls.allow_insecure
-                        });
+/// A server left out of the written outbounds because generating its
+/// outbound panicked. Routing and the index must leave it out too.
+#[derive(Debug, Clone)]
+pub struct SkippedOutbound {
+    pub tag: String,
+    pub panic: PanickedInput,
+}
 
-                        if let Some(ref alpn) = tls.alpn {
-                            tls_settings_json["alpn"] = json!(alpn);
-                        }
+/// Generates outbounds in parallel and streams them straight into `path`,
+/// producing the same JSON as `generate_outbounds` without ever holding the
+/// whole document (or its pretty-printed string) in memory.
+///
+/// A server whose generation panics is left out of the file and returned.
+pub fn write_outbounds(
+    path: &Path,
+    servers: &[ServerConfig],
+    generator: &GeneratorOptions,
+    options: &WriteOptions,
+) -> Result<Vec<SkippedOutbound>> {
+    write_outbounds_with_hooks(path, servers, generator, options, &Hooks::default())
+}
 
-                        stream_settings["tlsSettings"] = tls_settings_json;
-                    }
-                }
+/// Like `write_outbounds`, reporting `Generate` progress after every chunk of
+/// servers and stopping with `Cancelled` between chunks once cancelled. A
+/// cancelled write leaves the previous file in place.
+pub fn write_outbounds_with_hooks(
+    path: &Path,
+    servers: &[ServerConfig],
+    generator: &GeneratorOptions,
+    options: &WriteOptions,
+    hooks: &Hooks,
+) -> Result<Vec<SkippedOutbound>> {
+    let skipped = Mutex::new(Vec::new());
+    let result = write_outbounds_document(
+        path,
+        &OutboundsDocument {
+            servers,
+            standard: &standard_outbounds(),
+            options: generator,
+            hooks,
+            skipped: &skipped,
+        },
+        options,
+    );
+
+    if result.is_err() && hooks.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    result?;
+    Ok(skipped.into_inner().unwrap_or_else(|e| e.into_inner()))
+}
 
-                // Add network settings
-                if let Some(net) = network_settings {
-                    match net {
-                        NetworkSettings::WebSocket { path, host } => {
-                            stream_settings["wsSettings"] = json!({
-                                "path": path,
-                                "host": host
-                            });
-                        }
-                        NetworkSettings::Grpc {
-                            service_name,
-                            authority,
-                        } => {
-                            stream_settings["grpcSettings"] = json!({
-                                "serviceName": service_name,
-                                "authority": authority,
-                                "multiMode": false
-                            });
-                        }
-                        NetworkSettings::Tcp { header_type } => {
-                            stream_settings["tcpSettings"] = json!({
-                                "header": {
-                                    "type": header_type
-                                }
-                            });
-                        }
-                    }
-                }
+/// Like `write_outbounds`, but splits the outbounds across numbered files
+/// next to `path` (`04a_outbounds.json`, `04b_outbounds.json`, …) holding at
+/// most `chunk_size` outbounds each. Xray's confdir loader appends the
+/// outbounds of every file, in file name order.
+///
+/// Removes `path` itself and chunks left over from earlier runs once the new
+/// chunks are written. Returns the paths written and the servers skipped
+/// because their generation panicked, which leaves their chunk one short.
+pub fn write_outbounds_chunked(
+    path: &Path,
+    servers: &[ServerConfig],
+    chunk_size: usize,
+    generator: &GeneratorOptions,
+    options: &WriteOptions,
+) -> Result<(Vec<PathBuf>, Vec<SkippedOutbound>)> {
+    anyhow::ensure!(chunk_size > 0, "Chunk size must be at least 1");
+
+    // Only supported servers produce an outbound, so count those
+    let servers: Vec<ServerConfig> = servers
+        .iter()
+        .filter(|s| capability::is_supported_with(Target::Xray, s, generator))
+        .cloned()
+        .collect();
+    let standard = standard_outbounds();
+    let total = servers.len() + standard.len();
+    let chunks = total.div_ceil(chunk_size);
+
+    let mut written = Vec::with_capacity(chunks);
+    let skipped = Mutex::new(Vec::new());
+    for idx in 0..chunks {
+        let start = idx * chunk_size;
+        let end = (start + chunk_size).min(total);
+        let chunk_path = chunk_path(path, idx, chunks);
+
+        write_outbounds_document(
+            &chunk_path,
+            &OutboundsDocument {
+                servers: &servers[start.min(servers.len())..end.min(servers.len())],
+                standard: &standard[start.max(servers.len()) - servers.len()
+                    ..end.max(servers.len()) - servers.len()],
+                options: generator,
+                hooks: &Hooks::default(),
+                skipped: &skipped,
+            },
+            options,
+        )?;
+        written.push(chunk_path);
+    }
 
-                outbound["streamSettings"] = stream_settings;
-                outbound
+    // Nothing on disk to clean up when printing to stdout
+    if !options.stdout {
+        remove_stale_chunks(path, &written)?;
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok((
+        written,
+        skipped.into_inner().unwrap_or_else(|e| e.into_inner()),
+    ))
+}
+
+/// Removes outbound chunk files next to `path` that aren't in `keep`, e.g.
+/// after a run that wrote fewer chunks, or none at all.
+pub fn remove_stale_chunks(path: &Path, keep: &[PathBuf]) -> Result<()> {
+    let Some(dir) = path.parent() else {
+        return Ok(());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let (number, rest) = split_file_name(path);
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry_path = entry?.path();
+        let name = entry_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let is_chunk = name
+            .strip_prefix(&number)
+            .and_then(|name| name.strip_suffix(&rest))
+            .is_some_and(|label| {
+                !label.is_empty() && label.bytes().all(|b| b.is_ascii_lowercase())
+            });
+
+        if is_chunk
+            && !keep
+                .iter()
+                .any(|kept| kept.file_name() == entry_path.file_name())
+        {
+            std::fs::remove_file(&entry_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of chunk `idx` out of `chunks`, labelled with fixed-width letters so
+/// chunks sort in order: `04a_outbounds.json`, or `04aa_outbounds.json` past 26.
+fn chunk_path(path: &Path, idx: usize, chunks: usize) -> PathBuf {
+    let mut width = 1;
+    while 26usize.pow(width) < chunks {
+        width += 1;
+    }
+
+    let mut label = vec![b'a'; width as usize];
+    let mut rest = idx;
+    for byte in label.iter_mut().rev() {
+        *byte += (rest % 26) as u8;
+        rest /= 26;
+    }
+
+    let (number, suffix) = split_file_name(path);
+    path.with_file_name(format!(
+        "{}{}{}",
+        number,
+        String::from_utf8_lossy(&label),
+        suffix
+    ))
+}
+
+/// Splits `04_outbounds.json` into `04` and `_outbounds.json`.
+fn split_file_name(path: &Path) -> (String, String) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let at = name
+        .find('_')
+        .or_else(|| name.find('.'))
+        .unwrap_or(name.len());
+    (name[..at].to_string(), name[at..].to_string())
+}
+
+fn write_outbounds_document(
+    path: &Path,
+    document: &OutboundsDocument,
+    options: &WriteOptions,
+) -> Result<()> {
+    write_config_file(path, options, |file| {
+        let mut writer = BufWriter::new(file);
+        if options.compact {
+            document.serialize(&mut serde_json::Serializer::new(&mut writer))?;
+        } else {
+            document.serialize(&mut serde_json::Serializer::pretty(&mut writer))?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Builds the Xray outbound for a single server, or None if Xray can't express it.
+pub fn generate_outbound(server: &ServerConfig) -> Option<Value> {
+    generate_outbound_with(server, &GeneratorOptions::default())
+}
+
+pub fn generate_outbound_with(server: &ServerConfig, options: &GeneratorOptions) -> Option<Value> {
+    let mut outbound = match server {
+        ServerConfig::Shadowsocks {
+            tag,
+            address,
+            port,
+            method,
+            password,
+            udp_over_tcp,
+            plugin,
+        } => {
+            let mut ss_server = json!({
+                "address": address,
+                "port": port,
+                "method": method,
+                "password": password
+            });
+
+            if *udp_over_tcp {
+                ss_server["uot"] = json!(true);
             }
-            ServerConfig::Vmess {
-                tag,
-                address,
-                port,
-                id,
-                alter_id,
-                security,
-                network,
-                network_settings,
-                tls_settings,
-                allow_insecure,
-            } => {
-                let mut outbound = json!({
-                    "tag": tag,
-                    "protocol": "vmess",
-                    "settings": {
-                        "vnext": [
-                            {
-                                "address": address,
-                                "port": port,
-                                "users": [
-                                    {
-                                        "id": id,
-                                        "alterId": alter_id,
-                                        "security": security,
-                                        "level": 0
-                                    }
-                                ]
-                            }
-                        ]
-                    }
-                });
 
-                // Build stream settings for Vmess
+            let mut outbound = json!({
+                "tag": tag,
+                "protocol": "shadowsocks",
+                "settings": {
+                    "servers": [ss_server]
+                }
+            });
+
+            // v2ray-plugin is shadowsocks over websocket; other plugins
+            // are reported by config::capability
+            if let Some(plugin) = plugin {
+                let ws = plugin.websocket()?;
                 let mut stream_settings = json!({
-                    "network": network
+                    "network": "ws",
+                    "security": if ws.tls { "tls" } else { "none" },
+                    "wsSettings": {
+                        "path": ws.path,
+                        "host": ws.host
+                    }
                 });
+                if ws.tls {
+                    stream_settings["tlsSettings"] = json!({ "serverName": ws.host });
+                }
+                outbound["streamSettings"] = stream_settings;
+            }
+            outbound
+        }
+        // SSR without protocol plugin or obfs is plain shadowsocks
+        ServerConfig::ShadowsocksR {
+            tag,
+            address,
+            port,
+            method,
+            password,
+            protocol,
+            obfs,
+            ..
+        } if protocol == "origin" && obfs == "plain" => json!({
+            "tag": tag,
+            "protocol": "shadowsocks",
+            "settings": {
+                "servers": [{
+                    "address": address,
+                    "port": port,
+                    "method": method,
+                    "password": password
+                }]
+            }
+        }),
+        ServerConfig::Vless {
+            tag,
+            address,
+            port,
+            id,
+            encryption,
+            flow,
+            network,
+            security,
+            tls_settings,
+            network_settings,
+        } => {
+            let mut outbound = json!({
+                "tag": tag,
+                "protocol": "vless",
+                "settings": {
+                    "vnext": [
+                        {
+                            "address": address,
+                            "port": port,
+                            "users": [
+                                {
+                                    "id": id,
+                                    "flow": flow,
+                                    "encryption": encryption,
+                                    "level": 0
+                                }
+                            ]
+                        }
+                    ]
+                }
+            });
+
+            // Build stream settings
+            let mut stream_settings = json!({
+                "network": network,
+                "security": security
+            });
+
+            // Add TLS/Reality settings
+            if let Some(tls) = &**tls_settings {
+                if security == "reality" {
+                    let mut reality_settings = json!({
+                        "fingerprint": tls.fingerprint,
+                        "serverName": tls.server_name
+                    });
 
-                // Add TLS settings if needed
-                let security_type = if let Some(tls) = &**tls_settings {
-                    if !tls.server_name.is_empty() {
-                        "tls"
-                    } else {
-                        "none"
+                    if let Some(ref pk) = tls.public_key {
+                        reality_settings["publicKey"] = json!(pk);
+                    }
+                    if let Some(ref sid) = tls.short_id {
+                        reality_settings["shortId"] = json!(sid);
+                    }
+                    if let Some(ref spx) = tls.spider_x {
+                        reality_settings["spiderX"] = json!(spx);
                     }
-                } else {
-                    "none"
-                };
-
-                stream_settings["security"] = json!(security_type);
 
-                if security_type == "tls"
-                    && let Some(tls) = &**tls_settings
-                {
+                    stream_settings["realitySettings"] = reality_settings;
+                } else if security == "tls" {
                     let mut tls_settings_json = json!({
+                        "fingerprint": tls.fingerprint,
                         "serverName": tls.server_name,
-                        "allowInsecure": *allow_insecure || tls.allow_insecure
+                        "allowInsecure": tls.allow_insecure
                     });
 
-                    if !tls.fingerprint.is_empty() && tls.fingerprint != "none" {
-                        tls_settings_json["fingerprint"] = json!(tls.fingerprint);
-                    }
-
-                    if let Some(ref alpn) = tls.alpn
-                        && !alpn.is_empty()
-                    {
+                    if let Some(ref alpn) = tls.alpn {
                         tls_settings_json["alpn"] = json!(alpn);
                     }
 
                     stream_settings["tlsSettings"] = tls_settings_json;
                 }
+            }
+
+            // Add network settings
+            if let Some(net) = network_settings {
+                match net {
+                    NetworkSettings::WebSocket { path, host } => {
+                        stream_settings["wsSettings"] = json!({
+                            "path": path,
+                            "host": host
+                        });
+                    }
+                    NetworkSettings::Grpc {
+                        service_name,
+                        authority,
+                    } => {
+                        stream_settings["grpcSettings"] = json!({
+                            "serviceName": service_name,
+                            "authority": authority,
+                            "multiMode": false
+                        });
+                    }
+                    NetworkSettings::Tcp { header_type } => {
+                        stream_settings["tcpSettings"] = json!({
+                            "header": {
+                                "type": header_type
+                            }
+                        });
+                    }
+                    NetworkSettings::Xhttp { path, host, mode } => {
+                        stream_settings["xhttpSettings"] = json!({
+                            "path": path,
+                            "host": host,
+                            "mode": mode
+                        });
+                    }
+                    NetworkSettings::Kcp { header_type, seed } => {
+                        stream_settings["kcpSettings"] = kcp_settings(header_type, seed);
+                    }
+                    NetworkSettings::Quic {
+                        security,
+                        key,
+                        header_type,
+                    } => {
+                        stream_settings["quicSettings"] = json!({
+                            "security": security,
+                            "key": key,
+                            "header": {
+                                "type": header_type
+                            }
+                        });
+                    }
+                }
+            }
 
-                // Add network settings
-                if let Some(net) = network_settings {
-                    match net {
-                        NetworkSettings::WebSocket { path, host } => {
-                            stream_settings["wsSettings"] = json!({
-                                "path": path,
-                                "headers": {
-                                    "Host": host
+            outbound["streamSettings"] = stream_settings;
+            outbound
+        }
+        ServerConfig::Vmess {
+            tag,
+            address,
+            port,
+            id,
+            alter_id,
+            security,
+            network,
+            network_settings,
+            tls_settings,
+            allow_insecure,
+        } => {
+            let mut outbound = json!({
+                "tag": tag,
+                "protocol": "vmess",
+                "settings": {
+                    "vnext": [
+                        {
+                            "address": address,
+                            "port": port,
+                            "users": [
+                                {
+                                    "id": id,
+                                    "alterId": alter_id,
+                                    "security": security,
+                                    "level": 0
                                 }
-                            });
+                            ]
                         }
-                        NetworkSettings::Grpc {
-                            service_name,
-                            authority,
-                        } => {
-                            stream_settings["grpcSettings"] = json!({
-                                "serviceName": service_name,
-                                "authority": authority,
-                                "multiMode": false
+                    ]
+                }
+            });
+
+            // Build stream settings for Vmess
+            let mut stream_settings = json!({
+                "network": network
+            });
+
+            // Add TLS settings if needed
+            let security_type = if let Some(tls) = &**tls_settings {
+                if !tls.server_name.is_empty() {
+                    "tls"
+                } else {
+                    "none"
+                }
+            } else {
+                "none"
+            };
+
+            stream_settings["security"] = json!(security_type);
+
+            if security_type == "tls"
+                && let Some(tls) = &**tls_settings
+            {
+                let mut tls_settings_json = json!({
+                    "serverName": tls.server_name,
+                    "allowInsecure": *allow_insecure || tls.allow_insecure
+                });
+
+                if !tls.fingerprint.is_empty() && tls.fingerprint != "none" {
+                    tls_settings_json["fingerprint"] = json!(tls.fingerprint);
+                }
+
+                if let Some(ref alpn) = tls.alpn
+                    && !alpn.is_empty()
+                {
+                    tls_settings_json["alpn"] = json!(alpn);
+                }
+
+                stream_settings["tlsSettings"] = tls_settings_json;
+            }
+
+            // Add network settings
+            if let Some(net) = network_settings {
+                match net {
+                    NetworkSettings::WebSocket { path, host } => {
+                        stream_settings["wsSettings"] = json!({
+                            "path": path,
+                            "headers": {
+                                "Host": host
+                            }
+                        });
+                    }
+                    NetworkSettings::Grpc {
+                        service_name,
+                        authority,
+                    } => {
+                        stream_settings["grpcSettings"] = json!({
+                            "serviceName": service_name,
+                            "authority": authority,
+                            "multiMode": false
+                        });
+                    }
+                    NetworkSettings::Tcp { header_type } => {
+                        if header_type != "none" {
+                            stream_settings["tcpSettings"] = json!({
+                                "header": {
+                                    "type": header_type
+                                }
                             });
                         }
-                        NetworkSettings::Tcp { header_type } => {
-                            if header_type != "none" {
-                                stream_settings["tcpSettings"] = json!({
-                                    "header": {
-                                        "type": header_type
-                                    }
-                                });
+                    }
+                    NetworkSettings::Xhttp { path, host, mode } => {
+                        stream_settings["xhttpSettings"] = json!({
+                            "path": path,
+                            "host": host,
+                            "mode": mode
+                        });
+                    }
+                    NetworkSettings::Kcp { header_type, seed } => {
+                        stream_settings["kcpSettings"] = kcp_settings(header_type, seed);
+                    }
+                    NetworkSettings::Quic {
+                        security,
+                        key,
+                        header_type,
+                    } => {
+                        stream_settings["quicSettings"] = json!({
+                            "security": security,
+                            "key": key,
+                            "header": {
+                                "type": header_type
                             }
-                        }
+                        });
                     }
                 }
-
-                outbound["streamSettings"] = stream_settings;
-                outbound
             }
-            ServerConfig::Trojan {
-                tag,
-                address,
-                port,
-                password,
-                network,
-                security,
-                tls_settings,
-                network_settings,
-                allow_insecure,
-            } => {
-                let mut outbound = json!({
-                    "tag": tag,
-                    "protocol": "trojan",
-                    "settings": {
-                        "servers": [
-                            {
-                                "address": address,
-                                "port": port,
-                                "password": password,
-                                "level": 0
-                            }
-                        ]
-                    }
-                });
 
-                // Build stream settings
-                let mut stream_settings = json!({
-                    "network": network,
-                    "security": security
+            outbound["streamSettings"] = stream_settings;
+            outbound
+        }
+        ServerConfig::Trojan {
+            tag,
+            address,
+            port,
+            password,
+            network,
+            security,
+            tls_settings,
+            network_settings,
+            allow_insecure,
+        } => {
+            let mut outbound = json!({
+                "tag": tag,
+                "protocol": "trojan",
+                "settings": {
+                    "servers": [
+                        {
+                            "address": address,
+                            "port": port,
+                            "password": password,
+                            "level": 0
+                        }
+                    ]
+                }
+            });
+
+            // Build stream settings
+            let mut stream_settings = json!({
+                "network": network,
+                "security": security
+            });
+
+            // Add TLS settings
+            if security == "tls"
+                && let Some(tls) = &**tls_settings
+            {
+                let mut tls_settings_json = json!({
+                    "serverName": tls.server_name,
+                    "allowInsecure": *allow_insecure || tls.allow_insecure
                 });
 
-                // Add TLS settings
-                if security == "tls"
-                    && let Some(tls) = &**tls_settings
+                if !tls.fingerprint.is_empty() && tls.fingerprint != "none" {
+                    tls_settings_json["fingerprint"] = json!(tls.fingerprint);
+                }
+
+                if let Some(ref alpn) = tls.alpn
+                    && !alpn.is_empty()
                 {
-                    let mut tls_settings_json = json!({
-                        "serverName": tls.server_name,
-                        "allowInsecure": *allow_insecure || tls.allow_insecure
-                    });
+                    tls_settings_json["alpn"] = json!(alpn);
+                }
 
-                    if !tls.fingerprint.is_empty() && tls.fingerprint != "none" {
-                        tls_settings_json["fingerprint"] = json!(tls.fingerprint);
-                    }
+                stream_settings["tlsSettings"] = tls_settings_json;
+            }
 
-                    if let Some(ref alpn) = tls.alpn
-                        && !alpn.is_empty()
-                    {
-                        tls_settings_json["alpn"] = json!(alpn);
+            // Add network settings
+            if let Some(net) = network_settings {
+                match net {
+                    NetworkSettings::WebSocket { path, host } => {
+                        stream_settings["wsSettings"] = json!({
+                            "path": path,
+                            "headers": {
+                                "Host": host
+                            }
+                        });
                     }
-
-                    stream_settings["tlsSettings"] = tls_settings_json;
-                }
-
-                // Add network settings
-                if let Some(net) = network_settings {
-                    match net {
-                        NetworkSettings::WebSocket { path, host } => {
-                            stream_settings["wsSettings"] = json!({
-                                "path": path,
-                                "headers": {
-                                    "Host": host
+                    NetworkSettings::Grpc {
+                        service_name,
+                        authority,
+                    } => {
+                        stream_settings["grpcSettings"] = json!({
+                            "serviceName": service_name,
+                            "authority": authority,
+                            "multiMode": false
+                        });
+                    }
+                    NetworkSettings::Tcp { header_type } => {
+                        if header_type != "none" {
+                            stream_settings["tcpSettings"] = json!({
+                                "header": {
+                                    "type": header_type
                                 }
                             });
                         }
-                        NetworkSettings::Grpc {
-                            service_name,
-                            authority,
-                        } => {
-                            stream_settings["grpcSettings"] = json!({
-                                "serviceName": service_name,
-                                "authority": authority,
-                                "multiMode": false
-                            });
-                        }
-                        NetworkSettings::Tcp { header_type } => {
-                            if header_type != "none" {
-                                stream_settings["tcpSettings"] = json!({
-                                    "header": {
-                                        "type": header_type
-                                    }
-                                });
+                    }
+                    NetworkSettings::Xhttp { path, host, mode } => {
+                        stream_settings["xhttpSettings"] = json!({
+                            "path": path,
+                            "host": host,
+                            "mode": mode
+                        });
+                    }
+                    NetworkSettings::Kcp { header_type, seed } => {
+                        stream_settings["kcpSettings"] = kcp_settings(header_type, seed);
+                    }
+                    NetworkSettings::Quic {
+                        security,
+                        key,
+                        header_type,
+                    } => {
+                        stream_settings["quicSettings"] = json!({
+                            "security": security,
+                            "key": key,
+                            "header": {
+                                "type": header_type
                             }
-                        }
+                        });
                     }
                 }
+            }
 
-                outbound["streamSettings"] = stream_settings;
-                outbound
+            outbound["streamSettings"] = stream_settings;
+            outbound
+        }
+        ServerConfig::Socks {
+            tag,
+            address,
+            port,
+            username,
+            password,
+        }
+        | ServerConfig::Http {
+            tag,
+            address,
+            port,
+            username,
+            password,
+        } => {
+            let mut proxy = json!({
+                "address": address,
+                "port": port
+            });
+            if username.is_some() || password.is_some() {
+                proxy["users"] = json!([{
+                    "user": username.as_deref().unwrap_or_default(),
+                    "pass": password.as_deref().unwrap_or_default()
+                }]);
             }
-            ServerConfig::Hysteria2 {
-                tag,
-                address,
-                port,
-                password,
-                server_name,
-                allow_insecure,
-                obfs,
-                obfs_password,
-            } => {
-                let mut settings = json!({
-                    "auth": password,
-                    "server": address,
-                    "serverPort": port,
-                    "tls": {
-                        "enabled": true,
-                        "serverName": server_name,
-                        "insecure": allow_insecure
-                    }
-                });
 
-                // Add obfs settings if present
-                if let Some(obfs_type) = obfs {
-                    settings["obfs"] = json!({
-                        "type": obfs_type,
-                        "password": obfs_password.as_ref().map(|s| s.as_str()).unwrap_or("")
-                    });
+            json!({
+                "tag": tag,
+                "protocol": server.protocol(),
+                "settings": {
+                    "servers": [proxy]
+                }
+            })
+        }
+        ServerConfig::Hysteria2 { tag, .. } => match &options.hysteria2 {
+            Hysteria2Mode::Skip => return None,
+            Hysteria2Mode::Bridge(ports) => json!({
+                "tag": tag,
+                "protocol": "socks",
+                "settings": {
+                    "servers": [{
+                        "address": BRIDGE_ADDRESS,
+                        "port": ports.get(tag)?
+                    }]
                 }
+            }),
+            // Used as written, apart from the tag routing relies on
+            Hysteria2Mode::Template(template) => {
+                let mut outbound = render_hysteria2_template(template, server).ok()?;
+                outbound["tag"] = json!(tag);
+                return Some(outbound);
+            }
+        },
+        ServerConfig::Wireguard {
+            tag,
+            address,
+            port,
+            secret_key,
+            public_key,
+            pre_shared_key,
+            local_address,
+            reserved,
+            mtu,
+        } => {
+            let mut peer = json!({
+                "publicKey": public_key,
+                "endpoint": format!("{}:{}", format_host(address), port)
+            });
+            if let Some(pre_shared_key) = pre_shared_key {
+                peer["preSharedKey"] = json!(pre_shared_key);
+            }
 
-                json!({
-                    "tag": tag,
-                    "protocol": "hysteria",
-                    "settings": settings
-                })
+            let mut settings = json!({
+                "secretKey": secret_key,
+                "peers": [peer]
+            });
+            if !local_address.is_empty() {
+                settings["address"] = json!(local_address);
+            }
+            if let Some(reserved) = reserved {
+                settings["reserved"] = json!(reserved);
+            }
+            if let Some(mtu) = mtu {
+                settings["mtu"] = json!(mtu);
             }
-        };
 
-        outbounds.push(outbound);
+            json!({
+                "tag": tag,
+                "protocol": "wireguard",
+                "settings": settings
+            })
+        }
+        // Not representable in Xray, reported by config::capability
+        ServerConfig::ShadowsocksR { .. }
+        | ServerConfig::Naive { .. }
+        | ServerConfig::Hysteria { .. }
+        | ServerConfig::Brook { .. }
+        | ServerConfig::Mieru { .. }
+        | ServerConfig::Tuic { .. } => return None,
+    };
+
+    apply_user_options(&mut outbound, options);
+    if let Some(sockopt) = options.dialer.sockopt() {
+        outbound["streamSettings"]["sockopt"] = sockopt;
     }
+    Some(outbound)
+}
 
-    // Add standard outbounds
-    outbounds.push(json!({
-        "tag": "direct",
-        "protocol": "freedom"
-    }));
-
-    outbounds.push(json!({
-        "tag": "block",
-        "protocol": "blackhole",
-        "settings": {
-            "response": {
-                "type": "http"
+/// Address the local hysteria2 clients of [`Hysteria2Mode::Bridge`] listen on.
+pub const BRIDGE_ADDRESS: &str = "127.0.0.1";
+
+/// How hysteria2 servers are exported. Xray has no outbound that speaks
+/// hysteria2 the way share links describe it, so by default they're left
+/// out; the other modes hand them to something that does.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Hysteria2Mode {
+    /// Leave them out, reported by config::capability
+    #[default]
+    Skip,
+    /// A SOCKS outbound per server to a local hysteria2 client listening on
+    /// the port assigned to its tag, see [`Hysteria2Mode::bridge`]
+    Bridge(BTreeMap<String, u16>),
+    /// A user-written outbound, see [`load_hysteria2_template`]
+    Template(Value),
+}
+
+impl Hysteria2Mode {
+    /// Bridges the hysteria2 servers among `servers`, giving them consecutive
+    /// local ports from `base_port` in list order.
+    pub fn bridge(servers: &[ServerConfig], base_port: u16) -> Result<Self> {
+        let mut ports = BTreeMap::new();
+        let hysteria2 = servers
+            .iter()
+            .filter(|server| matches!(server, ServerConfig::Hysteria2 { .. }));
+        for (offset, server) in hysteria2.enumerate() {
+            let port = u16::try_from(usize::from(base_port) + offset)
+                .ok()
+                .with_context(|| {
+                    format!(
+                        "Not enough local ports from {} for {} hysteria2 bridges",
+                        base_port,
+                        offset + 1
+                    )
+                })?;
+            ports.insert(server.tag().to_string(), port);
+        }
+        Ok(Self::Bridge(ports))
+    }
+}
+
+/// Lists what the local clients of [`Hysteria2Mode::Bridge`] must run, as
+/// `{"bridges": [{"tag", "listen", "server"}]}` with the server as a
+/// `hysteria2://` share link; None in the other modes.
+pub fn hysteria2_bridges(servers: &[ServerConfig], options: &GeneratorOptions) -> Option<Value> {
+    let Hysteria2Mode::Bridge(ports) = &options.hysteria2 else {
+        return None;
+    };
+
+    let bridges: Vec<Value> = servers
+        .iter()
+        .filter_map(|server| {
+            let port = ports.get(server.tag())?;
+            Some(json!({
+                "tag": server.tag(),
+                "listen": format!("{}:{}", BRIDGE_ADDRESS, port),
+                "server": crate::convert::server_to_url(server)?
+            }))
+        })
+        .collect();
+    Some(json!({ "bridges": bridges }))
+}
+
+/// Reads an outbound template for hysteria2 servers: a JSON object in which
+/// `${tag}`, `${address}`, `${port}`, `${password}`, `${sni}`, `${insecure}`,
+/// `${obfs}` and `${obfs_password}` are replaced by each server's values
+/// (`$${` is a literal `${`). A string that is just `${port}` or
+/// `${insecure}` becomes a number or boolean.
+pub fn load_hysteria2_template(path: &Path) -> Result<Value> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read hysteria2 template {}", path.display()))?;
+    parse_hysteria2_template(&content)
+        .with_context(|| format!("Invalid hysteria2 template {}", path.display()))
+}
+
+pub fn parse_hysteria2_template(content: &str) -> Result<Value> {
+    let template: Value = serde_json::from_str(content)?;
+    anyhow::ensure!(template.is_object(), "Template must be a JSON object");
+
+    // Every server defines the same variables, so this catches any unknown
+    // name up front
+    let sample = ServerConfig::Hysteria2 {
+        tag: String::new(),
+        address: String::new(),
+        port: 443,
+        password: String::new(),
+        server_name: String::new(),
+        allow_insecure: false,
+        obfs: None,
+        obfs_password: None,
+    };
+    render_hysteria2_template(&template, &sample)?;
+    Ok(template)
+}
+
+fn render_hysteria2_template(template: &Value, server: &ServerConfig) -> Result<Value> {
+    let ServerConfig::Hysteria2 {
+        tag,
+        address,
+        port,
+        password,
+        server_name,
+        allow_insecure,
+        obfs,
+        obfs_password,
+    } = server
+    else {
+        anyhow::bail!("{} is not a hysteria2 server", server.tag());
+    };
+    let variables = [
+        ("tag", json!(tag)),
+        ("address", json!(address)),
+        ("port", json!(port)),
+        ("password", json!(password)),
+        ("sni", json!(server_name)),
+        ("insecure", json!(allow_insecure)),
+        ("obfs", json!(obfs.as_deref().unwrap_or_default())),
+        (
+            "obfs_password",
+            json!(obfs_password.as_deref().unwrap_or_default()),
+        ),
+    ];
+    let lookup = |name: &str| {
+        variables
+            .iter()
+            .find(|(variable, _)| *variable == name)
+            .map(|(_, value)| match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+    };
+
+    let mut outbound = template.clone();
+    render_strings(&mut outbound, &|text| {
+        let whole = text
+            .strip_prefix("${")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .and_then(|name| variables.iter().find(|(variable, _)| *variable == name));
+        match whole {
+            Some((_, value)) => Ok(value.clone()),
+            None => super::routing::expand(text, &lookup).map(Value::String),
+        }
+    })?;
+    Ok(outbound)
+}
+
+fn render_strings(value: &mut Value, render: &impl Fn(&str) -> Result<Value>) -> Result<()> {
+    match value {
+        Value::String(text) => *value = render(text)?,
+        Value::Array(items) => {
+            for item in items {
+                render_strings(item, render)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                render_strings(field, render)?;
             }
         }
-    }));
+        _ => {}
+    }
+    Ok(())
+}
 
-    Ok(json!({
-        "outbounds": outbounds
-    }))
+/// mKCP settings; the seed is left out when empty, which Xray treats as
+/// no obfuscation.
+fn kcp_settings(header_type: &str, seed: &str) -> Value {
+    let mut settings = json!({
+        "header": {
+            "type": header_type
+        }
+    });
+    if !seed.is_empty() {
+        settings["seed"] = json!(seed);
+    }
+    settings
+}
+
+/// Sets `level` and optionally `email` on every user (vnext) and server entry.
+fn apply_user_options(outbound: &mut Value, options: &GeneratorOptions) {
+    let email = options
+        .user_email
+        .then(|| outbound["tag"].as_str().map(str::to_string))
+        .flatten();
+
+    let settings = &mut outbound["settings"];
+    let mut entries: Vec<&mut Value> = Vec::new();
+    if settings.get("vnext").is_some() {
+        for server in settings["vnext"].as_array_mut().into_iter().flatten() {
+            if let Some(users) = server.get_mut("users").and_then(Value::as_array_mut) {
+                entries.extend(users.iter_mut());
+            }
+        }
+    } else if let Some(servers) = settings.get_mut("servers").and_then(Value::as_array_mut) {
+        entries.extend(servers.iter_mut());
+    }
+
+    for entry in entries {
+        entry["level"] = json!(options.user_level);
+        if let Some(email) = &email {
+            entry["email"] = json!(email);
+        }
+    }
+}
+
+fn standard_outbounds() -> [Value; 2] {
+    [
+        json!({
+            "tag": "direct",
+            "protocol": "freedom"
+        }),
+        json!({
+            "tag": "block",
+            "protocol": "blackhole",
+            "settings": {
+                "response": {
+                    "type": "http"
+                }
+            }
+        }),
+    ]
+}
+
+/// The server's share URL as [`redact_input`] cuts it down, for reports.
+fn redact_server(server: &ServerConfig) -> String {
+    // The panic may have come from data the URL is built from, too
+    match catch_panic(|| server_to_url(server)) {
+        Ok(Some(url)) => redact_input(&url),
+        _ => format!(
+            "{}://…@{}",
+            server.protocol(),
+            format_host(server.address())
+        ),
+    }
+}
+
+/// Generates the outbounds of `servers` in parallel, in order. Servers whose
+/// generation panics are left out and pushed to `skipped`.
+fn generate_outbounds_parallel(
+    servers: &[ServerConfig],
+    options: &GeneratorOptions,
+    skipped: &Mutex<Vec<SkippedOutbound>>,
+) -> Vec<Value> {
+    generate_outbounds_parallel_by(
+        servers,
+        |server| generate_outbound_with(server, options),
+        skipped,
+    )
+}
+
+fn generate_outbounds_parallel_by<F>(
+    servers: &[ServerConfig],
+    generate: F,
+    skipped: &Mutex<Vec<SkippedOutbound>>,
+) -> Vec<Value>
+where
+    F: Fn(&ServerConfig) -> Option<Value> + Sync,
+{
+    let results = generate_isolated(servers, generate);
+    servers
+        .iter()
+        .zip(results)
+        .filter_map(|(server, result)| match result {
+            Ok(outbound) => outbound,
+            Err(message) => {
+                log::warn!(
+                    "Skipping {}: outbound generation panicked: {}",
+                    server.tag(),
+                    message
+                );
+                skipped
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(SkippedOutbound {
+                        tag: server.tag().to_string(),
+                        panic: PanickedInput {
+                            stage: "generate".to_string(),
+                            input: redact_server(server),
+                            message,
+                        },
+                    });
+                None
+            }
+        })
+        .collect()
+}
+
+/// Runs `generate` on every server across threads, in order, with a panic
+/// caught per server and returned as its message.
+fn generate_isolated<F>(
+    servers: &[ServerConfig],
+    generate: F,
+) -> Vec<std::result::Result<Option<Value>, String>>
+where
+    F: Fn(&ServerConfig) -> Option<Value> + Sync,
+{
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let per_thread = servers.len().div_ceil(threads).max(1);
+    let generate = &generate;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = servers
+            .chunks(per_thread)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|server| catch_panic(|| generate(server)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .expect("panics are caught per server inside the thread")
+            })
+            .collect()
+    })
+}
+
+struct OutboundsDocument<'a> {
+    servers: &'a [ServerConfig],
+    /// Standard outbounds (`direct`, `block`) following the servers
+    standard: &'a [Value],
+    options: &'a GeneratorOptions,
+    hooks: &'a Hooks,
+    /// Collects servers skipped while streaming
+    skipped: &'a Mutex<Vec<SkippedOutbound>>,
+}
+
+impl Serialize for OutboundsDocument<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(
+            "outbounds",
+            &OutboundList {
+                servers: self.servers,
+                standard: self.standard,
+                options: self.options,
+                hooks: self.hooks,
+                skipped: self.skipped,
+            },
+        )?;
+        map.end()
+    }
+}
+
+struct OutboundList<'a> {
+    servers: &'a [ServerConfig],
+    standard: &'a [Value],
+    options: &'a GeneratorOptions,
+    hooks: &'a Hooks,
+    /// Collects servers skipped while streaming
+    skipped: &'a Mutex<Vec<SkippedOutbound>>,
+}
+
+impl Serialize for OutboundList<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        let total = Some(self.servers.len());
+        let mut done = 0;
+        for chunk in self.servers.chunks(STREAM_CHUNK_SIZE) {
+            if self.hooks.is_cancelled() {
+                return Err(S::Error::custom(Cancelled));
+            }
+            for outbound in generate_outbounds_parallel(chunk, self.options, self.skipped) {
+                seq.serialize_element(&outbound)?;
+            }
+            done += chunk.len();
+            self.hooks.report(Stage::Generate, done, total);
+        }
+        for outbound in self.standard {
+            seq.serialize_element(outbound)?;
+        }
+        seq.end()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::DialerOptions;
     use crate::parser::{NetworkSettings, ServerConfig, TlsSettings};
 
     #[test]
@@ -407,6 +1193,8 @@ mod tests {
             port: 8388,
             method: "aes-256-gcm".to_string(),
             password: "test-password".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
         }];
 
         let result = generate_outbounds(&servers);
@@ -425,6 +1213,24 @@ mod tests {
         assert_eq!(ss["settings"]["servers"][0]["address"], "1.2.3.4");
         assert_eq!(ss["settings"]["servers"][0]["port"], 8388);
         assert_eq!(ss["settings"]["servers"][0]["method"], "aes-256-gcm");
+        assert!(ss["settings"]["servers"][0].get("uot").is_none());
+    }
+
+    #[test]
+    fn test_generate_outbounds_shadowsocks_uot() {
+        let servers = vec![ServerConfig::Shadowsocks {
+            tag: "test-ss-uot".to_string(),
+            address: "1.2.3.4".to_string(),
+            port: 8388,
+            method: "2022-blake3-aes-128-gcm".to_string(),
+            password: "test-password".to_string(),
+            udp_over_tcp: true,
+            plugin: None,
+        }];
+
+        let config = generate_outbounds(&servers).unwrap();
+        let ss = &config["outbounds"][0];
+        assert_eq!(ss["settings"]["servers"][0]["uot"], true);
     }
 
     #[test]
@@ -712,20 +1518,408 @@ mod tests {
             obfs_password: Some("obfs-pass".to_string()),
         }];
 
-        let result = generate_outbounds(&servers);
-        assert!(result.is_ok());
+        // Skipped by default: Xray can't dial hysteria2 itself
+        assert!(generate_outbound(&servers[0]).is_none());
 
-        let config = result.unwrap();
+        let bridged = GeneratorOptions {
+            hysteria2: Hysteria2Mode::bridge(&servers, 20800).unwrap(),
+            ..Default::default()
+        };
+        let outbound = generate_outbound_with(&servers[0], &bridged).unwrap();
+        assert_eq!(outbound["tag"], "hysteria2-server");
+        assert_eq!(outbound["protocol"], "socks");
+        assert_eq!(outbound["settings"]["servers"][0]["address"], "127.0.0.1");
+        assert_eq!(outbound["settings"]["servers"][0]["port"], 20800);
+
+        let bridges = hysteria2_bridges(&servers, &bridged).unwrap();
+        assert_eq!(bridges["bridges"][0]["listen"], "127.0.0.1:20800");
+        assert!(
+            bridges["bridges"][0]["server"]
+                .as_str()
+                .unwrap()
+                .starts_with("hysteria2://test-password@example.com:443")
+        );
+        assert!(hysteria2_bridges(&servers, &GeneratorOptions::default()).is_none());
+        assert!(Hysteria2Mode::bridge(&[servers[0].clone(), servers[0].clone()], 65535).is_err());
+
+        let template = parse_hysteria2_template(
+            r#"{
+                "tag": "ignored",
+                "protocol": "hysteria",
+                "settings": { "version": 2, "address": "${address}", "port": "${port}" },
+                "streamSettings": {
+                    "network": "hysteria",
+                    "hysteriaSettings": { "version": 2, "auth": "${password}" },
+                    "security": "tls",
+                    "tlsSettings": { "serverName": "${sni}", "allowInsecure": "${insecure}" },
+                    "note": "${obfs}:${obfs_password} $${literal}"
+                }
+            }"#,
+        )
+        .unwrap();
+        let templated = GeneratorOptions {
+            hysteria2: Hysteria2Mode::Template(template),
+            ..Default::default()
+        };
+        let outbound = generate_outbound_with(&servers[0], &templated).unwrap();
+        assert_eq!(outbound["tag"], "hysteria2-server");
+        assert_eq!(outbound["settings"]["address"], "example.com");
+        assert_eq!(outbound["settings"]["port"], 443);
+        let stream = &outbound["streamSettings"];
+        assert_eq!(stream["hysteriaSettings"]["auth"], "test-password");
+        assert_eq!(stream["tlsSettings"]["allowInsecure"], false);
+        assert_eq!(stream["note"], "salamander:obfs-pass ${literal}");
+
+        assert!(parse_hysteria2_template(r#"{ "auth": "${token}" }"#).is_err());
+        assert!(parse_hysteria2_template(r#"[]"#).is_err());
+    }
+
+    #[test]
+    fn test_generate_outbounds_wireguard() {
+        let servers = vec![ServerConfig::Wireguard {
+            tag: "warp-wg".to_string(),
+            address: "engage.cloudflareclient.com".to_string(),
+            port: 2408,
+            secret_key: "c2VjcmV0".to_string(),
+            public_key: "cHVibGlj".to_string(),
+            pre_shared_key: None,
+            local_address: vec!["172.16.0.2/32".to_string()],
+            reserved: Some(vec![1, 2, 3]),
+            mtu: Some(1280),
+        }];
+
+        let config = generate_outbounds(&servers).unwrap();
+        let wireguard = &config["outbounds"][0];
+        assert_eq!(wireguard["protocol"], "wireguard");
+        assert_eq!(wireguard["settings"]["secretKey"], "c2VjcmV0");
+        assert_eq!(wireguard["settings"]["address"], json!(["172.16.0.2/32"]));
+        assert_eq!(wireguard["settings"]["reserved"], json!([1, 2, 3]));
+        assert_eq!(wireguard["settings"]["mtu"], 1280);
+        assert_eq!(
+            wireguard["settings"]["peers"][0],
+            json!({
+                "publicKey": "cHVibGlj",
+                "endpoint": "engage.cloudflareclient.com:2408"
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_outbounds_socks_and_http() {
+        let servers = vec![
+            ServerConfig::Socks {
+                tag: "socks-server".to_string(),
+                address: "1.2.3.4".to_string(),
+                port: 1080,
+                username: Some("user".to_string()),
+                password: Some("pass".to_string()),
+            },
+            ServerConfig::Http {
+                tag: "http-server".to_string(),
+                address: "5.6.7.8".to_string(),
+                port: 3128,
+                username: None,
+                password: None,
+            },
+        ];
+
+        let config = generate_outbounds(&servers).unwrap();
         let outbounds = config["outbounds"].as_array().unwrap();
 
-        let hysteria = &outbounds[0];
-        assert_eq!(hysteria["tag"], "hysteria2-server");
-        assert_eq!(hysteria["protocol"], "hysteria");
-        assert_eq!(hysteria["settings"]["auth"], "test-password");
-        assert_eq!(hysteria["settings"]["server"], "example.com");
-        assert_eq!(hysteria["settings"]["serverPort"], 443);
-        assert_eq!(hysteria["settings"]["tls"]["serverName"], "example.com");
-        assert_eq!(hysteria["settings"]["obfs"]["type"], "salamander");
-        assert_eq!(hysteria["settings"]["obfs"]["password"], "obfs-pass");
+        assert_eq!(outbounds[0]["protocol"], "socks");
+        let socks = &outbounds[0]["settings"]["servers"][0];
+        assert_eq!(socks["address"], "1.2.3.4");
+        assert_eq!(socks["port"], 1080);
+        assert_eq!(socks["users"], json!([{ "user": "user", "pass": "pass" }]));
+
+        assert_eq!(outbounds[1]["protocol"], "http");
+        let http = &outbounds[1]["settings"]["servers"][0];
+        assert_eq!(http["port"], 3128);
+        assert!(http.get("users").is_none());
+    }
+
+    #[test]
+    fn test_generate_outbounds_user_level_and_email() {
+        let servers = vec![ServerConfig::Trojan {
+            tag: "trojan-1".to_string(),
+            address: "example.com".to_string(),
+            port: 443,
+            password: "secret".to_string(),
+            network: "tcp".to_string(),
+            security: "none".to_string(),
+            tls_settings: Box::new(None),
+            network_settings: None,
+            allow_insecure: false,
+        }];
+        let options = GeneratorOptions {
+            user_level: 2,
+            user_email: true,
+            ..Default::default()
+        };
+
+        let config = generate_outbounds_with(&servers, &options).unwrap();
+        let server = &config["outbounds"][0]["settings"]["servers"][0];
+        assert_eq!(server["level"], 2);
+        assert_eq!(server["email"], "trojan-1");
+
+        let config = generate_outbounds(&servers).unwrap();
+        let server = &config["outbounds"][0]["settings"]["servers"][0];
+        assert_eq!(server["level"], 0);
+        assert!(server.get("email").is_none());
+    }
+
+    #[test]
+    fn test_generate_outbounds_dialer_options() {
+        let servers = vec![ServerConfig::Shadowsocks {
+            tag: "ss-1".to_string(),
+            address: "example.com".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }];
+        let options = GeneratorOptions {
+            dialer: DialerOptions {
+                tcp_user_timeout_ms: Some(5000),
+                happy_eyeballs_delay_ms: Some(250),
+                prefer_ipv6: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let config = generate_outbounds_with(&servers, &options).unwrap();
+        let sockopt = &config["outbounds"][0]["streamSettings"]["sockopt"];
+        assert_eq!(sockopt["tcpUserTimeout"], 5000);
+        assert_eq!(sockopt["domainStrategy"], "UseIP");
+        assert_eq!(sockopt["happyEyeballs"]["tryDelayMs"], 250);
+        assert_eq!(sockopt["happyEyeballs"]["prioritizeIPv6"], true);
+
+        // Standard outbounds and default options get no sockopt
+        assert!(config["outbounds"][1].get("streamSettings").is_none());
+        let config = generate_outbounds(&servers).unwrap();
+        assert!(config["outbounds"][0].get("streamSettings").is_none());
+    }
+
+    #[test]
+    fn test_write_outbounds_matches_generate_outbounds() {
+        let servers: Vec<ServerConfig> = (0..2500)
+            .map(|i| ServerConfig::Shadowsocks {
+                tag: format!("ss-{}", i),
+                address: "1.2.3.4".to_string(),
+                port: 8388,
+                method: "aes-256-gcm".to_string(),
+                password: "test-password".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join(format!(
+            "proxy-harvest-outbounds-{}.json",
+            std::process::id()
+        ));
+        write_outbounds(
+            &path,
+            &servers,
+            &GeneratorOptions::default(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected =
+            serde_json::to_string_pretty(&generate_outbounds(&servers).unwrap()).unwrap();
+        assert_eq!(written, expected);
+
+        let options = WriteOptions {
+            compact: true,
+            ..Default::default()
+        };
+        write_outbounds(&path, &servers, &GeneratorOptions::default(), &options).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = serde_json::to_string(&generate_outbounds(&servers).unwrap()).unwrap();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_write_outbounds_chunked() {
+        let servers: Vec<ServerConfig> = (0..5)
+            .map(|i| ServerConfig::Shadowsocks {
+                tag: format!("ss-{}", i),
+                address: "1.2.3.4".to_string(),
+                port: 8388,
+                method: "aes-256-gcm".to_string(),
+                password: "test-password".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
+            })
+            .collect();
+
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-chunks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("04_outbounds.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let read_chunks = |paths: &[PathBuf]| -> Vec<Value> {
+            paths
+                .iter()
+                .flat_map(|path| {
+                    let json: Value =
+                        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+                    json["outbounds"].as_array().unwrap().clone()
+                })
+                .collect()
+        };
+
+        let (written, _) = write_outbounds_chunked(
+            &path,
+            &servers,
+            3,
+            &GeneratorOptions::default(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let names: Vec<_> = written
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "04a_outbounds.json",
+                "04b_outbounds.json",
+                "04c_outbounds.json"
+            ]
+        );
+        assert!(!path.exists());
+        assert_eq!(
+            Value::Array(read_chunks(&written)),
+            generate_outbounds(&servers).unwrap()["outbounds"]
+        );
+
+        // Fewer chunks than last time: the extra ones are removed
+        let (written, _) = write_outbounds_chunked(
+            &path,
+            &servers,
+            10,
+            &GeneratorOptions::default(),
+            &WriteOptions::default(),
+        )
+        .unwrap();
+        let exists = [
+            dir.join("04a_outbounds.json").exists(),
+            dir.join("04b_outbounds.json").exists(),
+        ];
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written.len(), 1);
+        assert_eq!(exists, [true, false]);
+    }
+
+    #[test]
+    fn test_generate_outbounds_parallel_skips_panics() {
+        let server = |tag: &str| ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: "1.2.3.4".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        };
+        let servers = vec![server("de-1"), server("bad"), server("nl-1")];
+
+        let skipped = Mutex::new(Vec::new());
+        let outbounds = generate_outbounds_parallel_by(
+            &servers,
+            |server| {
+                assert_ne!(server.tag(), "bad", "malformed server");
+                generate_outbound_with(server, &GeneratorOptions::default())
+            },
+            &skipped,
+        );
+        let tags: Vec<&str> = outbounds
+            .iter()
+            .map(|outbound| outbound["tag"].as_str().unwrap())
+            .collect();
+        let skipped = skipped.into_inner().unwrap();
+        assert_eq!(tags, vec!["de-1", "nl-1"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].tag, "bad");
+        assert_eq!(skipped[0].panic.stage, "generate");
+        assert_eq!(skipped[0].panic.input, "ss://…@1.2.3.4:8388");
+        assert!(skipped[0].panic.message.contains("malformed server"));
+    }
+
+    #[test]
+    fn test_write_outbounds_with_hooks() {
+        let servers: Vec<ServerConfig> = (0..STREAM_CHUNK_SIZE + 1)
+            .map(|i| ServerConfig::Shadowsocks {
+                tag: format!("ss-{}", i),
+                address: "1.2.3.4".to_string(),
+                port: 8388,
+                method: "aes-256-gcm".to_string(),
+                password: "test-password".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
+            })
+            .collect();
+        let path =
+            std::env::temp_dir().join(format!("proxy-harvest-hooks-{}.json", std::process::id()));
+
+        let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = progress.clone();
+        let hooks = Hooks::default().with_progress(move |p| sink.lock().unwrap().push(p.done));
+        write_outbounds_with_hooks(
+            &path,
+            &servers,
+            &GeneratorOptions::default(),
+            &WriteOptions::default(),
+            &hooks,
+        )
+        .unwrap();
+        assert_eq!(
+            *progress.lock().unwrap(),
+            [STREAM_CHUNK_SIZE, STREAM_CHUNK_SIZE + 1]
+        );
+
+        // A cancelled run keeps the previous file
+        let before = std::fs::read_to_string(&path).unwrap();
+        hooks.cancel.cancel();
+        let err = write_outbounds_with_hooks(
+            &path,
+            &servers[..1],
+            &GeneratorOptions::default(),
+            &WriteOptions::default(),
+            &hooks,
+        )
+        .unwrap_err();
+        let after = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(err.is::<Cancelled>());
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_chunk_path_sorts_in_order() {
+        let path = Path::new("configs/04_outbounds.json");
+        assert_eq!(
+            chunk_path(path, 1, 3),
+            Path::new("configs/04b_outbounds.json")
+        );
+        assert_eq!(
+            chunk_path(path, 0, 27),
+            Path::new("configs/04aa_outbounds.json")
+        );
+        assert_eq!(
+            chunk_path(path, 26, 27),
+            Path::new("configs/04ba_outbounds.json")
+        );
     }
 }
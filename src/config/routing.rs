@@ -1,54 +1,619 @@
+use super::GeneratorOptions;
+use super::capability::{self, Target};
 use crate::parser::ServerConfig;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Deserialize;
 use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A user-supplied routing rule, e.g. sending a kid's device to the block
+/// list or a TV straight to `direct`.
+///
+/// Read from a JSON array in Xray's own field names:
+///
+/// ```json
+/// [{ "source": ["192.168.1.50"], "domain": ["geosite:category-porn"], "outboundTag": "block" },
+///  { "user": ["tv@home"], "sourcePort": "1000-2000", "outboundTag": "direct" }]
+/// ```
+///
+/// or, to share one file between hosts, as `{ "variables": {...}, "rules":
+/// [...] }` with `${NAME}` in rule strings, see [`parse_rules`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CustomRule {
+    /// Source IPs or CIDR ranges
+    #[serde(default)]
+    pub source: Vec<String>,
+    /// Inbound user emails
+    #[serde(default)]
+    pub user: Vec<String>,
+    /// Source ports, e.g. "53,443,1000-2000"
+    pub source_port: Option<String>,
+    #[serde(default)]
+    pub domain: Vec<String>,
+    pub outbound_tag: Option<String>,
+    pub balancer_tag: Option<String>,
+}
+
+impl CustomRule {
+    fn validate(&self) -> Result<()> {
+        if self.outbound_tag.is_some() == self.balancer_tag.is_some() {
+            anyhow::bail!("Rule needs exactly one of outboundTag and balancerTag");
+        }
+        if self.source.is_empty()
+            && self.user.is_empty()
+            && self.source_port.is_none()
+            && self.domain.is_empty()
+        {
+            anyhow::bail!("Rule matches nothing: set source, user, sourcePort or domain");
+        }
+
+        for source in &self.source {
+            let (ip, prefix) = match source.split_once('/') {
+                Some((ip, prefix)) => (ip, Some(prefix)),
+                None => (source.as_str(), None),
+            };
+            let ip: IpAddr = ip
+                .parse()
+                .with_context(|| format!("Invalid source address: {}", source))?;
+            let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+            if let Some(prefix) = prefix
+                && !prefix.parse::<u8>().is_ok_and(|p| p <= max_prefix)
+            {
+                anyhow::bail!("Invalid source prefix: {}", source);
+            }
+        }
+
+        if let Some(ports) = &self.source_port {
+            for part in ports.split(',') {
+                let valid = match part.trim().split_once('-') {
+                    Some((from, to)) => matches!(
+                        (from.parse::<u16>(), to.parse::<u16>()),
+                        (Ok(from), Ok(to)) if from <= to
+                    ),
+                    None => part.trim().parse::<u16>().is_ok(),
+                };
+                if !valid {
+                    anyhow::bail!("Invalid sourcePort: {}", ports);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        let mut rule = json!({
+            "type": "field",
+            "inboundTag": ["redirect", "tproxy"]
+        });
+        if !self.source.is_empty() {
+            rule["source"] = json!(self.source);
+        }
+        if !self.user.is_empty() {
+            rule["user"] = json!(self.user);
+        }
+        if let Some(ports) = &self.source_port {
+            rule["sourcePort"] = json!(ports);
+        }
+        if !self.domain.is_empty() {
+            rule["domain"] = json!(self.domain);
+        }
+        if let Some(tag) = &self.outbound_tag {
+            rule["outboundTag"] = json!(tag);
+        }
+        if let Some(tag) = &self.balancer_tag {
+            rule["balancerTag"] = json!(tag);
+        }
+        rule
+    }
+}
+
+/// Which balancer a server is routed through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    Cloudflare,
+    Warp,
+    Proxy,
+}
+
+impl Category {
+    pub fn of(server: &ServerConfig, options: &GeneratorOptions) -> Self {
+        if server.is_warp() {
+            Category::Warp
+        } else if server.is_cloudflare() || options.cdn_tags.contains(server.tag()) {
+            Category::Cloudflare
+        } else {
+            Category::Proxy
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Cloudflare => "cloudflare",
+            Category::Warp => "warp",
+            Category::Proxy => "proxy",
+        }
+    }
+
+    pub fn balancer_tag(self) -> &'static str {
+        match self {
+            Category::Cloudflare => "claude-balance",
+            Category::Warp => "warp-balance",
+            Category::Proxy => "proxy-balance",
+        }
+    }
+
+    pub const ALL: &'static [Category] = &[Category::Cloudflare, Category::Warp, Category::Proxy];
+}
+
+impl std::str::FromStr for Category {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Category::ALL
+            .iter()
+            .copied()
+            .find(|category| category.as_str() == value)
+            .with_context(|| {
+                format!(
+                    "Unknown category {}, expected cloudflare, warp or proxy",
+                    value
+                )
+            })
+    }
+}
+
+/// The tag prefix of one category's servers with `--exact-selectors`,
+/// parsed from `CATEGORY=PREFIX`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorPrefix {
+    pub category: Category,
+    pub prefix: String,
+}
+
+impl std::str::FromStr for SelectorPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (category, prefix) = value.split_once('=').with_context(|| {
+            format!(
+                "Invalid selector prefix {}, expected CATEGORY=PREFIX",
+                value
+            )
+        })?;
+        Ok(Self {
+            category: category.parse()?,
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+/// Tag prefixes that keep each balancer's selector, which Xray matches by
+/// prefix, from picking up servers of another balancer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorPrefixes {
+    cloudflare: String,
+    warp: String,
+    proxy: String,
+}
+
+impl Default for SelectorPrefixes {
+    fn default() -> Self {
+        Self {
+            cloudflare: "cf".to_string(),
+            warp: "warp".to_string(),
+            proxy: "px".to_string(),
+        }
+    }
+}
+
+impl SelectorPrefixes {
+    /// The default prefixes with `overrides` applied, checked so that no
+    /// prefix is a prefix of another.
+    pub fn with(overrides: &[SelectorPrefix]) -> Result<Self> {
+        let mut prefixes = Self::default();
+        for SelectorPrefix { category, prefix } in overrides {
+            *prefixes.get_mut(*category) = prefix.clone();
+        }
+
+        for &category in Category::ALL {
+            let prefix = prefixes.get(category);
+            anyhow::ensure!(
+                !prefix.is_empty(),
+                "Selector prefix of {} is empty",
+                category.as_str()
+            );
+            // Tags containing "warp" are routed as WARP servers
+            anyhow::ensure!(
+                category == Category::Warp || !prefix.to_lowercase().contains("warp"),
+                "Selector prefix {} of {} would make its servers WARP servers",
+                prefix,
+                category.as_str()
+            );
+            for &other in Category::ALL.iter().filter(|&&other| other != category) {
+                anyhow::ensure!(
+                    !prefixes.get(other).starts_with(prefix),
+                    "Selector prefixes {} ({}) and {} ({}) overlap",
+                    prefix,
+                    category.as_str(),
+                    prefixes.get(other),
+                    other.as_str()
+                );
+            }
+        }
+
+        Ok(prefixes)
+    }
+
+    pub fn get(&self, category: Category) -> &str {
+        match category {
+            Category::Cloudflare => &self.cloudflare,
+            Category::Warp => &self.warp,
+            Category::Proxy => &self.proxy,
+        }
+    }
+
+    fn get_mut(&mut self, category: Category) -> &mut String {
+        match category {
+            Category::Cloudflare => &mut self.cloudflare,
+            Category::Warp => &mut self.warp,
+            Category::Proxy => &mut self.proxy,
+        }
+    }
+}
+
+/// Renames servers to `{tag_prefix}{category prefix}{N}-{name}`, numbering
+/// each category from 1 with zero-padded numbers of equal width. No tag is
+/// then a prefix of another (`de-1` of `de-10`, say), so the exact tag lists
+/// in balancer selectors only match their own servers.
+///
+/// `options.cdn_tags` follows the renames. Returns the previous tag of every
+/// server, keyed by its new tag.
+pub fn apply_selector_prefixes(
+    servers: &mut [ServerConfig],
+    options: &mut GeneratorOptions,
+    prefixes: &SelectorPrefixes,
+    tag_prefix: &str,
+) -> Result<HashMap<String, String>> {
+    let categories: Vec<Category> = servers
+        .iter()
+        .map(|server| Category::of(server, options))
+        .collect();
+    let mut counts: HashMap<Category, usize> = HashMap::new();
+    for category in &categories {
+        *counts.entry(*category).or_default() += 1;
+    }
+
+    let mut numbers: HashMap<Category, usize> = HashMap::new();
+    let mut renamed = HashMap::new();
+    for (server, category) in servers.iter_mut().zip(categories) {
+        let number = numbers.entry(category).or_default();
+        *number += 1;
+        let width = counts[&category].to_string().len();
+
+        let old_tag = server.tag().to_string();
+        let name = old_tag.strip_prefix(tag_prefix).unwrap_or(&old_tag);
+        let new_tag = format!(
+            "{}{}{:0width$}-{}",
+            tag_prefix,
+            prefixes.get(category),
+            number,
+            name
+        );
+        server.set_tag(new_tag.clone());
+
+        if options.cdn_tags.remove(&old_tag) {
+            options.cdn_tags.insert(new_tag.clone());
+        }
+        anyhow::ensure!(
+            Category::of(server, options) == category,
+            "Renaming {} to {} would move it out of {}",
+            old_tag,
+            new_tag,
+            category.balancer_tag()
+        );
+        renamed.insert(new_tag, old_tag);
+    }
+
+    Ok(renamed)
+}
+
+/// How a balancer picks among its outbounds (Xray `strategy.type`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BalancerStrategy {
+    /// Lowest observed latency
+    #[default]
+    #[value(name = "leastping")]
+    LeastPing,
+    /// Most stable among the fastest, tuned by the strategy settings
+    #[value(name = "leastload")]
+    LeastLoad,
+    Random,
+    #[value(name = "roundrobin")]
+    RoundRobin,
+}
+
+impl BalancerStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BalancerStrategy::LeastPing => "leastping",
+            BalancerStrategy::LeastLoad => "leastload",
+            BalancerStrategy::Random => "random",
+            BalancerStrategy::RoundRobin => "roundrobin",
+        }
+    }
+}
+
+/// Per-outbound weight for leastload, matched against outbound tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalancerCost {
+    pub regexp: bool,
+    pub pattern: String,
+    pub value: f64,
+}
+
+impl std::str::FromStr for BalancerCost {
+    type Err = anyhow::Error;
+
+    /// Parses `TAG=VALUE`, or `regexp:PATTERN=VALUE` to match tags by regex.
+    fn from_str(value: &str) -> Result<Self> {
+        let (pattern, cost) = value
+            .rsplit_once('=')
+            .with_context(|| format!("Invalid balancer cost {}, expected TAG=VALUE", value))?;
+        let cost: f64 = cost
+            .parse()
+            .with_context(|| format!("Invalid balancer cost value: {}", cost))?;
+        let (regexp, pattern) = match pattern.strip_prefix("regexp:") {
+            Some(pattern) => {
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid balancer cost regexp: {}", pattern))?;
+                (true, pattern)
+            }
+            None => (false, pattern),
+        };
+        Ok(Self {
+            regexp,
+            pattern: pattern.to_string(),
+            value: cost,
+        })
+    }
+}
+
+/// Strategy of the generated balancers, with Xray's strategy settings.
+/// The settings are read by `leastload`; other strategies ignore them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StrategyOptions {
+    pub strategy: BalancerStrategy,
+    /// How many of the best outbounds to spread load across
+    pub expected: Option<u32>,
+    /// Outbounds slower than this (e.g. "1s") aren't picked
+    pub max_rtt: Option<String>,
+    /// Tolerated share of failed health checks, 0 to 1
+    pub tolerance: Option<f64>,
+    /// Latency tiers (e.g. "300ms", "1s") used to group outbounds
+    pub baselines: Vec<String>,
+    pub costs: Vec<BalancerCost>,
+}
+
+impl StrategyOptions {
+    fn has_settings(&self) -> bool {
+        self.expected.is_some()
+            || self.max_rtt.is_some()
+            || self.tolerance.is_some()
+            || !self.baselines.is_empty()
+            || !self.costs.is_empty()
+    }
+
+    fn validate(&self) -> Result<()> {
+        let duration = Regex::new(r"^(\d+(\.\d+)?(ns|us|µs|ms|s|m|h))+$").unwrap();
+        for value in self.max_rtt.iter().chain(&self.baselines) {
+            anyhow::ensure!(
+                duration.is_match(value),
+                "Invalid duration {}, expected e.g. 500ms or 1s",
+                value
+            );
+        }
+        if let Some(tolerance) = self.tolerance {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&tolerance),
+                "Balancer tolerance must be between 0 and 1, got {}",
+                tolerance
+            );
+        }
+        if self.has_settings() && self.strategy != BalancerStrategy::LeastLoad {
+            log::warn!(
+                "Balancer strategy settings are only used by leastload, not {}",
+                self.strategy.as_str()
+            );
+        }
+        Ok(())
+    }
+
+    /// The balancer `strategy` object.
+    fn to_json(&self) -> Value {
+        let mut strategy = json!({ "type": self.strategy.as_str() });
+        if !self.has_settings() {
+            return strategy;
+        }
+
+        let mut settings = serde_json::Map::new();
+        if let Some(expected) = self.expected {
+            settings.insert("expected".into(), expected.into());
+        }
+        if let Some(max_rtt) = &self.max_rtt {
+            settings.insert("maxRTT".into(), max_rtt.as_str().into());
+        }
+        if let Some(tolerance) = self.tolerance {
+            settings.insert("tolerance".into(), tolerance.into());
+        }
+        if !self.baselines.is_empty() {
+            settings.insert("baselines".into(), json!(self.baselines));
+        }
+        if !self.costs.is_empty() {
+            let costs: Vec<Value> = self
+                .costs
+                .iter()
+                .map(|cost| {
+                    json!({
+                        "regexp": cost.regexp,
+                        "match": cost.pattern,
+                        "value": cost.value
+                    })
+                })
+                .collect();
+            settings.insert("costs".into(), costs.into());
+        }
+        strategy["settings"] = Value::Object(settings);
+        strategy
+    }
+}
+
+/// Loads and validates custom rules from a JSON array.
+pub fn load_rules(path: &Path) -> Result<Vec<CustomRule>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read rules {}", path.display()))?;
+    parse_rules(&content, |name| std::env::var(name).ok())
+        .with_context(|| format!("Invalid rules {}", path.display()))
+}
+
+/// A rules file: a plain list, or one with variables for its rules.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RulesFile {
+    Rules(Vec<Value>),
+    WithVariables {
+        #[serde(default)]
+        variables: HashMap<String, String>,
+        rules: Vec<Value>,
+    },
+}
+
+/// Parses and validates rules, replacing `${NAME}` in their strings with
+/// the environment variable of that name, or else the file's own
+/// `variables` entry, so the file holds defaults each host can override.
+/// `$${` is a literal `${`.
+pub fn parse_rules(content: &str, env: impl Fn(&str) -> Option<String>) -> Result<Vec<CustomRule>> {
+    let (variables, rules) = match serde_json::from_str(content)? {
+        RulesFile::Rules(rules) => (HashMap::new(), rules),
+        RulesFile::WithVariables { variables, rules } => (variables, rules),
+    };
+    let lookup = |name: &str| env(name).or_else(|| variables.get(name).cloned());
+
+    rules
+        .into_iter()
+        .enumerate()
+        .map(|(idx, mut rule)| {
+            let parse = || -> Result<CustomRule> {
+                substitute(&mut rule, &lookup)?;
+                let rule: CustomRule = serde_json::from_value(rule)?;
+                rule.validate()?;
+                Ok(rule)
+            };
+            parse().with_context(|| format!("Rule {}", idx + 1))
+        })
+        .collect()
+}
+
+/// Expands `${NAME}` in every string of `value`.
+fn substitute(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    match value {
+        Value::String(text) => *text = expand(text, lookup)?,
+        Value::Array(items) => {
+            for item in items {
+                substitute(item, lookup)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                substitute(item, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub(super) fn expand(text: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(name_start) = after.strip_prefix("${") {
+            let (name, tail) = name_start
+                .split_once('}')
+                .with_context(|| format!("Unclosed ${{ in {}", text))?;
+            let value = lookup(name).with_context(|| format!("Undefined variable {}", name))?;
+            expanded.push_str(&value);
+            rest = tail;
+        } else {
+            expanded.push('$');
+            rest = &after[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
 pub fn generate_routing(servers: &[ServerConfig]) -> Result<Value> {
+    generate_routing_with(servers, &GeneratorOptions::default())
+}
+
+pub fn generate_routing_with(
+    servers: &[ServerConfig],
+    options: &GeneratorOptions,
+) -> Result<Value> {
     // Separate servers into different categories
     let mut warp_servers = Vec::new();
     let mut cloudflare_servers = Vec::new();
     let mut proxy_servers = Vec::new();
 
-    for server in servers {
+    for server in servers
+        .iter()
+        .filter(|s| capability::is_supported_with(Target::Xray, s, options))
+    {
         let tag = server.tag().to_string();
-        if server.is_warp() {
-            warp_servers.push(tag);
-        } else if server.is_cloudflare() {
-            cloudflare_servers.push(tag);
-        } else {
-            proxy_servers.push(tag);
+        match Category::of(server, options) {
+            Category::Warp => warp_servers.push(tag),
+            Category::Cloudflare => cloudflare_servers.push(tag),
+            Category::Proxy => proxy_servers.push(tag),
         }
     }
 
     // Create balancers
+    options.balancer.validate()?;
+    let strategy = options.balancer.to_json();
     let mut balancers = Vec::new();
 
     if !cloudflare_servers.is_empty() {
         balancers.push(json!({
-            "tag": "claude-balance",
+            "tag": Category::Cloudflare.balancer_tag(),
             "selector": cloudflare_servers,
-            "strategy": {
-                "type": "leastping"
-            }
+            "strategy": strategy
         }));
     }
 
     if !warp_servers.is_empty() {
         balancers.push(json!({
-            "tag": "warp-balance",
+            "tag": Category::Warp.balancer_tag(),
             "selector": warp_servers,
-            "strategy": {
-                "type": "leastping"
-            }
+            "strategy": strategy
         }));
     }
 
     if !proxy_servers.is_empty() {
         balancers.push(json!({
-            "tag": "proxy-balance",
+            "tag": Category::Proxy.balancer_tag(),
             "selector": proxy_servers,
-            "strategy": {
-                "type": "leastping"
-            }
+            "strategy": strategy
         }));
     }
 
@@ -93,12 +658,22 @@ pub fn generate_routing(servers: &[ServerConfig]) -> Result<Value> {
 
     let mut routing_rules = rules;
 
+    // User rules go ahead of the balancers so they can override them
+    for rule in &options.custom_rules {
+        if let Some(balancer) = &rule.balancer_tag
+            && !balancers.iter().any(|b| b["tag"] == balancer.as_str())
+        {
+            anyhow::bail!("Rule references unknown balancer {}", balancer);
+        }
+        routing_rules.push(rule.to_json());
+    }
+
     // Add balancer rules
     if !cloudflare_servers.is_empty() {
         routing_rules.push(json!({
             "type": "field",
             "inboundTag": ["redirect", "tproxy"],
-            "balancerTag": "claude-balance",
+            "balancerTag": Category::Cloudflare.balancer_tag(),
             "domain": []
         }));
     }
@@ -107,7 +682,7 @@ pub fn generate_routing(servers: &[ServerConfig]) -> Result<Value> {
         routing_rules.push(json!({
             "type": "field",
             "inboundTag": ["redirect", "tproxy"],
-            "balancerTag": "warp-balance",
+            "balancerTag": Category::Warp.balancer_tag(),
             "domain": []
         }));
     }
@@ -116,7 +691,7 @@ pub fn generate_routing(servers: &[ServerConfig]) -> Result<Value> {
         routing_rules.push(json!({
             "type": "field",
             "inboundTag": ["redirect", "tproxy"],
-            "balancerTag": "proxy-balance",
+            "balancerTag": Category::Proxy.balancer_tag(),
             "domain": []
         }));
     }
@@ -187,6 +762,8 @@ mod tests {
                 port: 8388,
                 method: "aes-256-gcm".to_string(),
                 password: "test".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
             },
             ServerConfig::Shadowsocks {
                 tag: "normal-server".to_string(),
@@ -194,6 +771,8 @@ mod tests {
                 port: 8388,
                 method: "aes-256-gcm".to_string(),
                 password: "test".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
             },
         ];
 
@@ -215,6 +794,256 @@ mod tests {
         assert_eq!(warp_balance["selector"][0], "warp-test");
     }
 
+    #[test]
+    fn test_generate_routing_with_custom_rules() {
+        let rules: Vec<CustomRule> = serde_json::from_value(json!([
+            { "source": ["192.168.1.50", "10.0.0.0/24"], "outboundTag": "block" },
+            { "user": ["tv@home"], "sourcePort": "1000-2000,443", "outboundTag": "direct" }
+        ]))
+        .unwrap();
+        assert!(rules.iter().all(|r| r.validate().is_ok()));
+
+        let options = GeneratorOptions {
+            custom_rules: rules,
+            ..Default::default()
+        };
+        let config = generate_routing_with(&[], &options).unwrap();
+        let routing_rules = config["routing"]["rules"].as_array().unwrap();
+
+        let source_rule = routing_rules
+            .iter()
+            .find(|r| r.get("source").is_some())
+            .unwrap();
+        assert_eq!(source_rule["outboundTag"], "block");
+        assert_eq!(source_rule["source"][1], "10.0.0.0/24");
+
+        let user_rule = routing_rules
+            .iter()
+            .find(|r| r.get("user").is_some())
+            .unwrap();
+        assert_eq!(user_rule["sourcePort"], "1000-2000,443");
+        assert_eq!(user_rule["outboundTag"], "direct");
+    }
+
+    #[test]
+    fn test_generate_routing_with_cdn_tags() {
+        let servers = vec![ServerConfig::Shadowsocks {
+            tag: "ss-edge.cdn.example".to_string(),
+            address: "203.0.113.1".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }];
+
+        let config = generate_routing(&servers).unwrap();
+        assert_eq!(config["routing"]["balancers"][0]["tag"], "proxy-balance");
+
+        let options = GeneratorOptions {
+            cdn_tags: ["ss-edge.cdn.example".to_string()].into(),
+            ..Default::default()
+        };
+        let config = generate_routing_with(&servers, &options).unwrap();
+        assert_eq!(config["routing"]["balancers"][0]["tag"], "claude-balance");
+    }
+
+    #[test]
+    fn test_generate_routing_with_balancer_strategy() {
+        let servers = vec![ServerConfig::Shadowsocks {
+            tag: "ss-1".to_string(),
+            address: "203.0.113.1".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }];
+
+        let config = generate_routing(&servers).unwrap();
+        assert_eq!(
+            config["routing"]["balancers"][0]["strategy"],
+            json!({ "type": "leastping" })
+        );
+
+        let options = GeneratorOptions {
+            balancer: StrategyOptions {
+                strategy: BalancerStrategy::LeastLoad,
+                expected: Some(2),
+                max_rtt: Some("1s".to_string()),
+                baselines: vec!["300ms".to_string(), "1s".to_string()],
+                costs: vec![
+                    "regexp:^ss-=0.5".parse().unwrap(),
+                    "ss-1=2".parse().unwrap(),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let config = generate_routing_with(&servers, &options).unwrap();
+        assert_eq!(
+            config["routing"]["balancers"][0]["strategy"],
+            json!({
+                "type": "leastload",
+                "settings": {
+                    "expected": 2,
+                    "maxRTT": "1s",
+                    "baselines": ["300ms", "1s"],
+                    "costs": [
+                        { "regexp": true, "match": "^ss-", "value": 0.5 },
+                        { "regexp": false, "match": "ss-1", "value": 2.0 }
+                    ]
+                }
+            })
+        );
+
+        assert!("ss-1".parse::<BalancerCost>().is_err());
+        assert!("regexp:(=1".parse::<BalancerCost>().is_err());
+        let options = GeneratorOptions {
+            balancer: StrategyOptions {
+                max_rtt: Some("1 second".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(generate_routing_with(&servers, &options).is_err());
+    }
+
+    #[test]
+    fn test_selector_prefixes() {
+        let prefixes = SelectorPrefixes::with(&["proxy=eu-".parse().unwrap()]).unwrap();
+        assert_eq!(prefixes.get(Category::Proxy), "eu-");
+        assert_eq!(prefixes.get(Category::Cloudflare), "cf");
+
+        for overrides in [
+            vec!["proxy=c"],
+            vec!["warp=cf"],
+            vec!["cloudflare=px-", "proxy=px"],
+            vec!["proxy="],
+            vec!["proxy=no-warp-"],
+        ] {
+            let overrides: Vec<SelectorPrefix> =
+                overrides.iter().map(|o| o.parse().unwrap()).collect();
+            assert!(
+                SelectorPrefixes::with(&overrides).is_err(),
+                "{:?} should be rejected",
+                overrides
+            );
+        }
+
+        assert!("proxy".parse::<SelectorPrefix>().is_err());
+        assert!("cdn=c".parse::<SelectorPrefix>().is_err());
+    }
+
+    #[test]
+    fn test_apply_selector_prefixes() {
+        let ss = |tag: &str, address: &str| ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: address.to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "test".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        };
+        let mut servers: Vec<ServerConfig> = (1..=10)
+            .map(|n| ss(&format!("h-de-{}", n), "5.6.7.8"))
+            .chain([ss("h-warp-1", "1.1.1.1"), ss("h-cdn", "9.9.9.9")])
+            .collect();
+        let mut options = GeneratorOptions {
+            cdn_tags: ["h-cdn".to_string()].into(),
+            ..Default::default()
+        };
+
+        let renamed = apply_selector_prefixes(
+            &mut servers,
+            &mut options,
+            &SelectorPrefixes::default(),
+            "h-",
+        )
+        .unwrap();
+
+        let tags: Vec<&str> = servers.iter().map(|s| s.tag()).collect();
+        assert_eq!(tags[0], "h-px01-de-1");
+        assert_eq!(tags[9], "h-px10-de-10");
+        assert_eq!(tags[10], "h-warp1-warp-1");
+        assert_eq!(tags[11], "h-cf1-cdn");
+        assert_eq!(renamed["h-px01-de-1"], "h-de-1");
+        assert!(options.cdn_tags.contains("h-cf1-cdn"));
+
+        for tag in &tags {
+            assert!(
+                !tags
+                    .iter()
+                    .any(|other| other != tag && other.starts_with(tag))
+            );
+        }
+
+        let routing = generate_routing_with(&servers, &options).unwrap();
+        let outbounds = tags
+            .iter()
+            .map(|tag| tag.to_string())
+            .chain(["direct".to_string(), "block".to_string()])
+            .collect();
+        let selector_findings: Vec<_> = crate::config::lint::lint_routing(&routing, &outbounds)
+            .into_iter()
+            .filter(|finding| finding.message.contains("selector"))
+            .collect();
+        assert_eq!(selector_findings, vec![]);
+    }
+
+    #[test]
+    fn test_custom_rule_validation() {
+        let invalid = [
+            json!({ "source": ["192.168.1.300"], "outboundTag": "block" }),
+            json!({ "source": ["10.0.0.0/33"], "outboundTag": "block" }),
+            json!({ "sourcePort": "2000-1000", "outboundTag": "direct" }),
+            json!({ "user": ["a@b"] }),
+            json!({ "outboundTag": "direct" }),
+        ];
+        for rule in invalid {
+            let rule: CustomRule = serde_json::from_value(rule).unwrap();
+            assert!(rule.validate().is_err(), "{:?} should be invalid", rule);
+        }
+
+        let options = GeneratorOptions {
+            custom_rules: vec![CustomRule {
+                user: vec!["a@b".to_string()],
+                balancer_tag: Some("warp-balance".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(generate_routing_with(&[], &options).is_err());
+    }
+
+    #[test]
+    fn test_parse_rules_with_variables() {
+        let content = r#"{
+            "variables": { "TV": "192.168.1.50", "PORTS": "1000-2000" },
+            "rules": [
+                { "source": ["${TV}"], "sourcePort": "${PORTS}", "outboundTag": "direct" },
+                { "user": ["$${literal}@${HOST}"], "outboundTag": "block" }
+            ]
+        }"#;
+        let env = |name: &str| (name == "HOST" || name == "PORTS").then(|| "10".to_string());
+
+        let rules = parse_rules(content, env).unwrap();
+        assert_eq!(rules[0].source, ["192.168.1.50"]);
+        // The environment wins over the file's defaults
+        assert_eq!(rules[0].source_port.as_deref(), Some("10"));
+        assert_eq!(rules[1].user, ["${literal}@10"]);
+
+        let err = parse_rules(content, |_| None).unwrap_err();
+        assert!(format!("{:#}", err).contains("Undefined variable HOST"));
+
+        // Plain lists still work, and substituted values are validated
+        let plain = r#"[{ "source": ["${TV}"], "outboundTag": "direct" }]"#;
+        let rules = parse_rules(plain, |_| Some("10.0.0.1".to_string())).unwrap();
+        assert_eq!(rules[0].source, ["10.0.0.1"]);
+        assert!(parse_rules(plain, |_| Some("not-an-ip".to_string())).is_err());
+    }
+
     #[test]
     fn test_generate_routing_with_cloudflare_servers() {
         let servers = vec![ServerConfig::Vless {
@@ -267,7 +1096,7 @@ mod tests {
         // Check ads blocking rule
         let ads_rule = &rules[2];
         assert_eq!(ads_rule["outboundTag"], "block");
-        assert!(ads_rule["domain"].as_array().unwrap().len() > 0);
+        assert!(!ads_rule["domain"].as_array().unwrap().is_empty());
 
         // Check local IPs rule
         let local_rule = rules
@@ -289,6 +1118,8 @@ mod tests {
             port: 8388,
             method: "aes-256-gcm".to_string(),
             password: "test".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
         }];
 
         let result = generate_routing(&proxy_servers).unwrap();
@@ -331,6 +1162,8 @@ mod tests {
                 port: 8388,
                 method: "aes-256-gcm".to_string(),
                 password: "test".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
             },
             ServerConfig::Vless {
                 tag: "cf-1".to_string(),
@@ -350,6 +1183,8 @@ mod tests {
                 port: 8388,
                 method: "aes-256-gcm".to_string(),
                 password: "test".to_string(),
+                udp_over_tcp: false,
+                plugin: None,
             },
         ];
 
@@ -0,0 +1,170 @@
+use super::{WriteOptions, write_config_file};
+use crate::parser::{ServerConfig, format_host};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Longest interface name Linux accepts (`IFNAMSIZ` - 1); wg-quick names the
+/// interface after the file.
+const MAX_INTERFACE_NAME: usize = 15;
+
+/// Renders a WireGuard server as a wg-quick config that sends all traffic
+/// through it, or None for other protocols.
+///
+/// `reserved` bytes (WARP's client id) have no wg-quick equivalent and are
+/// left out; kernel WireGuard connects to WARP without them.
+pub fn wg_quick_conf(server: &ServerConfig) -> Option<String> {
+    let ServerConfig::Wireguard {
+        address,
+        port,
+        secret_key,
+        public_key,
+        pre_shared_key,
+        local_address,
+        mtu,
+        ..
+    } = server
+    else {
+        return None;
+    };
+
+    let mut conf = format!("[Interface]\nPrivateKey = {}\n", secret_key);
+    if !local_address.is_empty() {
+        conf.push_str(&format!("Address = {}\n", local_address.join(", ")));
+    }
+    if let Some(mtu) = mtu {
+        conf.push_str(&format!("MTU = {}\n", mtu));
+    }
+
+    conf.push_str(&format!("\n[Peer]\nPublicKey = {}\n", public_key));
+    if let Some(pre_shared_key) = pre_shared_key {
+        conf.push_str(&format!("PresharedKey = {}\n", pre_shared_key));
+    }
+    conf.push_str("AllowedIPs = 0.0.0.0/0, ::/0\n");
+    conf.push_str(&format!("Endpoint = {}:{}\n", format_host(address), port));
+    Some(conf)
+}
+
+/// Writes a wg-quick config for every WireGuard server into `dir`, named
+/// after its tag cut down to a valid interface name. Files are readable by
+/// the owner only unless `options` sets a mode, since they hold private keys.
+/// Returns the paths written.
+pub fn write_wg_quick(
+    dir: &Path,
+    servers: &[ServerConfig],
+    options: &WriteOptions,
+) -> Result<Vec<PathBuf>> {
+    let options = WriteOptions {
+        file_mode: Some(options.file_mode.unwrap_or(0o600)),
+        ..*options
+    };
+
+    let mut names = HashSet::new();
+    let mut written = Vec::new();
+    for server in servers {
+        let Some(conf) = wg_quick_conf(server) else {
+            continue;
+        };
+        let name = interface_name(server.tag(), &names);
+        let path = dir.join(format!("{}.conf", name));
+        names.insert(name);
+
+        write_config_file(&path, &options, |file| Ok(file.write_all(conf.as_bytes())?))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// `tag` as an interface name: characters wg-quick rejects become `-`, and
+/// it is cut to 15 bytes, with a number appended if that clashes with one of
+/// `taken`.
+fn interface_name(tag: &str, taken: &HashSet<String>) -> String {
+    let name: String = tag
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_=+.-".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = if name.is_empty() {
+        "wg".to_string()
+    } else {
+        name
+    };
+
+    let mut candidate = name.chars().take(MAX_INTERFACE_NAME).collect::<String>();
+    let mut n = 2;
+    while taken.contains(&candidate) {
+        let suffix = format!("-{}", n);
+        let prefix: String = name
+            .chars()
+            .take(MAX_INTERFACE_NAME - suffix.len())
+            .collect();
+        candidate = format!("{}{}", prefix, suffix);
+        n += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warp(tag: &str) -> ServerConfig {
+        ServerConfig::Wireguard {
+            tag: tag.to_string(),
+            address: "2606:4700:d0::a29f:c001".to_string(),
+            port: 2408,
+            secret_key: "cHJpdmF0ZQ==".to_string(),
+            public_key: "cHVibGlj".to_string(),
+            pre_shared_key: None,
+            local_address: vec!["172.16.0.2/32".to_string(), "fd01::2/128".to_string()],
+            reserved: Some(vec![1, 2, 3]),
+            mtu: Some(1280),
+        }
+    }
+
+    #[test]
+    fn test_wg_quick_conf() {
+        assert_eq!(
+            wg_quick_conf(&warp("warp-1")).unwrap(),
+            "[Interface]\n\
+             PrivateKey = cHJpdmF0ZQ==\n\
+             Address = 172.16.0.2/32, fd01::2/128\n\
+             MTU = 1280\n\
+             \n\
+             [Peer]\n\
+             PublicKey = cHVibGlj\n\
+             AllowedIPs = 0.0.0.0/0, ::/0\n\
+             Endpoint = [2606:4700:d0::a29f:c001]:2408\n"
+        );
+
+        let shadowsocks = ServerConfig::Shadowsocks {
+            tag: "de-1".to_string(),
+            address: "203.0.113.1".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "secret".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        };
+        assert_eq!(wg_quick_conf(&shadowsocks), None);
+    }
+
+    #[test]
+    fn test_interface_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(interface_name("warp 🇩🇪 #1", &taken), "warp-----1");
+
+        let name = interface_name("cloudflare-warp-frankfurt", &taken);
+        assert_eq!(name, "cloudflare-warp");
+        taken.insert(name);
+        assert_eq!(
+            interface_name("cloudflare-warp-amsterdam", &taken),
+            "cloudflare-wa-2"
+        );
+    }
+}
@@ -0,0 +1,852 @@
+use crate::parser::{
+    NetworkSettings, ServerConfig, ShadowsocksPlugin, TlsSettings, format_host, normalize_network,
+    split_host_port,
+};
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use serde_json::{Value, json};
+use std::io::Write;
+use urlencoding::encode;
+
+/// Reads servers back from Xray outbounds.
+///
+/// Accepts either a full Xray config or an outbounds file (`{"outbounds": [...]}`).
+/// Outbounds that aren't proxy servers (freedom, blackhole, ...) are skipped.
+pub fn servers_from_xray(config: &Value) -> Result<Vec<ServerConfig>> {
+    let outbounds = config["outbounds"]
+        .as_array()
+        .context("Xray config has no outbounds array")?;
+
+    let mut servers = Vec::new();
+    for outbound in outbounds {
+        let tag = outbound["tag"].as_str().unwrap_or_default();
+        match outbound_to_server(outbound) {
+            Ok(Some(server)) => servers.push(server),
+            Ok(None) => log::debug!("Skipping non-proxy outbound {}", tag),
+            Err(e) => log::warn!("Failed to convert outbound {}: {}", tag, e),
+        }
+    }
+
+    Ok(servers)
+}
+
+/// Converts a single Xray outbound into a server, or None for non-proxy outbounds.
+pub fn outbound_to_server(outbound: &Value) -> Result<Option<ServerConfig>> {
+    let tag = outbound["tag"].as_str().unwrap_or_default().to_string();
+    let settings = &outbound["settings"];
+
+    let server = match outbound["protocol"].as_str().unwrap_or_default() {
+        "shadowsocks" => {
+            let server = &settings["servers"][0];
+            ServerConfig::Shadowsocks {
+                tag,
+                address: str_field(server, "address")?,
+                port: port_field(server, "port")?,
+                method: str_field(server, "method")?,
+                password: str_field(server, "password")?,
+                udp_over_tcp: server["uot"].as_bool().unwrap_or(false),
+                plugin: stream_plugin(&outbound["streamSettings"]),
+            }
+        }
+        "vless" => {
+            let vnext = &settings["vnext"][0];
+            let user = &vnext["users"][0];
+            let stream = &outbound["streamSettings"];
+            let security = stream["security"].as_str().unwrap_or("none").to_string();
+            ServerConfig::Vless {
+                tag,
+                address: str_field(vnext, "address")?,
+                port: port_field(vnext, "port")?,
+                id: str_field(user, "id")?,
+                encryption: user["encryption"].as_str().unwrap_or("none").to_string(),
+                flow: user["flow"].as_str().unwrap_or_default().to_string(),
+                network: normalize_network(stream["network"].as_str().unwrap_or("tcp")),
+                tls_settings: Box::new(stream_tls_settings(stream, &security)),
+                security,
+                network_settings: stream_network_settings(stream),
+            }
+        }
+        "vmess" => {
+            let vnext = &settings["vnext"][0];
+            let user = &vnext["users"][0];
+            let stream = &outbound["streamSettings"];
+            let security = stream["security"].as_str().unwrap_or("none");
+            let tls_settings = stream_tls_settings(stream, security);
+            ServerConfig::Vmess {
+                tag,
+                address: str_field(vnext, "address")?,
+                port: port_field(vnext, "port")?,
+                id: str_field(user, "id")?,
+                alter_id: user["alterId"].as_u64().unwrap_or(0) as u16,
+                security: user["security"].as_str().unwrap_or("auto").to_string(),
+                network: normalize_network(stream["network"].as_str().unwrap_or("tcp")),
+                network_settings: stream_network_settings(stream),
+                allow_insecure: tls_settings
+                    .as_ref()
+                    .map(|t| t.allow_insecure)
+                    .unwrap_or(false),
+                tls_settings: Box::new(tls_settings),
+            }
+        }
+        "trojan" => {
+            let server = &settings["servers"][0];
+            let stream = &outbound["streamSettings"];
+            let security = stream["security"].as_str().unwrap_or("tls").to_string();
+            let tls_settings = stream_tls_settings(stream, &security);
+            ServerConfig::Trojan {
+                tag,
+                address: str_field(server, "address")?,
+                port: port_field(server, "port")?,
+                password: str_field(server, "password")?,
+                network: normalize_network(stream["network"].as_str().unwrap_or("tcp")),
+                allow_insecure: tls_settings
+                    .as_ref()
+                    .map(|t| t.allow_insecure)
+                    .unwrap_or(false),
+                security,
+                tls_settings: Box::new(tls_settings),
+                network_settings: stream_network_settings(stream),
+            }
+        }
+        "hysteria" => ServerConfig::Hysteria2 {
+            tag,
+            address: str_field(settings, "server")?,
+            port: port_field(settings, "serverPort")?,
+            password: str_field(settings, "auth")?,
+            server_name: settings["tls"]["serverName"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            allow_insecure: settings["tls"]["insecure"].as_bool().unwrap_or(false),
+            obfs: settings["obfs"]["type"].as_str().map(|s| s.to_string()),
+            obfs_password: settings["obfs"]["password"].as_str().map(|s| s.to_string()),
+        },
+        protocol @ ("socks" | "http") => {
+            let server = &settings["servers"][0];
+            let user = &server["users"][0];
+            let field = |key: &str| {
+                user[key]
+                    .as_str()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+            };
+            let (address, port) = (str_field(server, "address")?, port_field(server, "port")?);
+            let (username, password) = (field("user"), field("pass"));
+            if protocol == "socks" {
+                ServerConfig::Socks {
+                    tag,
+                    address,
+                    port,
+                    username,
+                    password,
+                }
+            } else {
+                ServerConfig::Http {
+                    tag,
+                    address,
+                    port,
+                    username,
+                    password,
+                }
+            }
+        }
+        "wireguard" => {
+            let peer = &settings["peers"][0];
+            let endpoint = str_field(peer, "endpoint")?;
+            let (address, port) = split_host_port(&endpoint)
+                .ok()
+                .and_then(|(address, port)| Some((address, port?)))
+                .with_context(|| format!("Invalid endpoint {}", endpoint))?;
+            ServerConfig::Wireguard {
+                tag,
+                address,
+                port,
+                secret_key: str_field(settings, "secretKey")?,
+                public_key: str_field(peer, "publicKey")?,
+                pre_shared_key: peer["preSharedKey"].as_str().map(|s| s.to_string()),
+                local_address: settings["address"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|a| a.as_str().map(|s| s.to_string()))
+                    .collect(),
+                reserved: settings["reserved"].as_array().map(|bytes| {
+                    bytes
+                        .iter()
+                        .filter_map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                        .collect()
+                }),
+                mtu: settings["mtu"].as_u64().and_then(|m| u16::try_from(m).ok()),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(server))
+}
+
+/// Builds a share URL that `parser::parse_servers` reads back into the same server.
+///
+/// Returns None for protocols without a share URL form supported here.
+pub fn server_to_url(server: &ServerConfig) -> Option<String> {
+    let url = match server {
+        ServerConfig::Shadowsocks {
+            tag,
+            address,
+            port,
+            method,
+            password,
+            udp_over_tcp,
+            plugin,
+        } => {
+            // SIP022 wants 2022 keys plain, so they stay readable
+            let userinfo = if method.starts_with("2022-") {
+                format!("{}:{}", encode(method), encode(password))
+            } else {
+                BASE64_URL_SAFE_NO_PAD.encode(format!("{}:{}", method, password))
+            };
+            let mut params = Vec::new();
+            if *udp_over_tcp {
+                params.push(("uot", "1".to_string()));
+            }
+            if let Some(plugin) = plugin {
+                params.push(("plugin", plugin.to_plugin_string()));
+            }
+            let query = if params.is_empty() {
+                String::new()
+            } else {
+                format!("/?{}", encode_query(&params))
+            };
+            format!(
+                "ss://{}@{}:{}{}#{}",
+                userinfo,
+                format_host(address),
+                port,
+                query,
+                encode(tag)
+            )
+        }
+        ServerConfig::Vless {
+            tag,
+            address,
+            port,
+            id,
+            encryption,
+            flow,
+            network,
+            security,
+            tls_settings,
+            network_settings,
+        } => {
+            let mut params = vec![
+                ("encryption", encryption.clone()),
+                ("type", network.clone()),
+                ("security", security.clone()),
+            ];
+            if !flow.is_empty() {
+                params.push(("flow", flow.clone()));
+            }
+            push_tls_params(&mut params, tls_settings.as_ref().as_ref());
+            push_network_params(&mut params, network_settings.as_ref());
+            format!(
+                "vless://{}@{}:{}?{}#{}",
+                id,
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::Vmess {
+            tag,
+            address,
+            port,
+            id,
+            alter_id,
+            security,
+            network,
+            network_settings,
+            tls_settings,
+            allow_insecure,
+        } => {
+            let mut config = json!({
+                "v": "2",
+                "ps": tag,
+                "add": address,
+                "port": port.to_string(),
+                "id": id,
+                "aid": alter_id.to_string(),
+                "scy": security,
+                "net": network,
+            });
+
+            match network_settings {
+                Some(NetworkSettings::WebSocket { path, host }) => {
+                    config["path"] = json!(path);
+                    config["host"] = json!(host);
+                }
+                Some(NetworkSettings::Grpc {
+                    service_name,
+                    authority,
+                }) => {
+                    config["path"] = json!(service_name);
+                    config["host"] = json!(authority);
+                }
+                Some(NetworkSettings::Tcp { header_type }) => {
+                    config["type"] = json!(header_type);
+                }
+                Some(NetworkSettings::Xhttp { path, host, .. }) => {
+                    config["path"] = json!(path);
+                    config["host"] = json!(host);
+                }
+                Some(NetworkSettings::Kcp { header_type, seed }) => {
+                    config["type"] = json!(header_type);
+                    config["path"] = json!(seed);
+                }
+                Some(NetworkSettings::Quic {
+                    security,
+                    key,
+                    header_type,
+                }) => {
+                    config["type"] = json!(header_type);
+                    config["host"] = json!(security);
+                    config["path"] = json!(key);
+                }
+                None => {}
+            }
+
+            if let Some(tls) = tls_settings.as_ref() {
+                config["tls"] = json!("tls");
+                config["sni"] = json!(tls.server_name);
+                config["fp"] = json!(tls.fingerprint);
+                if let Some(alpn) = &tls.alpn {
+                    config["alpn"] = json!(alpn.join(","));
+                }
+            }
+            if *allow_insecure {
+                config["insecure"] = json!("1");
+            }
+
+            format!("vmess://{}", BASE64_STANDARD.encode(config.to_string()))
+        }
+        ServerConfig::Trojan {
+            tag,
+            address,
+            port,
+            password,
+            network,
+            security,
+            tls_settings,
+            network_settings,
+            allow_insecure,
+        } => {
+            let mut params = vec![("type", network.clone()), ("security", security.clone())];
+            push_tls_params(&mut params, tls_settings.as_ref().as_ref());
+            push_network_params(&mut params, network_settings.as_ref());
+            if *allow_insecure {
+                params.push(("insecure", "1".to_string()));
+            }
+            format!(
+                "trojan://{}@{}:{}?{}#{}",
+                encode(password),
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::Socks {
+            tag,
+            address,
+            port,
+            username,
+            password,
+        }
+        | ServerConfig::Http {
+            tag,
+            address,
+            port,
+            username,
+            password,
+        } => {
+            let userinfo = match (username, password) {
+                (None, None) => String::new(),
+                (username, password) => format!(
+                    "{}:{}@",
+                    encode(username.as_deref().unwrap_or_default()),
+                    encode(password.as_deref().unwrap_or_default())
+                ),
+            };
+            format!(
+                "{}://{}{}:{}#{}",
+                server.protocol(),
+                userinfo,
+                format_host(address),
+                port,
+                encode(tag)
+            )
+        }
+        ServerConfig::Naive {
+            tag,
+            address,
+            port,
+            username,
+            password,
+            padding,
+        } => {
+            let userinfo = match (username, password) {
+                (None, None) => String::new(),
+                (username, password) => format!(
+                    "{}:{}@",
+                    encode(username.as_deref().unwrap_or_default()),
+                    encode(password.as_deref().unwrap_or_default())
+                ),
+            };
+            format!(
+                "naive+https://{}{}:{}{}#{}",
+                userinfo,
+                format_host(address),
+                port,
+                if *padding { "?padding=true" } else { "" },
+                encode(tag)
+            )
+        }
+        ServerConfig::Hysteria {
+            tag,
+            address,
+            port,
+            auth,
+            transport,
+            up_mbps,
+            down_mbps,
+            obfs,
+            server_name,
+            alpn,
+            allow_insecure,
+        } => {
+            let mut params = vec![("protocol", transport.clone())];
+            if let Some(auth) = auth {
+                params.push(("auth", auth.clone()));
+            }
+            if !server_name.is_empty() {
+                params.push(("peer", server_name.clone()));
+            }
+            if *allow_insecure {
+                params.push(("insecure", "1".to_string()));
+            }
+            if let Some(up_mbps) = up_mbps {
+                params.push(("upmbps", up_mbps.to_string()));
+            }
+            if let Some(down_mbps) = down_mbps {
+                params.push(("downmbps", down_mbps.to_string()));
+            }
+            if let Some(alpn) = alpn {
+                params.push(("alpn", alpn.clone()));
+            }
+            if let Some(obfs) = obfs {
+                params.push(("obfs", "xplus".to_string()));
+                params.push(("obfsParam", obfs.clone()));
+            }
+            format!(
+                "hysteria://{}:{}?{}#{}",
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::Hysteria2 {
+            tag,
+            address,
+            port,
+            password,
+            server_name,
+            allow_insecure,
+            obfs,
+            obfs_password,
+        } => {
+            let mut params = vec![
+                ("sni", server_name.clone()),
+                (
+                    "insecure",
+                    if *allow_insecure { "1" } else { "0" }.to_string(),
+                ),
+            ];
+            if let Some(obfs) = obfs {
+                params.push(("obfs", obfs.clone()));
+            }
+            if let Some(obfs_password) = obfs_password {
+                params.push(("obfs-password", obfs_password.clone()));
+            }
+            format!(
+                "hysteria2://{}@{}:{}?{}#{}",
+                encode(password),
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::Tuic {
+            tag,
+            address,
+            port,
+            uuid,
+            password,
+            congestion_control,
+            udp_relay_mode,
+            alpn,
+            sni,
+            allow_insecure,
+        } => {
+            let mut params = Vec::new();
+            if let Some(congestion_control) = congestion_control {
+                params.push(("congestion_control", congestion_control.clone()));
+            }
+            if let Some(udp_relay_mode) = udp_relay_mode {
+                params.push(("udp_relay_mode", udp_relay_mode.clone()));
+            }
+            if !alpn.is_empty() {
+                params.push(("alpn", alpn.join(",")));
+            }
+            if !sni.is_empty() {
+                params.push(("sni", sni.clone()));
+            }
+            if *allow_insecure {
+                params.push(("allow_insecure", "1".to_string()));
+            }
+            format!(
+                "tuic://{}:{}@{}:{}?{}#{}",
+                encode(uuid),
+                encode(password),
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::Wireguard {
+            tag,
+            address,
+            port,
+            secret_key,
+            public_key,
+            pre_shared_key,
+            local_address,
+            reserved,
+            mtu,
+        } => {
+            let mut params = vec![("publickey", public_key.clone())];
+            if let Some(pre_shared_key) = pre_shared_key {
+                params.push(("presharedkey", pre_shared_key.clone()));
+            }
+            if !local_address.is_empty() {
+                params.push(("address", local_address.join(",")));
+            }
+            if let Some(reserved) = reserved {
+                let reserved: Vec<String> = reserved.iter().map(|b| b.to_string()).collect();
+                params.push(("reserved", reserved.join(",")));
+            }
+            if let Some(mtu) = mtu {
+                params.push(("mtu", mtu.to_string()));
+            }
+            format!(
+                "wireguard://{}@{}:{}?{}#{}",
+                encode(secret_key),
+                format_host(address),
+                port,
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+        ServerConfig::ShadowsocksR {
+            tag,
+            address,
+            port,
+            method,
+            password,
+            protocol,
+            protocol_param,
+            obfs,
+            obfs_param,
+        } => {
+            let b64 = |value: &str| BASE64_URL_SAFE_NO_PAD.encode(value);
+            let host = format_host(address);
+            let params = [
+                ("obfsparam", b64(obfs_param.as_deref().unwrap_or_default())),
+                (
+                    "protoparam",
+                    b64(protocol_param.as_deref().unwrap_or_default()),
+                ),
+                ("remarks", b64(tag)),
+            ];
+            let body = format!(
+                "{}:{}:{}:{}:{}:{}/?{}",
+                host,
+                port,
+                protocol,
+                method,
+                obfs,
+                b64(password),
+                encode_query(&params)
+            );
+            format!("ssr://{}", b64(&body))
+        }
+        ServerConfig::Brook {
+            tag,
+            address,
+            port,
+            password,
+            kind,
+            path,
+        } => {
+            let host_port = format!("{}:{}", format_host(address), port);
+            let server = match kind.as_str() {
+                "wsserver" => format!("ws://{}{}", host_port, path),
+                "wssserver" => format!("wss://{}{}", host_port, path),
+                _ => host_port,
+            };
+            let params = [(kind.as_str(), server), ("password", password.clone())];
+            format!("brook://{}?{}#{}", kind, encode_query(&params), encode(tag))
+        }
+        ServerConfig::Mieru {
+            tag,
+            address,
+            port,
+            username,
+            password,
+            transport,
+            multiplexing,
+        } => {
+            let mut params = vec![("port", port.to_string()), ("protocol", transport.clone())];
+            if let Some(multiplexing) = multiplexing {
+                params.push(("multiplexing", multiplexing.clone()));
+            }
+            format!(
+                "mierus://{}:{}@{}?{}#{}",
+                encode(username),
+                encode(password),
+                format_host(address),
+                encode_query(&params),
+                encode(tag)
+            )
+        }
+    };
+
+    Some(url)
+}
+
+/// Writes servers as NDJSON, one serialized [`ServerConfig`] per line, for
+/// handing the parse stage's result to another machine.
+pub fn write_ndjson(out: &mut dyn Write, servers: &[ServerConfig]) -> Result<()> {
+    for server in servers {
+        serde_json::to_writer(&mut *out, server)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Reads servers written by [`write_ndjson`]. Blank lines are skipped; any
+/// other line that isn't a server fails the whole file.
+pub fn servers_from_ndjson(content: &str) -> Result<Vec<ServerConfig>> {
+    content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid server on line {}", idx + 1))
+        })
+        .collect()
+}
+
+fn str_field(value: &Value, key: &str) -> Result<String> {
+    value[key]
+        .as_str()
+        .map(|s| s.to_string())
+        .with_context(|| format!("Missing {} field", key))
+}
+
+fn port_field(value: &Value, key: &str) -> Result<u16> {
+    let port = value[key]
+        .as_u64()
+        .with_context(|| format!("Missing {} field", key))?;
+    u16::try_from(port).with_context(|| format!("Invalid port {}", port))
+}
+
+fn stream_tls_settings(stream: &Value, security: &str) -> Option<TlsSettings> {
+    let settings = match security {
+        "tls" => &stream["tlsSettings"],
+        "reality" => &stream["realitySettings"],
+        _ => return None,
+    };
+
+    let optional = |key: &str| settings[key].as_str().map(|s| s.to_string());
+
+    Some(TlsSettings {
+        server_name: optional("serverName").unwrap_or_default(),
+        fingerprint: optional("fingerprint").unwrap_or_else(|| "chrome".to_string()),
+        alpn: settings["alpn"].as_array().map(|alpn| {
+            alpn.iter()
+                .filter_map(|a| a.as_str().map(|s| s.to_string()))
+                .collect()
+        }),
+        allow_insecure: settings["allowInsecure"].as_bool().unwrap_or(false),
+        public_key: optional("publicKey"),
+        short_id: optional("shortId"),
+        spider_x: optional("spiderX"),
+    })
+}
+
+/// The v2ray-plugin options matching a shadowsocks outbound's websocket
+/// transport, or None if it uses plain TCP.
+fn stream_plugin(stream: &Value) -> Option<ShadowsocksPlugin> {
+    let Some(NetworkSettings::WebSocket { path, host }) = stream_network_settings(stream) else {
+        return None;
+    };
+
+    let mut opts = Vec::new();
+    if stream["security"] == "tls" {
+        opts.push(("tls".to_string(), String::new()));
+    }
+    opts.push(("host".to_string(), host));
+    opts.push(("path".to_string(), path));
+    Some(ShadowsocksPlugin {
+        name: "v2ray-plugin".to_string(),
+        opts,
+    })
+}
+
+fn stream_network_settings(stream: &Value) -> Option<NetworkSettings> {
+    match normalize_network(stream["network"].as_str().unwrap_or("tcp")).as_str() {
+        "ws" => {
+            let ws = &stream["wsSettings"];
+            let host = ws["host"]
+                .as_str()
+                .or_else(|| ws["headers"]["Host"].as_str())
+                .unwrap_or_default();
+            Some(NetworkSettings::WebSocket {
+                path: ws["path"].as_str().unwrap_or("/").to_string(),
+                host: host.to_string(),
+            })
+        }
+        "grpc" => {
+            let grpc = &stream["grpcSettings"];
+            Some(NetworkSettings::Grpc {
+                service_name: grpc["serviceName"].as_str().unwrap_or_default().to_string(),
+                authority: grpc["authority"].as_str().unwrap_or_default().to_string(),
+            })
+        }
+        "xhttp" => {
+            let xhttp = &stream["xhttpSettings"];
+            Some(NetworkSettings::Xhttp {
+                path: xhttp["path"].as_str().unwrap_or("/").to_string(),
+                host: xhttp["host"].as_str().unwrap_or_default().to_string(),
+                mode: xhttp["mode"].as_str().unwrap_or("auto").to_string(),
+            })
+        }
+        "kcp" => {
+            let kcp = &stream["kcpSettings"];
+            Some(NetworkSettings::Kcp {
+                header_type: kcp["header"]["type"].as_str().unwrap_or("none").to_string(),
+                seed: kcp["seed"].as_str().unwrap_or_default().to_string(),
+            })
+        }
+        "quic" => {
+            let quic = &stream["quicSettings"];
+            Some(NetworkSettings::Quic {
+                security: quic["security"].as_str().unwrap_or("none").to_string(),
+                key: quic["key"].as_str().unwrap_or_default().to_string(),
+                header_type: quic["header"]["type"]
+                    .as_str()
+                    .unwrap_or("none")
+                    .to_string(),
+            })
+        }
+        "tcp" => Some(NetworkSettings::Tcp {
+            header_type: stream["tcpSettings"]["header"]["type"]
+                .as_str()
+                .unwrap_or("none")
+                .to_string(),
+        }),
+        _ => None,
+    }
+}
+
+fn push_tls_params(params: &mut Vec<(&'static str, String)>, tls: Option<&TlsSettings>) {
+    let Some(tls) = tls else {
+        return;
+    };
+
+    params.push(("sni", tls.server_name.clone()));
+    params.push(("fp", tls.fingerprint.clone()));
+    params.push((
+        "allowInsecure",
+        if tls.allow_insecure { "1" } else { "0" }.to_string(),
+    ));
+    if let Some(alpn) = &tls.alpn {
+        params.push(("alpn", alpn.join(",")));
+    }
+    if let Some(pbk) = &tls.public_key {
+        params.push(("pbk", pbk.clone()));
+    }
+    if let Some(sid) = &tls.short_id {
+        params.push(("sid", sid.clone()));
+    }
+    if let Some(spx) = &tls.spider_x {
+        params.push(("spx", spx.clone()));
+    }
+}
+
+fn push_network_params(
+    params: &mut Vec<(&'static str, String)>,
+    network_settings: Option<&NetworkSettings>,
+) {
+    match network_settings {
+        Some(NetworkSettings::WebSocket { path, host }) => {
+            params.push(("path", path.clone()));
+            params.push(("host", host.clone()));
+        }
+        Some(NetworkSettings::Grpc {
+            service_name,
+            authority,
+        }) => {
+            params.push(("serviceName", service_name.clone()));
+            params.push(("authority", authority.clone()));
+        }
+        Some(NetworkSettings::Tcp { header_type }) => {
+            params.push(("headerType", header_type.clone()));
+        }
+        Some(NetworkSettings::Xhttp { path, host, mode }) => {
+            params.push(("path", path.clone()));
+            params.push(("host", host.clone()));
+            params.push(("mode", mode.clone()));
+        }
+        Some(NetworkSettings::Kcp { header_type, seed }) => {
+            params.push(("headerType", header_type.clone()));
+            if !seed.is_empty() {
+                params.push(("seed", seed.clone()));
+            }
+        }
+        Some(NetworkSettings::Quic {
+            security,
+            key,
+            header_type,
+        }) => {
+            params.push(("quicSecurity", security.clone()));
+            params.push(("key", key.clone()));
+            params.push(("headerType", header_type.clone()));
+        }
+        None => {}
+    }
+}
+
+pub(crate) fn encode_query(params: &[(&str, String)]) -> String {
+    params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
@@ -0,0 +1,118 @@
+use crate::config::LOCK_FILE_NAME;
+use crate::git::run_git;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// Default `--git-commit-message` template.
+pub const DEFAULT_COMMIT_MESSAGE: &str =
+    "Update configs: {servers} servers from {parsed} parsed, {skipped} skipped";
+
+/// Numbers of a run that commit message templates can refer to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommitStats {
+    /// Servers parsed from the sources
+    pub parsed: usize,
+    /// Servers written as outbounds
+    pub servers: usize,
+    /// Servers dropped by filters or unsupported by the target
+    pub skipped: usize,
+}
+
+/// Fills `{parsed}`, `{servers}` and `{skipped}` in a message template.
+pub fn render_message(template: &str, stats: &CommitStats) -> String {
+    template
+        .replace("{parsed}", &stats.parsed.to_string())
+        .replace("{servers}", &stats.servers.to_string())
+        .replace("{skipped}", &stats.skipped.to_string())
+}
+
+/// Commits everything in `dir` (the output directory, inside a git work tree)
+/// and optionally pushes to the branch's upstream.
+///
+/// Deleted files (e.g. outbound chunks no longer written) are committed too;
+/// the lock file is left out. Returns false, without committing or pushing,
+/// when nothing changed since the last commit.
+pub fn git_commit(dir: &Path, message: &str, push: bool) -> Result<bool> {
+    run_git(Some(dir), &["rev-parse", "--is-inside-work-tree"])
+        .with_context(|| format!("{} is not inside a git repository", dir.display()))?;
+
+    let exclude_lock = format!(":(exclude){}", LOCK_FILE_NAME);
+    run_git(Some(dir), &["add", "--all", "--", ".", &exclude_lock])?;
+
+    let unchanged = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["diff", "--cached", "--quiet", "--", "."])
+        .status()
+        .context("Failed to run git")?
+        .success();
+    if unchanged {
+        return Ok(false);
+    }
+
+    run_git(Some(dir), &["commit", "--quiet", "-m", message, "--", "."])?;
+    if push {
+        run_git(Some(dir), &["push", "--quiet"])?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_message() {
+        let stats = CommitStats {
+            parsed: 12,
+            servers: 10,
+            skipped: 2,
+        };
+        assert_eq!(
+            render_message(DEFAULT_COMMIT_MESSAGE, &stats),
+            "Update configs: 10 servers from 12 parsed, 2 skipped"
+        );
+    }
+
+    #[test]
+    fn test_git_commit() {
+        let repo =
+            std::env::temp_dir().join(format!("proxy-harvest-deploy-{}", std::process::id()));
+        let output = repo.join("configs");
+        std::fs::create_dir_all(&output).unwrap();
+
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8(output.stdout).unwrap()
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.name", "test"]);
+        git(&["config", "user.email", "test@example.com"]);
+
+        std::fs::write(output.join("04_outbounds.json"), "{}").unwrap();
+        std::fs::write(output.join(LOCK_FILE_NAME), "").unwrap();
+        // Files outside the output directory aren't touched
+        std::fs::write(repo.join("notes.txt"), "draft").unwrap();
+
+        assert!(git_commit(&output, "first", false).unwrap());
+        assert!(!git_commit(&output, "second", false).unwrap());
+
+        std::fs::remove_file(output.join("04_outbounds.json")).unwrap();
+        std::fs::write(output.join("04a_outbounds.json"), "{}").unwrap();
+        assert!(git_commit(&output, "chunked", false).unwrap());
+
+        let files = git(&["ls-files"]);
+        let log = git(&["log", "--format=%s"]);
+        std::fs::remove_dir_all(&repo).unwrap();
+
+        assert_eq!(files, "configs/04a_outbounds.json\n");
+        assert_eq!(log, "chunked\nfirst\n");
+    }
+}
@@ -0,0 +1,365 @@
+use crate::backup;
+use base64::Engine;
+use base64::prelude::{
+    BASE64_STANDARD, BASE64_STANDARD_NO_PAD, BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Formats subscription content is served in. The serde names are the ones
+/// a `--sources` manifest gives as a source's `format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    /// Newline-delimited share URLs
+    UrlList,
+    /// Base64 encoded URL list
+    Base64,
+    /// SIP008 shadowsocks JSON
+    Sip008,
+    /// v2rayNG backup (angconfig or profile items)
+    #[serde(rename = "v2rayng")]
+    V2rayNg,
+    /// NekoBox profile export
+    #[serde(rename = "nekobox")]
+    NekoBox,
+    /// Any other JSON document, e.g. a sing-box or Xray config
+    Json,
+    /// Parsed servers written by `--emit-parsed`, one JSON object per line
+    Ndjson,
+    /// YAML, e.g. a Clash config
+    #[serde(alias = "clash")]
+    Yaml,
+    /// An RSS or Atom feed whose entries contain share URLs
+    Feed,
+    /// An HTML page, usually an error or login page instead of a subscription
+    Html,
+}
+
+impl InputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputFormat::UrlList => "url list",
+            InputFormat::Base64 => "base64",
+            InputFormat::Sip008 => "sip008",
+            InputFormat::V2rayNg => "v2rayng backup",
+            InputFormat::NekoBox => "nekobox profiles",
+            InputFormat::Json => "json",
+            InputFormat::Ndjson => "ndjson servers",
+            InputFormat::Yaml => "yaml",
+            InputFormat::Feed => "rss/atom feed",
+            InputFormat::Html => "html",
+        }
+    }
+}
+
+/// Best guess for the format of some content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub format: InputFormat,
+    /// How sure the guess is, from 0.0 to 1.0
+    pub confidence: f32,
+}
+
+/// Guesses the format of subscription content, or None if nothing fits.
+///
+/// Every format is scored and the highest score wins, so e.g. a URL list with
+/// a few junk lines is still detected as a URL list, with lower confidence.
+pub fn detect(content: &str) -> Option<Detection> {
+    let content = content.trim_start_matches('\u{feff}').trim();
+    if content.is_empty() {
+        return None;
+    }
+
+    let candidates = [
+        (InputFormat::UrlList, url_list_score(content)),
+        (InputFormat::Base64, base64_score(content)),
+        (InputFormat::Sip008, sip008_score(content)),
+        (InputFormat::V2rayNg, v2rayng_score(content)),
+        (InputFormat::NekoBox, nekobox_score(content)),
+        (InputFormat::Json, json_score(content)),
+        (InputFormat::Ndjson, ndjson_score(content)),
+        (InputFormat::Yaml, yaml_score(content)),
+        (InputFormat::Feed, feed_score(content)),
+        (InputFormat::Html, html_score(content)),
+    ];
+
+    candidates
+        .into_iter()
+        .filter(|(_, confidence)| *confidence > 0.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(format, confidence)| Detection { format, confidence })
+}
+
+/// Decodes a base64 blob (standard or URL-safe, padded or not, possibly
+/// wrapped over several lines) into text.
+pub fn decode_base64(content: &str) -> Option<String> {
+    let compact: String = content.split_whitespace().collect();
+    if compact.is_empty() {
+        return None;
+    }
+
+    [
+        &BASE64_STANDARD,
+        &BASE64_STANDARD_NO_PAD,
+        &BASE64_URL_SAFE,
+        &BASE64_URL_SAFE_NO_PAD,
+    ]
+    .iter()
+    .find_map(|engine| engine.decode(&compact).ok())
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// Share of non-empty lines that look like `scheme://...`.
+fn url_list_score(content: &str) -> f32 {
+    let mut total = 0;
+    let mut urls = 0;
+    for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        total += 1;
+        if is_share_url(line) {
+            urls += 1;
+        }
+    }
+
+    if total == 0 {
+        0.0
+    } else {
+        urls as f32 / total as f32
+    }
+}
+
+fn base64_score(content: &str) -> f32 {
+    let is_alphabet = content
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+/=-_".contains(c) || c.is_ascii_whitespace());
+    if !is_alphabet {
+        return 0.0;
+    }
+
+    match decode_base64(content) {
+        // Slightly below a clean URL list, which can't be valid base64 anyway
+        Some(decoded) => 0.95 * url_list_score(&decoded),
+        None => 0.0,
+    }
+}
+
+fn sip008_score(content: &str) -> f32 {
+    match serde_json::from_str::<Value>(content) {
+        Ok(json) if json["servers"].is_array() => 1.0,
+        _ => 0.0,
+    }
+}
+
+fn v2rayng_score(content: &str) -> f32 {
+    match serde_json::from_str::<Value>(content) {
+        Ok(json) if backup::is_v2rayng(&json) => 1.0,
+        _ => 0.0,
+    }
+}
+
+fn nekobox_score(content: &str) -> f32 {
+    match serde_json::from_str::<Value>(content) {
+        Ok(json) if backup::is_nekobox(&json) => 1.0,
+        _ => 0.0,
+    }
+}
+
+fn json_score(content: &str) -> f32 {
+    let looks_like_json = content.starts_with('{') || content.starts_with('[');
+    if looks_like_json && serde_json::from_str::<Value>(content).is_ok() {
+        0.9
+    } else {
+        0.0
+    }
+}
+
+/// Share of non-empty lines that are JSON objects naming a `protocol`.
+fn ndjson_score(content: &str) -> f32 {
+    let mut total = 0;
+    let mut servers = 0;
+    for line in content.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        total += 1;
+        let is_server = line.starts_with('{')
+            && serde_json::from_str::<Value>(line).is_ok_and(|json| json["protocol"].is_string());
+        if is_server {
+            servers += 1;
+        }
+    }
+
+    if total == 0 {
+        0.0
+    } else {
+        servers as f32 / total as f32
+    }
+}
+
+fn yaml_score(content: &str) -> f32 {
+    let mut total = 0;
+    let mut yaml_like = 0;
+    for line in content
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+    {
+        total += 1;
+        let trimmed = line.trim_start();
+        let is_mapping = trimmed.split_once(':').is_some_and(|(key, rest)| {
+            !key.contains("//") && (rest.is_empty() || rest.starts_with(' '))
+        });
+        if is_mapping || trimmed.starts_with("- ") {
+            yaml_like += 1;
+        }
+    }
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    let score = 0.8 * yaml_like as f32 / total as f32;
+    if content.lines().any(|l| l.starts_with("proxies:")) {
+        score.max(0.9)
+    } else {
+        score
+    }
+}
+
+fn feed_score(content: &str) -> f32 {
+    let head = content
+        .chars()
+        .take(512)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let head = head
+        .strip_prefix("<?xml")
+        .and_then(|rest| rest.split_once("?>"))
+        .map_or(head.as_str(), |(_, rest)| rest)
+        .trim_start();
+
+    if head.starts_with("<rss") || head.starts_with("<feed") {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn html_score(content: &str) -> f32 {
+    let head = content
+        .chars()
+        .take(512)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        1.0
+    } else if head.contains("<html") || head.contains("<body") || head.contains("<head") {
+        0.7
+    } else {
+        0.0
+    }
+}
+
+fn is_share_url(line: &str) -> bool {
+    match line.split_once("://") {
+        Some((scheme, rest)) => {
+            !rest.is_empty()
+                && scheme
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL_LIST: &str = "\
+ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNzd29yZA@1.2.3.4:8388#DE-1
+trojan://secret@example.com:443?security=tls#trojan-1
+";
+
+    fn format_of(content: &str) -> Option<InputFormat> {
+        detect(content).map(|d| d.format)
+    }
+
+    #[test]
+    fn test_detect_url_list() {
+        let detection = detect(URL_LIST).unwrap();
+        assert_eq!(detection.format, InputFormat::UrlList);
+        assert_eq!(detection.confidence, 1.0);
+
+        let noisy = format!("# my servers\n{}", URL_LIST);
+        let detection = detect(&noisy).unwrap();
+        assert_eq!(detection.format, InputFormat::UrlList);
+        assert!(detection.confidence < 1.0);
+    }
+
+    #[test]
+    fn test_detect_base64() {
+        let encoded = BASE64_STANDARD.encode(URL_LIST);
+        assert_eq!(format_of(&encoded), Some(InputFormat::Base64));
+
+        let wrapped: String = encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|c| format!("{}\n", String::from_utf8_lossy(c)))
+            .collect();
+        assert_eq!(format_of(&wrapped), Some(InputFormat::Base64));
+        assert_eq!(decode_base64(&wrapped).as_deref(), Some(URL_LIST));
+
+        let url_safe = BASE64_URL_SAFE_NO_PAD.encode(URL_LIST);
+        assert_eq!(format_of(&url_safe), Some(InputFormat::Base64));
+    }
+
+    #[test]
+    fn test_detect_json_and_sip008() {
+        assert_eq!(
+            format_of(r#"{"version": 1, "servers": []}"#),
+            Some(InputFormat::Sip008)
+        );
+        assert_eq!(
+            format_of(r#"{"outbounds": [{"type": "vless"}]}"#),
+            Some(InputFormat::Json)
+        );
+    }
+
+    #[test]
+    fn test_detect_ndjson() {
+        let ndjson = concat!(
+            r#"{"protocol":"socks","tag":"a","address":"1.2.3.4","port":1080,"username":null,"password":null}"#,
+            "\n",
+            r#"{"protocol":"http","tag":"b","address":"5.6.7.8","port":3128,"username":null,"password":null}"#,
+            "\n",
+        );
+        assert_eq!(format_of(ndjson), Some(InputFormat::Ndjson));
+
+        // A single server is still a server, not just any JSON
+        let single = ndjson.lines().next().unwrap();
+        assert_eq!(format_of(single), Some(InputFormat::Ndjson));
+    }
+
+    #[test]
+    fn test_detect_yaml_and_html() {
+        let clash = "port: 7890\nproxies:\n  - name: de-1\n    type: ss\n    server: 1.2.3.4\n";
+        assert_eq!(format_of(clash), Some(InputFormat::Yaml));
+
+        let page = "<!DOCTYPE html>\n<html><body>Subscription expired</body></html>";
+        assert_eq!(format_of(page), Some(InputFormat::Html));
+
+        let rss = "<?xml version=\"1.0\"?>\n<rss version=\"2.0\"><channel></channel></rss>";
+        assert_eq!(format_of(rss), Some(InputFormat::Feed));
+
+        let atom = "<feed xmlns=\"http://www.w3.org/2005/Atom\"></feed>";
+        assert_eq!(format_of(atom), Some(InputFormat::Feed));
+    }
+
+    #[test]
+    fn test_detect_nothing() {
+        assert_eq!(detect(""), None);
+        assert_eq!(detect("   \n  "), None);
+        assert_eq!(detect("!!! ???"), None);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::progress::{Cancelled, Hooks, Stage};
+use anyhow::{Context, Result};
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::{Resolver, TokioAsyncResolver};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A DNS server used instead of the system resolver.
+///
+/// Accepted forms:
+/// - `1.1.1.1` or `1.1.1.1:53` - plain DNS over UDP/TCP
+/// - `tls://1.1.1.1#cloudflare-dns.com` - DNS over TLS
+/// - `https://1.1.1.1#cloudflare-dns.com` - DNS over HTTPS
+///
+/// The part after `#` is the name checked against the server certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsServer {
+    Plain(SocketAddr),
+    Tls { addr: SocketAddr, tls_name: String },
+    Https { addr: SocketAddr, tls_name: String },
+}
+
+impl FromStr for DnsServer {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (scheme, rest) = match value.split_once("://") {
+            Some((scheme, rest)) => (scheme, rest),
+            None => ("udp", value),
+        };
+
+        let (addr_part, tls_name) = match rest.split_once('#') {
+            Some((addr, name)) => (addr, Some(name.to_string())),
+            None => (rest, None),
+        };
+
+        let default_port = match scheme {
+            "udp" | "tcp" => 53,
+            "tls" => 853,
+            "https" => 443,
+            _ => anyhow::bail!("Unsupported DNS server scheme: {}", scheme),
+        };
+        let addr = parse_socket_addr(addr_part, default_port)?;
+
+        match scheme {
+            "tls" => Ok(DnsServer::Tls {
+                addr,
+                tls_name: tls_name.context("DNS over TLS requires a server name after '#'")?,
+            }),
+            "https" => Ok(DnsServer::Https {
+                addr,
+                tls_name: tls_name.context("DNS over HTTPS requires a server name after '#'")?,
+            }),
+            _ => Ok(DnsServer::Plain(addr)),
+        }
+    }
+}
+
+fn parse_socket_addr(value: &str, default_port: u16) -> Result<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+
+    let ip: IpAddr = value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid DNS server address: {}", value))?;
+    Ok(SocketAddr::new(ip, default_port))
+}
+
+/// Resolver for reqwest that sends every lookup to a configured DNS server.
+#[derive(Clone)]
+pub struct DnsResolver {
+    server: DnsServer,
+}
+
+impl DnsResolver {
+    pub fn new(server: DnsServer) -> Self {
+        Self { server }
+    }
+
+    fn resolver_config(&self) -> ResolverConfig {
+        let group = match &self.server {
+            DnsServer::Plain(addr) => {
+                NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true)
+            }
+            DnsServer::Tls { addr, tls_name } => NameServerConfigGroup::from_ips_tls(
+                &[addr.ip()],
+                addr.port(),
+                tls_name.clone(),
+                true,
+            ),
+            DnsServer::Https { addr, tls_name } => NameServerConfigGroup::from_ips_https(
+                &[addr.ip()],
+                addr.port(),
+                tls_name.clone(),
+                true,
+            ),
+        };
+        ResolverConfig::from_parts(None, vec![], group)
+    }
+}
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let config = self.resolver_config();
+        Box::pin(async move {
+            // Built inside the future so it runs on reqwest's runtime
+            let resolver = TokioAsyncResolver::tokio(config, ResolverOpts::default());
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            let addrs: Addrs = Box::new(
+                lookup
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, 0))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            );
+            Ok(addrs)
+        })
+    }
+}
+
+/// Looks up the PTR name of each IP through `server`, or the system resolver
+/// if None. IPs without a PTR record are left out of the result.
+pub fn reverse_lookup(
+    ips: &[IpAddr],
+    server: Option<&DnsServer>,
+) -> Result<HashMap<IpAddr, String>> {
+    reverse_lookup_with_hooks(ips, server, &Hooks::default())
+}
+
+/// Like `reverse_lookup`, reporting each lookup as `Check` progress. Once
+/// cancelled it fails with `Cancelled`; past the hooks' deadline it stops
+/// and returns the names found so far.
+pub fn reverse_lookup_with_hooks(
+    ips: &[IpAddr],
+    server: Option<&DnsServer>,
+    hooks: &Hooks,
+) -> Result<HashMap<IpAddr, String>> {
+    let resolver = match server {
+        Some(server) => Resolver::new(
+            DnsResolver::new(server.clone()).resolver_config(),
+            ResolverOpts::default(),
+        ),
+        None => Resolver::from_system_conf(),
+    }
+    .context("Failed to create DNS resolver")?;
+
+    let mut names = HashMap::new();
+    for (done, &ip) in ips.iter().enumerate() {
+        if hooks.should_stop() {
+            break;
+        }
+        hooks.report(Stage::Check, done, Some(ips.len()));
+        if names.contains_key(&ip) {
+            continue;
+        }
+        match resolver.reverse_lookup(ip) {
+            Ok(lookup) => {
+                if let Some(name) = lookup.iter().next() {
+                    let name = name.to_utf8();
+                    names.insert(ip, name.trim_end_matches('.').to_lowercase());
+                }
+            }
+            Err(e) => log::debug!("No reverse DNS for {}: {}", ip, e),
+        }
+    }
+    if hooks.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_server_from_str() {
+        assert_eq!(
+            "1.1.1.1".parse::<DnsServer>().unwrap(),
+            DnsServer::Plain("1.1.1.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            "9.9.9.9:5353".parse::<DnsServer>().unwrap(),
+            DnsServer::Plain("9.9.9.9:5353".parse().unwrap())
+        );
+        assert_eq!(
+            "tls://1.1.1.1#cloudflare-dns.com"
+                .parse::<DnsServer>()
+                .unwrap(),
+            DnsServer::Tls {
+                addr: "1.1.1.1:853".parse().unwrap(),
+                tls_name: "cloudflare-dns.com".to_string()
+            }
+        );
+        assert_eq!(
+            "https://[2606:4700:4700::1111]#cloudflare-dns.com"
+                .parse::<DnsServer>()
+                .unwrap(),
+            DnsServer::Https {
+                addr: "[2606:4700:4700::1111]:443".parse().unwrap(),
+                tls_name: "cloudflare-dns.com".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_dns_server_from_str_invalid() {
+        assert!("tls://1.1.1.1".parse::<DnsServer>().is_err());
+        assert!("quic://1.1.1.1#dns".parse::<DnsServer>().is_err());
+        assert!("dns.google".parse::<DnsServer>().is_err());
+    }
+}
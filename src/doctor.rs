@@ -0,0 +1,292 @@
+use crate::config::geosite;
+use crate::detect::detect;
+use crate::fetch::HttpFetcher;
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+/// Geodata files Xray loads from its asset directory.
+const GEODATA_FILES: [&str; 2] = ["geosite.dat", "geoip.dat"];
+
+/// Result of one environment check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure
+    pub fix: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Check {
+    fn ok(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        status: Status,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{}] {}: {}", label, self.name, self.detail)?;
+        if let Some(fix) = &self.fix {
+            write!(f, "\n       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that files can be created in the output directory.
+pub fn check_output_dir(path: &Path) -> Check {
+    let name = format!("output directory {}", path.display());
+    let probe = path.join(".proxy-harvest-doctor");
+
+    let result = std::fs::create_dir_all(path)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(()) => Check::ok(name, "writable"),
+        Err(e) => Check::problem(
+            Status::Fail,
+            name,
+            format!("not writable: {}", e),
+            "create it or fix its owner/permissions, or pick another --output",
+        ),
+    }
+}
+
+/// Runs `xray version` and reports the first line of its output.
+pub fn check_xray(binary: &Path) -> Check {
+    let name = format!("xray {}", binary.display());
+
+    match Command::new(binary).arg("version").output() {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            Check::ok(name, stdout.lines().next().unwrap_or("runs").trim())
+        }
+        Ok(output) => Check::problem(
+            Status::Fail,
+            name,
+            format!(
+                "exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            "check that the binary matches this machine's architecture",
+        ),
+        Err(e) => Check::problem(
+            Status::Fail,
+            name,
+            format!("can't run: {}", e),
+            "install Xray or point --xray at its binary",
+        ),
+    }
+}
+
+/// Checks that the geodata files exist, decode, and are younger than `max_age`.
+pub fn check_geodata(dir: &Path, max_age: Duration) -> Vec<Check> {
+    GEODATA_FILES
+        .iter()
+        .map(|file| {
+            let path = dir.join(file);
+            let name = path.display().to_string();
+
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    return Check::problem(
+                        Status::Fail,
+                        name,
+                        format!("can't read: {}", e),
+                        format!("download {} into {}", file, dir.display()),
+                    );
+                }
+            };
+
+            if *file == "geosite.dat"
+                && let Err(e) = geosite::parse_categories(&data)
+            {
+                return Check::problem(
+                    Status::Fail,
+                    name,
+                    format!("not a valid geosite file: {:#}", e),
+                    format!("download {} again", file),
+                );
+            }
+
+            let age = std::fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .unwrap_or_default();
+            let days = age.as_secs() / 86400;
+            if age > max_age {
+                Check::problem(
+                    Status::Warn,
+                    name,
+                    format!("{} days old", days),
+                    format!("update {}, blocklists go stale quickly", file),
+                )
+            } else {
+                Check::ok(name, format!("{} bytes, {} days old", data.len(), days))
+            }
+        })
+        .collect()
+}
+
+/// Resolves `host` with the system resolver.
+pub fn check_dns(host: &str) -> Check {
+    let name = format!("DNS lookup of {}", host);
+
+    match (host, 443).to_socket_addrs() {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => Check::ok(name, format!("resolves to {}", addr.ip())),
+            None => Check::problem(
+                Status::Fail,
+                name,
+                "no addresses",
+                "check /etc/resolv.conf or pass --dns to the generator",
+            ),
+        },
+        Err(e) => Check::problem(
+            Status::Fail,
+            name,
+            e.to_string(),
+            "check /etc/resolv.conf or pass --dns to the generator",
+        ),
+    }
+}
+
+/// Fetches a source and reports its size and detected format.
+pub fn check_source(fetcher: &dyn HttpFetcher, url: &str) -> Check {
+    let name = format!("source {}", url);
+
+    match fetcher.fetch(url, &[]) {
+        Ok(response) if response.is_success() => match detect(&response.text()) {
+            Some(detection) => Check::ok(
+                name,
+                format!(
+                    "HTTP {}, {} bytes of {}",
+                    response.status,
+                    response.body.len(),
+                    detection.format.name()
+                ),
+            ),
+            None => Check::problem(
+                Status::Warn,
+                name,
+                format!(
+                    "HTTP {}, {} bytes in an unrecognized format",
+                    response.status,
+                    response.body.len()
+                ),
+                "check that the URL points at the raw subscription, not a web page",
+            ),
+        },
+        Ok(response) => Check::problem(
+            Status::Fail,
+            name,
+            format!("HTTP {}", response.status),
+            "check the URL; the subscription may have expired or moved",
+        ),
+        Err(e) => Check::problem(
+            Status::Fail,
+            name,
+            format!("{:#}", e),
+            "check the network, or pass --dns if lookups are filtered",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::FetchResponse;
+    use anyhow::Result;
+
+    struct MockFetcher {
+        status: u16,
+        body: &'static str,
+    }
+
+    impl HttpFetcher for MockFetcher {
+        fn fetch(&self, _url: &str, _headers: &[(&str, &str)]) -> Result<FetchResponse> {
+            Ok(FetchResponse {
+                status: self.status,
+                body: self.body.as_bytes().to_vec(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_check_source() {
+        let fetcher = MockFetcher {
+            status: 200,
+            body: "trojan://secret@example.com:443#a\n",
+        };
+        let check = check_source(&fetcher, "https://example.com/sub");
+        assert_eq!(check.status, Status::Ok);
+        assert!(check.detail.contains("url list"), "{}", check.detail);
+
+        let fetcher = MockFetcher {
+            status: 404,
+            body: "",
+        };
+        let check = check_source(&fetcher, "https://example.com/sub");
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.fix.is_some());
+    }
+
+    #[test]
+    fn test_check_output_dir_and_geodata() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-doctor-{}", std::process::id()));
+        assert_eq!(check_output_dir(&dir).status, Status::Ok);
+
+        // An empty geosite list decodes fine, geoip.dat is missing
+        std::fs::write(dir.join("geosite.dat"), b"").unwrap();
+        let checks = check_geodata(&dir, Duration::from_secs(86400));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(checks[0].status, Status::Ok);
+        assert_eq!(checks[1].status, Status::Fail);
+    }
+
+    #[test]
+    fn test_check_xray_missing_binary() {
+        let check = check_xray(Path::new("/nonexistent/xray"));
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.to_string().contains("fix:"));
+    }
+}
@@ -0,0 +1,527 @@
+use crate::bind::Bind;
+use crate::cache::HttpCache;
+#[cfg(feature = "custom-dns")]
+use crate::dns::{DnsResolver, DnsServer};
+use crate::progress::{Cancelled, Hooks, Stage};
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::path::PathBuf;
+#[cfg(feature = "custom-dns")]
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Raw HTTP response as seen by the fetch layer.
+#[derive(Debug, Clone, Default)]
+pub struct FetchResponse {
+    pub status: u16,
+    /// Response headers with lowercase names
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl FetchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|s| s.as_str())
+    }
+
+    /// Body decoded as UTF-8, with invalid sequences replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Fetches subscription content over HTTP.
+///
+/// Implement this to plug in a custom client, e.g. one routed through a proxy
+/// or using its own resolver, or a mock in tests. Calls block: sources are
+/// read on their own threads and the fetch layer runs no async runtime.
+pub trait HttpFetcher: Send + Sync {
+    fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> Result<FetchResponse>;
+}
+
+/// Builds the fetcher for one download, with `timeout` being what is left
+/// of the run's budget for it.
+///
+/// `ClientOptions` builds a [`ReqwestFetcher`]; implement this to read input
+/// sources through a custom [`HttpFetcher`], see `input::read_sources_with`.
+pub trait FetcherFactory: Send + Sync {
+    fn fetcher(&self, timeout: Option<Duration>) -> Result<Box<dyn HttpFetcher>>;
+}
+
+/// Settings for the default reqwest client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// DNS server used instead of the system resolver
+    #[cfg(feature = "custom-dns")]
+    pub dns_server: Option<DnsServer>,
+    /// Limit for a whole request, body included; reqwest's 30 s if None
+    pub timeout: Option<Duration>,
+    /// Local address or interface for every connection
+    pub bind: Option<Bind>,
+    /// How failed downloads are retried
+    pub retry: RetryPolicy,
+    /// Directory downloads are cached in, see [`fetch_url_bytes_cached`]
+    pub cache_dir: Option<PathBuf>,
+    /// Don't make requests: URLs are read from `cache_dir` only
+    pub offline: bool,
+}
+
+/// How often and how patiently a failed download is retried.
+///
+/// Network errors, timeouts and the statuses that mean "try again later"
+/// (408, 429, 5xx) are retried; other statuses fail right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `retry`, counting from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500..=599)
+}
+
+/// Default fetcher backed by a blocking reqwest client.
+pub struct ReqwestFetcher {
+    client: reqwest::blocking::Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new() -> Result<Self> {
+        Self::with_options(&ClientOptions::default())
+    }
+
+    pub fn with_options(options: &ClientOptions) -> Result<Self> {
+        let mut builder = reqwest::blocking::Client::builder();
+
+        #[cfg(feature = "custom-dns")]
+        if let Some(dns_server) = &options.dns_server {
+            builder = builder.dns_resolver(Arc::new(DnsResolver::new(dns_server.clone())));
+        }
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(bind) = &options.bind {
+            builder = bind.apply(builder)?;
+        }
+
+        Ok(Self::with_client(builder.build()?))
+    }
+
+    pub fn with_client(client: reqwest::blocking::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl FetcherFactory for ClientOptions {
+    fn fetcher(&self, timeout: Option<Duration>) -> Result<Box<dyn HttpFetcher>> {
+        Ok(Box::new(ReqwestFetcher::with_options(&ClientOptions {
+            timeout,
+            ..self.clone()
+        })?))
+    }
+}
+
+impl HttpFetcher for ReqwestFetcher {
+    fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> Result<FetchResponse> {
+        let mut request = self.client.get(url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = request.send()?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|v| (name.as_str().to_ascii_lowercase(), v.to_string()))
+            })
+            .collect();
+        let body = response.bytes()?.to_vec();
+
+        Ok(FetchResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Fetches a URL and returns its body as text, failing on non-success statuses.
+pub fn fetch_url_content(fetcher: &dyn HttpFetcher, url: &str) -> Result<String> {
+    fetch_url_bytes(fetcher, url).map(|body| String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Fetches a URL and returns its raw body, failing on non-success statuses.
+pub fn fetch_url_bytes(fetcher: &dyn HttpFetcher, url: &str) -> Result<Vec<u8>> {
+    fetch_url_bytes_with_hooks(fetcher, url, &[], &Hooks::default())
+}
+
+/// Like `fetch_url_bytes`, checking for cancellation before the request and
+/// once the response has arrived, and reporting the fetch done.
+pub fn fetch_url_bytes_with_hooks(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    headers: &[(&str, &str)],
+    hooks: &Hooks,
+) -> Result<Vec<u8>> {
+    fetch_url_bytes_with_retry(fetcher, url, headers, &RetryPolicy::default(), hooks)
+}
+
+/// Like `fetch_url_bytes_with_hooks`, retrying transient failures as
+/// `retry` says. A retry whose wait would outlast the deadline of `hooks`
+/// isn't attempted; the last failure is returned instead.
+pub fn fetch_url_bytes_with_retry(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    headers: &[(&str, &str)],
+    retry: &RetryPolicy,
+    hooks: &Hooks,
+) -> Result<Vec<u8>> {
+    hooks.check_cancelled()?;
+    hooks.report(Stage::Fetch, 0, Some(1));
+
+    info!("Fetching content from URL...");
+    let response = fetch_with_retry(fetcher, url, headers, retry, hooks)?;
+    if !response.is_success() {
+        anyhow::bail!("Failed to fetch URL: HTTP {}", response.status);
+    }
+
+    hooks.report(Stage::Fetch, 1, Some(1));
+    Ok(response.body)
+}
+
+/// Like `fetch_url_bytes_with_retry`, going through `cache`: the request is
+/// made conditional on the cached copy, which is used when the server says
+/// it hasn't changed (HTTP 304) or fails with a network error or transient
+/// status. Fresh responses replace the cached copy.
+pub fn fetch_url_bytes_cached(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    headers: &[(&str, &str)],
+    retry: &RetryPolicy,
+    cache: &HttpCache,
+    hooks: &Hooks,
+) -> Result<Vec<u8>> {
+    hooks.check_cancelled()?;
+    hooks.report(Stage::Fetch, 0, Some(1));
+
+    info!("Fetching content from URL...");
+    let cached = cache.load(url);
+    let mut request_headers = headers.to_vec();
+    if let Some(cached) = &cached {
+        request_headers.extend(cached.conditional_headers());
+    }
+
+    let body = match fetch_with_retry(fetcher, url, &request_headers, retry, hooks) {
+        Ok(response) if response.status == 304 && cached.is_some() => {
+            info!("{} hasn't changed, using the cached copy", url);
+            cached.map(|cached| cached.body).unwrap_or_default()
+        }
+        Ok(response) if response.is_success() => {
+            if let Err(e) = cache.store(url, &response) {
+                log::warn!("Failed to cache {}: {:#}", url, e);
+            }
+            response.body
+        }
+        result => {
+            let error = match result {
+                Ok(response) if is_transient_status(response.status) => {
+                    anyhow::anyhow!("Failed to fetch URL: HTTP {}", response.status)
+                }
+                // Gone or forbidden: the cached copy is likely stale for good
+                Ok(response) => anyhow::bail!("Failed to fetch URL: HTTP {}", response.status),
+                Err(e) => e,
+            };
+            match cached {
+                Some(cached) if !error.is::<Cancelled>() => {
+                    log::warn!(
+                        "{:#}; using the cached copy from {} minutes ago",
+                        error,
+                        cached.age() / 60
+                    );
+                    cached.body
+                }
+                _ => return Err(error),
+            }
+        }
+    };
+
+    hooks.report(Stage::Fetch, 1, Some(1));
+    Ok(body)
+}
+
+/// Requests `url` until it answers with a non-transient status or `retry`
+/// gives up, returning the last response.
+fn fetch_with_retry(
+    fetcher: &dyn HttpFetcher,
+    url: &str,
+    headers: &[(&str, &str)],
+    retry: &RetryPolicy,
+    hooks: &Hooks,
+) -> Result<FetchResponse> {
+    let mut attempt = 0;
+    loop {
+        let result = fetcher.fetch(url, headers);
+        hooks.check_cancelled()?;
+
+        let failure = match &result {
+            Ok(response) if is_transient_status(response.status) => {
+                format!("HTTP {}", response.status)
+            }
+            Ok(_) => return result,
+            Err(e) => format!("{:#}", e),
+        };
+        let delay = retry.delay(attempt);
+        if attempt == retry.retries || hooks.remaining().is_some_and(|left| left < delay) {
+            return result.with_context(|| format!("Failed to fetch {}", url));
+        }
+
+        attempt += 1;
+        log::warn!(
+            "Fetching {} failed: {} (attempt {} of {}), retrying in {:?}",
+            url,
+            failure,
+            attempt,
+            retry.retries + 1,
+            delay
+        );
+        hooks.sleep(delay)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockFetcher {
+        status: u16,
+        body: &'static str,
+    }
+
+    impl HttpFetcher for MockFetcher {
+        fn fetch(&self, _url: &str, _headers: &[(&str, &str)]) -> Result<FetchResponse> {
+            Ok(FetchResponse {
+                status: self.status,
+                headers: HashMap::from([("etag".to_string(), "\"abc\"".to_string())]),
+                body: self.body.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_fetch_url_content_success() {
+        let fetcher = MockFetcher {
+            status: 200,
+            body: "ss://example",
+        };
+
+        let content = fetch_url_content(&fetcher, "https://example.com/sub").unwrap();
+        assert_eq!(content, "ss://example");
+    }
+
+    #[test]
+    fn test_fetch_url_content_http_error() {
+        let fetcher = MockFetcher {
+            status: 404,
+            body: "not found",
+        };
+
+        let result = fetch_url_content(&fetcher, "https://example.com/sub");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("404"));
+    }
+
+    /// Answers with the next status of `statuses`, then with the last one.
+    struct FlakyFetcher {
+        statuses: Vec<u16>,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl FlakyFetcher {
+        fn new(statuses: &[u16]) -> Self {
+            Self {
+                statuses: statuses.to_vec(),
+                calls: Default::default(),
+            }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(std::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    impl HttpFetcher for FlakyFetcher {
+        fn fetch(&self, _url: &str, _headers: &[(&str, &str)]) -> Result<FetchResponse> {
+            let call = self
+                .calls
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            match self.statuses[call.min(self.statuses.len() - 1)] {
+                0 => anyhow::bail!("connection reset"),
+                status => Ok(FetchResponse {
+                    status,
+                    body: b"ss://example".to_vec(),
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fetch_url_bytes_with_retry() {
+        let retry = RetryPolicy {
+            retries: 2,
+            backoff: Duration::ZERO,
+        };
+        let fetch = |fetcher: &FlakyFetcher| {
+            fetch_url_bytes_with_retry(fetcher, "https://a", &[], &retry, &Hooks::default())
+        };
+
+        let fetcher = FlakyFetcher::new(&[0, 503, 200]);
+        assert_eq!(fetch(&fetcher).unwrap(), b"ss://example");
+        assert_eq!(fetcher.calls(), 3);
+
+        let fetcher = FlakyFetcher::new(&[429]);
+        assert!(fetch(&fetcher).unwrap_err().to_string().contains("429"));
+        assert_eq!(fetcher.calls(), 3);
+
+        // Errors that won't go away aren't retried
+        let fetcher = FlakyFetcher::new(&[404, 200]);
+        assert!(fetch(&fetcher).is_err());
+        assert_eq!(fetcher.calls(), 1);
+
+        // Nor are retries started that would end past the deadline
+        let fetcher = FlakyFetcher::new(&[0, 200]);
+        let hooks =
+            Hooks::default().with_deadline(std::time::Instant::now() + Duration::from_secs(60));
+        let slow = RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_secs(3600),
+        };
+        let error =
+            fetch_url_bytes_with_retry(&fetcher, "https://a", &[], &slow, &hooks).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to fetch https://a: connection reset"
+        );
+        assert_eq!(fetcher.calls(), 1);
+    }
+
+    /// Answers requests with a closure over their headers.
+    struct FnFetcher<F>(F);
+
+    impl<F> HttpFetcher for FnFetcher<F>
+    where
+        F: Fn(&[(&str, &str)]) -> Result<FetchResponse> + Send + Sync,
+    {
+        fn fetch(&self, _url: &str, headers: &[(&str, &str)]) -> Result<FetchResponse> {
+            (self.0)(headers)
+        }
+    }
+
+    #[test]
+    fn test_fetch_url_bytes_cached() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-fetch-{}", std::process::id()));
+        let cache = HttpCache::new(&dir);
+        let retry = RetryPolicy::default();
+        let url = "https://example.com/sub";
+        let fetch = |fetcher: &dyn HttpFetcher| {
+            fetch_url_bytes_cached(fetcher, url, &[], &retry, &cache, &Hooks::default())
+        };
+
+        let server = FnFetcher(|headers: &[(&str, &str)]| {
+            let status = if headers.contains(&("If-None-Match", "\"v1\"")) {
+                304
+            } else {
+                200
+            };
+            Ok(FetchResponse {
+                status,
+                headers: HashMap::from([("etag".to_string(), "\"v1\"".to_string())]),
+                body: if status == 200 {
+                    b"ss://v1".to_vec()
+                } else {
+                    Vec::new()
+                },
+            })
+        });
+        assert_eq!(fetch(&server).unwrap(), b"ss://v1");
+        // Revalidated, served from the cache
+        assert_eq!(fetch(&server).unwrap(), b"ss://v1");
+
+        let status = |status: u16| {
+            FnFetcher(move |_: &[(&str, &str)]| {
+                Ok(FetchResponse {
+                    status,
+                    ..Default::default()
+                })
+            })
+        };
+        let down = FnFetcher(|_: &[(&str, &str)]| anyhow::bail!("connection refused"));
+        assert_eq!(fetch(&down).unwrap(), b"ss://v1");
+        assert_eq!(fetch(&status(502)).unwrap(), b"ss://v1");
+        // A removed feed isn't papered over
+        assert!(fetch(&status(404)).is_err());
+
+        let hooks = Hooks::default();
+        hooks.cancel.cancel();
+        assert!(fetch_url_bytes_cached(&down, url, &[], &retry, &cache, &hooks).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(fetch(&down).is_err());
+        // Without a cached copy a 304 is an error like any other status
+        assert!(fetch(&status(304)).is_err());
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let retry = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        };
+        assert_eq!(retry.delay(0), Duration::from_millis(500));
+        assert_eq!(retry.delay(2), Duration::from_secs(2));
+        // Saturates instead of overflowing
+        assert!(retry.delay(100) > Duration::from_secs(86400 * 365));
+    }
+
+    #[test]
+    fn test_fetch_response_header_case_insensitive() {
+        let response = MockFetcher {
+            status: 200,
+            body: "",
+        }
+        .fetch("https://example.com", &[])
+        .unwrap();
+
+        assert_eq!(response.header("ETag"), Some("\"abc\""));
+    }
+}
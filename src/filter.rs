@@ -0,0 +1,713 @@
+use crate::parser::{ServerConfig, extract_remark, stray_tls_settings};
+use crate::report::{CredentialCluster, SecurityNormalization, SkippedServer};
+use anyhow::Context;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
+use std::str::FromStr;
+
+/// Keeps or drops share URLs based on keywords found in their original remark.
+///
+/// Matching is case-insensitive (Unicode aware) and done on the remark as it
+/// appears in the feed, before the tag is sanitized, so keywords such as
+/// "剩余" or "官网" still match after emojis and CJK text are stripped from tags.
+#[derive(Debug, Clone, Default)]
+pub struct KeywordFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl KeywordFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        Self {
+            include: normalize_keywords(include),
+            exclude: normalize_keywords(exclude),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns true if a server with the given remark should be kept.
+    pub fn matches(&self, remark: Option<&str>) -> bool {
+        let remark = remark.map(str::to_lowercase).unwrap_or_default();
+
+        if self.exclude.iter().any(|k| remark.contains(k.as_str())) {
+            return false;
+        }
+
+        self.include.is_empty() || self.include.iter().any(|k| remark.contains(k.as_str()))
+    }
+
+    /// Filters subscription content line by line, keeping only the URLs whose
+    /// remark passes the filter.
+    pub fn apply(&self, content: &str) -> String {
+        if self.is_empty() {
+            return content.to_string();
+        }
+
+        let mut dropped = 0;
+        let kept: Vec<&str> = content
+            .lines()
+            .filter(|line| {
+                let line = line.trim();
+                if line.is_empty() {
+                    return false;
+                }
+
+                let keep = self.matches(extract_remark(line).as_deref());
+                if !keep {
+                    log::debug!("Filtered out by keyword: {}", line);
+                    dropped += 1;
+                }
+                keep
+            })
+            .collect();
+
+        if dropped > 0 {
+            log::info!("Keyword filter dropped {} servers", dropped);
+        }
+
+        kept.join("\n")
+    }
+}
+
+/// What to do with legacy vmess servers (alterId > 0, MD5 auth), which modern
+/// Xray rejects or runs degraded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LegacyVmessPolicy {
+    /// Keep them silently
+    Keep,
+    /// Remove them and record them as skipped
+    Drop,
+    /// Keep them and log a warning for each
+    #[default]
+    Warn,
+}
+
+/// Applies the legacy vmess policy, returning the remaining servers and the
+/// skip entries for dropped ones.
+pub fn apply_legacy_vmess_policy(
+    servers: Vec<ServerConfig>,
+    policy: LegacyVmessPolicy,
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    let mut kept = Vec::with_capacity(servers.len());
+    let mut skipped = Vec::new();
+
+    for server in servers {
+        let alter_id = match &server {
+            ServerConfig::Vmess { alter_id, .. } if *alter_id > 0 => *alter_id,
+            _ => {
+                kept.push(server);
+                continue;
+            }
+        };
+
+        match policy {
+            LegacyVmessPolicy::Keep => kept.push(server),
+            LegacyVmessPolicy::Warn => {
+                log::warn!(
+                    "{} is a legacy vmess server (alterId {}), Xray may reject it",
+                    server.tag(),
+                    alter_id
+                );
+                kept.push(server);
+            }
+            LegacyVmessPolicy::Drop => skipped.push(SkippedServer {
+                tag: server.tag().to_string(),
+                protocol: server.protocol().to_string(),
+                target: "all".to_string(),
+                reason: format!("legacy vmess (alterId {})", alter_id),
+            }),
+        }
+    }
+
+    (kept, skipped)
+}
+
+/// Resolves a `security=none` link that still carries TLS parameters
+/// (`sni`, `fp`, `alpn`) in `line`, the URL `server` was parsed from.
+///
+/// With `assume_tls` the server is upgraded to tls using those parameters
+/// (SNI defaulting to the address); otherwise they stay dropped, as the
+/// parser left them. Returns the decision for the report, None if the link
+/// was consistent.
+pub fn normalize_security(
+    line: &str,
+    server: &mut ServerConfig,
+    assume_tls: bool,
+) -> Option<SecurityNormalization> {
+    let (params, mut tls) = stray_tls_settings(line)?;
+
+    if assume_tls {
+        match server {
+            ServerConfig::Vless {
+                address,
+                security,
+                tls_settings,
+                ..
+            }
+            | ServerConfig::Trojan {
+                address,
+                security,
+                tls_settings,
+                ..
+            } => {
+                if tls.server_name.is_empty() {
+                    tls.server_name = address.clone();
+                }
+                *security = "tls".to_string();
+                **tls_settings = Some(tls);
+            }
+            ServerConfig::Vmess {
+                address,
+                tls_settings,
+                ..
+            } => {
+                if tls.server_name.is_empty() {
+                    tls.server_name = address.clone();
+                }
+                **tls_settings = Some(tls);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(SecurityNormalization {
+        tag: server.tag().to_string(),
+        protocol: server.protocol().to_string(),
+        action: if assume_tls { "upgraded" } else { "stripped" }.to_string(),
+        params,
+    })
+}
+
+/// Drops ShadowsocksR servers unless `allow` is set. Even when allowed, only
+/// those without protocol plugin and obfs can be expressed in Xray.
+pub fn apply_ssr_policy(
+    servers: Vec<ServerConfig>,
+    allow: bool,
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    if allow {
+        return (servers, Vec::new());
+    }
+
+    let (ssr, kept): (Vec<_>, Vec<_>) = servers
+        .into_iter()
+        .partition(|s| matches!(s, ServerConfig::ShadowsocksR { .. }));
+    let skipped = ssr
+        .iter()
+        .map(|server| SkippedServer {
+            tag: server.tag().to_string(),
+            protocol: server.protocol().to_string(),
+            target: "all".to_string(),
+            reason: "shadowsocksr is skipped without --allow-ssr".to_string(),
+        })
+        .collect();
+
+    (kept, skipped)
+}
+
+/// Keeps servers whose transport (see [`ServerConfig::transport`]) is one of
+/// `transports`, returning the skip entries for the rest. An empty list
+/// keeps everything.
+pub fn filter_by_transport(
+    servers: Vec<ServerConfig>,
+    transports: &[String],
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    if transports.is_empty() {
+        return (servers, Vec::new());
+    }
+
+    let allowed: HashSet<String> = transports
+        .iter()
+        .map(|t| crate::parser::normalize_network(t))
+        .collect();
+    let (kept, dropped): (Vec<_>, Vec<_>) = servers
+        .into_iter()
+        .partition(|s| allowed.contains(s.transport()));
+    let skipped = dropped
+        .iter()
+        .map(|server| SkippedServer {
+            tag: server.tag().to_string(),
+            protocol: server.protocol().to_string(),
+            target: "all".to_string(),
+            reason: format!("transport {} is not in --transports", server.transport()),
+        })
+        .collect();
+
+    (kept, skipped)
+}
+
+/// Presets keeping only the configurations least likely to be blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QualityPreset {
+    /// vless with Reality and the xtls-rprx-vision flow, plus hysteria2
+    RealityVision,
+}
+
+impl QualityPreset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityPreset::RealityVision => "reality-vision",
+        }
+    }
+
+    pub fn accepts(&self, server: &ServerConfig) -> bool {
+        match self {
+            QualityPreset::RealityVision => match server {
+                ServerConfig::Vless { security, flow, .. } => {
+                    security == "reality" && flow.starts_with("xtls-rprx-vision")
+                }
+                ServerConfig::Hysteria2 { .. } => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Keeps the servers `preset` accepts, returning the skip entries for the
+/// rest.
+pub fn filter_by_quality(
+    servers: Vec<ServerConfig>,
+    preset: QualityPreset,
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    let (kept, dropped): (Vec<_>, Vec<_>) = servers.into_iter().partition(|s| preset.accepts(s));
+    let skipped = dropped
+        .iter()
+        .map(|server| SkippedServer {
+            tag: server.tag().to_string(),
+            protocol: server.protocol().to_string(),
+            target: "all".to_string(),
+            reason: format!("not accepted by --quality {}", preset.as_str()),
+        })
+        .collect();
+
+    (kept, skipped)
+}
+
+/// Random sample size and optional seed, parsed from `N` or `N:SEED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub count: usize,
+    pub seed: Option<u64>,
+}
+
+impl FromStr for Sample {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let (count, seed) = match value.split_once(':') {
+            Some((count, seed)) => (count, Some(seed)),
+            None => (value, None),
+        };
+        Ok(Self {
+            count: count
+                .parse()
+                .with_context(|| format!("Invalid sample size: {}", count))?,
+            seed: seed
+                .map(|seed| {
+                    seed.parse()
+                        .with_context(|| format!("Invalid sample seed: {}", seed))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+/// Keeps `count` servers picked at random, in their original order. The same
+/// seed picks the same servers from the same input. Returns the seed used, a
+/// random one if none was given.
+pub fn sample(
+    servers: Vec<ServerConfig>,
+    count: usize,
+    seed: Option<u64>,
+) -> (Vec<ServerConfig>, u64) {
+    let seed = seed.unwrap_or_else(|| RandomState::new().hash_one(servers.len()));
+    if count >= servers.len() {
+        return (servers, seed);
+    }
+
+    // Partial Fisher-Yates shuffle of the indices
+    let mut rng = SplitMix64(seed);
+    let mut indices: Vec<usize> = (0..servers.len()).collect();
+    for i in 0..count {
+        let j = i + (rng.next() % (indices.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    let picked: HashSet<usize> = indices[..count].iter().copied().collect();
+
+    let sampled = servers
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| picked.contains(idx))
+        .map(|(_, server)| server)
+        .collect();
+    (sampled, seed)
+}
+
+/// Small, fast PRNG; sampling needs reproducibility, not cryptographic quality.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+fn normalize_keywords(keywords: &[String]) -> Vec<String> {
+    keywords
+        .iter()
+        .map(|k| k.trim().to_lowercase())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// Groups servers by (protocol, credential) and returns the groups spread over
+/// more than one address, largest first. Servers without a credential (open
+/// socks/http proxies) aren't grouped. Equal-sized groups are ordered by
+/// protocol and unmasked credential, so reports are reproducible.
+pub fn credential_clusters(servers: &[ServerConfig]) -> Vec<CredentialCluster> {
+    let mut groups: BTreeMap<(&str, String), Vec<&ServerConfig>> = BTreeMap::new();
+    for server in servers {
+        let credential = server.credential();
+        if !credential.is_empty() {
+            groups
+                .entry((server.protocol(), credential))
+                .or_default()
+                .push(server);
+        }
+    }
+
+    let mut clusters: Vec<CredentialCluster> = groups
+        .into_iter()
+        .filter_map(|((protocol, credential), members)| {
+            let addresses = members
+                .iter()
+                .map(|s| s.address().to_lowercase())
+                .collect::<HashSet<_>>()
+                .len();
+            (addresses > 1).then(|| CredentialCluster {
+                protocol: protocol.to_string(),
+                credential: mask_credential(&credential),
+                addresses,
+                tags: members.iter().map(|s| s.tag().to_string()).collect(),
+            })
+        })
+        .collect();
+
+    // Stable sort keeps the key order of equal-sized groups
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.tags.len()));
+    clusters
+}
+
+/// Keeps at most `max` servers (the first ones) per (protocol, credential),
+/// so one operator's pool can't dominate the balancers.
+pub fn limit_per_credential(
+    servers: Vec<ServerConfig>,
+    max: usize,
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    let mut seen: HashMap<(&'static str, String), usize> = HashMap::new();
+    let mut kept = Vec::with_capacity(servers.len());
+    let mut skipped = Vec::new();
+
+    for server in servers {
+        let credential = server.credential();
+        if credential.is_empty() {
+            kept.push(server);
+            continue;
+        }
+        let count = seen.entry((server.protocol(), credential)).or_default();
+        *count += 1;
+
+        if *count <= max {
+            kept.push(server);
+        } else {
+            skipped.push(SkippedServer {
+                tag: server.tag().to_string(),
+                protocol: server.protocol().to_string(),
+                target: "all".to_string(),
+                reason: format!("more than {} servers share its credential", max),
+            });
+        }
+    }
+
+    (kept, skipped)
+}
+
+fn mask_credential(credential: &str) -> String {
+    let visible: String = credential.chars().take(4).collect();
+    if credential.chars().count() > 8 {
+        format!("{}…", visible)
+    } else {
+        "…".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_filter_exclude() {
+        let filter = KeywordFilter::new(&[], &["expire".to_string(), "剩余".to_string()]);
+
+        assert!(filter.matches(Some("DE Frankfurt")));
+        assert!(!filter.matches(Some("Expire: 2024-01-01")));
+        assert!(!filter.matches(Some("剩余流量：10GB")));
+        assert!(filter.matches(None));
+    }
+
+    #[test]
+    fn test_keyword_filter_include() {
+        let filter = KeywordFilter::new(&["de".to_string()], &[]);
+
+        assert!(filter.matches(Some("🇩🇪 DE-1")));
+        assert!(!filter.matches(Some("NL-1")));
+        assert!(!filter.matches(None));
+    }
+
+    fn vmess_server(tag: &str, alter_id: u16) -> ServerConfig {
+        ServerConfig::Vmess {
+            tag: tag.to_string(),
+            address: "example.com".to_string(),
+            port: 443,
+            id: "test-uuid".to_string(),
+            alter_id,
+            security: "auto".to_string(),
+            network: "tcp".to_string(),
+            network_settings: None,
+            tls_settings: Box::new(None),
+            allow_insecure: false,
+        }
+    }
+
+    #[test]
+    fn test_legacy_vmess_policy() {
+        let servers = vec![vmess_server("modern", 0), vmess_server("legacy", 64)];
+
+        let (kept, skipped) = apply_legacy_vmess_policy(servers.clone(), LegacyVmessPolicy::Warn);
+        assert_eq!(kept.len(), 2);
+        assert!(skipped.is_empty());
+
+        let (kept, skipped) = apply_legacy_vmess_policy(servers, LegacyVmessPolicy::Drop);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].tag(), "modern");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].tag, "legacy");
+    }
+
+    #[test]
+    fn test_normalize_security() {
+        let line = "vless://uuid@example.com:443?security=none&sni=cdn.example.com&fp=chrome&type=tcp#node";
+        let parse = || crate::parser::parse_servers(line).unwrap().remove(0);
+
+        let mut server = parse();
+        let decision = normalize_security(line, &mut server, false).unwrap();
+        assert_eq!(decision.action, "stripped");
+        assert_eq!(decision.params, ["sni", "fp"]);
+        let ServerConfig::Vless { security, .. } = &server else {
+            unreachable!()
+        };
+        assert_eq!(security, "none");
+
+        let mut server = parse();
+        let decision = normalize_security(line, &mut server, true).unwrap();
+        assert_eq!(decision.action, "upgraded");
+        let ServerConfig::Vless {
+            security,
+            tls_settings,
+            ..
+        } = &server
+        else {
+            unreachable!()
+        };
+        assert_eq!(security, "tls");
+        let tls = tls_settings.as_ref().as_ref().unwrap();
+        assert_eq!(tls.server_name, "cdn.example.com");
+        assert!(!tls.allow_insecure);
+
+        // Consistent links are left alone
+        let line = "trojan://secret@example.com:443?sni=example.com#tls";
+        let mut server = crate::parser::parse_servers(line).unwrap().remove(0);
+        assert!(normalize_security(line, &mut server, true).is_none());
+        let line = "vless://uuid@example.com:443?security=none&type=ws#plain";
+        let mut server = crate::parser::parse_servers(line).unwrap().remove(0);
+        assert!(normalize_security(line, &mut server, true).is_none());
+    }
+
+    fn trojan_server(tag: &str, address: &str, password: &str) -> ServerConfig {
+        ServerConfig::Trojan {
+            tag: tag.to_string(),
+            address: address.to_string(),
+            port: 443,
+            password: password.to_string(),
+            network: "tcp".to_string(),
+            security: "tls".to_string(),
+            tls_settings: Box::new(None),
+            network_settings: None,
+            allow_insecure: false,
+        }
+    }
+
+    #[test]
+    fn test_credential_clusters_and_limit() {
+        let servers = vec![
+            trojan_server("a", "1.1.1.1", "shared-password"),
+            trojan_server("b", "2.2.2.2", "shared-password"),
+            trojan_server("c", "3.3.3.3", "shared-password"),
+            trojan_server("d", "4.4.4.4", "own-password"),
+            // Same credential on one address is not a cluster
+            trojan_server("e", "5.5.5.5", "port-password"),
+            trojan_server("f", "5.5.5.5", "port-password"),
+        ];
+
+        let clusters = credential_clusters(&servers);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].credential, "shar…");
+        assert_eq!(clusters[0].addresses, 3);
+        assert_eq!(clusters[0].tags, ["a", "b", "c"]);
+
+        let (kept, skipped) = limit_per_credential(servers, 2);
+        let tags: Vec<&str> = kept.iter().map(|s| s.tag()).collect();
+        assert_eq!(tags, ["a", "b", "d", "e", "f"]);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].tag, "c");
+    }
+
+    #[test]
+    fn test_credential_clusters_order_is_stable() {
+        // Both credentials mask to the same text
+        let servers = vec![
+            trojan_server("b1", "1.1.1.1", "same-b"),
+            trojan_server("b2", "2.2.2.2", "same-b"),
+            trojan_server("a1", "3.3.3.3", "same-a"),
+            trojan_server("a2", "4.4.4.4", "same-a"),
+        ];
+
+        for _ in 0..10 {
+            let clusters = credential_clusters(&servers);
+            assert_eq!(clusters[0].tags, ["a1", "a2"]);
+            assert_eq!(clusters[1].tags, ["b1", "b2"]);
+        }
+    }
+
+    #[test]
+    fn test_apply_ssr_policy() {
+        let servers = vec![
+            trojan_server("t", "1.1.1.1", "secret"),
+            ServerConfig::ShadowsocksR {
+                tag: "r".to_string(),
+                address: "2.2.2.2".to_string(),
+                port: 8388,
+                method: "aes-256-cfb".to_string(),
+                password: "secret".to_string(),
+                protocol: "origin".to_string(),
+                protocol_param: None,
+                obfs: "plain".to_string(),
+                obfs_param: None,
+            },
+        ];
+
+        let (kept, skipped) = apply_ssr_policy(servers.clone(), false);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(skipped[0].tag, "r");
+
+        let (kept, skipped) = apply_ssr_policy(servers, true);
+        assert_eq!(kept.len(), 2);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_filter_by_transport() {
+        let servers = crate::parser::parse_servers(
+            "vless://uuid@a.example.com:443?type=ws&security=tls#ws\n\
+             vless://uuid@b.example.com:443?type=gun&security=tls#grpc\n\
+             hysteria2://secret@c.example.com:443#hy2",
+        )
+        .unwrap();
+
+        let (kept, skipped) = filter_by_transport(servers.clone(), &[]);
+        assert_eq!((kept.len(), skipped.len()), (3, 0));
+
+        // Aliases are normalized like link `type` values
+        let transports = ["websocket".to_string(), "grpc".to_string()];
+        let (kept, skipped) = filter_by_transport(servers, &transports);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(skipped[0].tag, "hy2");
+        assert_eq!(skipped[0].reason, "transport quic is not in --transports");
+    }
+
+    #[test]
+    fn test_filter_by_quality() {
+        let servers = crate::parser::parse_servers(
+            "vless://uuid@a.example.com:443?security=reality&flow=xtls-rprx-vision&pbk=key&sni=www.example.com#vision\n\
+             vless://uuid@b.example.com:443?security=reality&pbk=key&sni=www.example.com#reality-only\n\
+             vless://uuid@c.example.com:443?security=tls&flow=xtls-rprx-vision#tls-vision\n\
+             hysteria2://secret@d.example.com:443#hy2\n\
+             trojan://secret@e.example.com:443?sni=e.example.com#trojan",
+        )
+        .unwrap();
+
+        let (kept, skipped) = filter_by_quality(servers, QualityPreset::RealityVision);
+        let kept: Vec<_> = kept.iter().map(|s| s.tag()).collect();
+        assert_eq!(kept, ["vision", "hy2"]);
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(
+            skipped[0].reason,
+            "not accepted by --quality reality-vision"
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let servers: Vec<ServerConfig> = (0..100)
+            .map(|i| trojan_server(&format!("s{}", i), "1.1.1.1", &format!("p{}", i)))
+            .collect();
+        let tags = |servers: &[ServerConfig]| -> Vec<String> {
+            servers.iter().map(|s| s.tag().to_string()).collect()
+        };
+
+        let (first, seed) = sample(servers.clone(), 10, Some(42));
+        let (second, _) = sample(servers.clone(), 10, Some(42));
+        assert_eq!(seed, 42);
+        assert_eq!(first.len(), 10);
+        assert_eq!(tags(&first), tags(&second));
+        // Original order is kept
+        let positions: Vec<usize> = first
+            .iter()
+            .map(|s| s.tag()[1..].parse().unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+
+        let (all, _) = sample(servers, 500, None);
+        assert_eq!(all.len(), 100);
+
+        assert_eq!(
+            "10:7".parse::<Sample>().unwrap(),
+            Sample {
+                count: 10,
+                seed: Some(7)
+            }
+        );
+        assert_eq!("10".parse::<Sample>().unwrap().seed, None);
+        assert!("ten".parse::<Sample>().is_err());
+    }
+
+    #[test]
+    fn test_keyword_filter_apply_uses_original_remark() {
+        let content = "\
+ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNzd29yZA@1.2.3.4:8388#%E5%AE%98%E7%BD%91%20example.com
+ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNzd29yZA@5.6.7.8:8388#DE-1
+";
+        let filter = KeywordFilter::new(&[], &["官网".to_string()]);
+        let filtered = filter.apply(content);
+
+        assert_eq!(filtered.lines().count(), 1);
+        assert!(filtered.contains("5.6.7.8"));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::input::Inputs;
+use crate::progress::{Hooks, TimedOut};
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A git repository whose files are read as sources.
+#[derive(Debug, Clone)]
+pub struct GitSource {
+    pub url: String,
+    /// Branch to follow, the remote's default branch if None
+    pub branch: Option<String>,
+    /// Glob selecting files relative to the repository root, e.g. `sub/*.txt`
+    pub path_glob: String,
+}
+
+impl GitSource {
+    /// Directory inside `cache_dir` the repository is checked out to.
+    pub fn checkout_dir(&self, cache_dir: &Path) -> PathBuf {
+        let name: String = self
+            .url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        cache_dir.join(name)
+    }
+
+    /// Brings the checkout up to date: a shallow clone the first time, a
+    /// shallow fetch and hard reset afterwards.
+    pub fn sync(&self, cache_dir: &Path) -> Result<PathBuf> {
+        self.sync_with_hooks(cache_dir, &Hooks::default())
+    }
+
+    /// Like `sync`, killing git and failing with `TimedOut` once the hooks'
+    /// deadline passes. A fetch cut short leaves the previous checkout intact.
+    pub fn sync_with_hooks(&self, cache_dir: &Path, hooks: &Hooks) -> Result<PathBuf> {
+        let dir = self.checkout_dir(cache_dir);
+        let deadline = hooks.remaining().map(|left| Instant::now() + left);
+        let run_git = |dir: Option<&Path>, args: &[&str]| {
+            hooks.check_cancelled()?;
+            run_git_until(dir, args, deadline)
+        };
+
+        if dir.join(".git").is_dir() {
+            let branch = self.branch.as_deref().unwrap_or("HEAD");
+            run_git(Some(&dir), &["fetch", "--depth", "1", "origin", branch])?;
+            run_git(Some(&dir), &["reset", "--hard", "FETCH_HEAD"])?;
+        } else {
+            std::fs::create_dir_all(cache_dir)?;
+            let dir_arg = dir.to_string_lossy();
+            let mut args = vec!["clone", "--depth", "1"];
+            if let Some(branch) = &self.branch {
+                args.extend(["--branch", branch.as_str()]);
+            }
+            args.extend([self.url.as_str(), &dir_arg]);
+            run_git(None, &args)?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Syncs the repository and reads every file matching the glob.
+    pub fn read(&self, cache_dir: &Path, max_size: u64) -> Result<Inputs> {
+        self.read_with_hooks(cache_dir, max_size, &Hooks::default())
+    }
+
+    /// Like `read`, syncing with [`GitSource::sync_with_hooks`].
+    pub fn read_with_hooks(
+        &self,
+        cache_dir: &Path,
+        max_size: u64,
+        hooks: &Hooks,
+    ) -> Result<Inputs> {
+        let dir = self
+            .sync_with_hooks(cache_dir, hooks)
+            .with_context(|| format!("Failed to sync {}", self.url))?;
+        self.read_checkout(&dir, max_size)
+    }
+
+    /// Reads the files of the existing checkout without contacting the remote.
+    pub fn read_offline(&self, cache_dir: &Path, max_size: u64) -> Result<Inputs> {
+        let dir = self.checkout_dir(cache_dir);
+        anyhow::ensure!(
+            dir.join(".git").is_dir(),
+            "No cached checkout of {} in {}; run once without --offline",
+            self.url,
+            cache_dir.display()
+        );
+        self.read_checkout(&dir, max_size)
+    }
+
+    fn read_checkout(&self, dir: &Path, max_size: u64) -> Result<Inputs> {
+        let mut inputs = Inputs::default();
+        for path in matching_files(dir, &self.path_glob)? {
+            let relative = path.strip_prefix(dir).unwrap_or(&path).display();
+            let source = format!("{}!{}", self.url, relative);
+            match std::fs::read(&path) {
+                Ok(bytes) => inputs.add_bytes(&source, &bytes, max_size),
+                Err(e) => inputs.add_source(&source, Err(e.into())),
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+pub(crate) fn run_git(dir: Option<&Path>, args: &[&str]) -> Result<()> {
+    run_git_until(dir, args, None)
+}
+
+/// Like `run_git`, killing git once `deadline` passes.
+fn run_git_until(dir: Option<&Path>, args: &[&str], deadline: Option<Instant>) -> Result<()> {
+    let Some(deadline) = deadline else {
+        return run_git_output(dir, args);
+    };
+
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let mut child = command
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow::Error::new(TimedOut)
+                .context(format!("git {} ran out of time", args.join(" "))));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        anyhow::bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(())
+}
+
+fn run_git_output(dir: Option<&Path>, args: &[&str]) -> Result<()> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command.args(args).output().context("Failed to run git")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Files under `root` (sorted, `.git` excluded) whose path relative to `root`
+/// matches `glob`.
+pub fn matching_files(root: &Path, glob: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.file_name().is_some_and(|name| name == ".git") {
+                continue;
+            }
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                if glob_match(glob, &relative) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Matches a `/`-separated path against a glob where `*` and `?` stay within
+/// one path segment and `**` spans any number of segments.
+pub fn glob_match(glob: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = glob.split('/').filter(|s| !s.is_empty()).collect();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match_segments(&pattern, &segments)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                match_segment(first.as_bytes(), segment.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::DEFAULT_MAX_INPUT_SIZE;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "sub.txt"));
+        assert!(!glob_match("*.txt", "daily/sub.txt"));
+        assert!(glob_match("**/*.txt", "sub.txt"));
+        assert!(glob_match("**/*.txt", "daily/2024/sub.txt"));
+        assert!(glob_match("daily/sub-??.txt", "daily/sub-01.txt"));
+        assert!(!glob_match("daily/sub-??.txt", "daily/sub-1.txt"));
+        assert!(!glob_match("**/*.txt", "README.md"));
+    }
+
+    #[test]
+    fn test_git_source_read() {
+        let root = std::env::temp_dir().join(format!("proxy-harvest-git-{}", std::process::id()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(repo.join("subs")).unwrap();
+        std::fs::write(
+            repo.join("subs/a.txt"),
+            "trojan://secret@example.com:443#a\n",
+        )
+        .unwrap();
+        std::fs::write(
+            repo.join("README.md"),
+            "trojan://secret@example.com:443#readme\n",
+        )
+        .unwrap();
+
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "initial"]);
+
+        let source = GitSource {
+            url: format!("file://{}", repo.display()),
+            branch: Some("main".to_string()),
+            path_glob: "subs/*.txt".to_string(),
+        };
+        let cache = root.join("cache");
+        assert!(source.read_offline(&cache, DEFAULT_MAX_INPUT_SIZE).is_err());
+
+        let inputs = source.read(&cache, DEFAULT_MAX_INPUT_SIZE).unwrap();
+        assert_eq!(inputs.urls.len(), 1);
+        assert!(inputs.urls[0].ends_with("#a"));
+
+        // A second read pulls new commits into the existing checkout
+        std::fs::write(
+            repo.join("subs/b.txt"),
+            "trojan://secret@example.com:443#b\n",
+        )
+        .unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "update"]);
+
+        // Offline reads keep the checkout as it was
+        let offline = source.read_offline(&cache, DEFAULT_MAX_INPUT_SIZE).unwrap();
+        assert_eq!(offline.urls.len(), 1);
+
+        // Out of time: nothing is pulled and the checkout stays readable
+        let expired = Hooks::default().with_deadline(Instant::now());
+        let err = source
+            .read_with_hooks(&cache, DEFAULT_MAX_INPUT_SIZE, &expired)
+            .unwrap_err();
+        assert!(err.is::<TimedOut>());
+        let offline = source.read_offline(&cache, DEFAULT_MAX_INPUT_SIZE).unwrap();
+        assert_eq!(offline.urls.len(), 1);
+
+        let hooks = Hooks::default().with_deadline(Instant::now() + Duration::from_secs(60));
+        let inputs = source
+            .read_with_hooks(&cache, DEFAULT_MAX_INPUT_SIZE, &hooks)
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(inputs.urls.len(), 2);
+    }
+}
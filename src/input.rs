@@ -0,0 +1,898 @@
+#[cfg(feature = "archive")]
+use crate::archive;
+use crate::backup;
+use crate::cache::HttpCache;
+use crate::convert::{self, server_to_url};
+use crate::detect::{InputFormat, decode_base64, detect};
+use crate::fetch;
+use crate::parser::{ServerConfig, clash, decode_html_entities};
+use crate::progress::Hooks;
+use crate::report::{SkippedSource, catch_panic};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Sources larger than this many bytes are skipped by default.
+pub const DEFAULT_MAX_INPUT_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Share URLs read from one or more sources, plus the sources that had to be
+/// skipped along the way.
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    pub urls: Vec<String>,
+    /// Source each entry of `urls` was read from
+    pub sources: Vec<String>,
+    pub skipped: Vec<SkippedSource>,
+}
+
+impl Inputs {
+    /// Adds the URLs of one source, or records why it was skipped.
+    pub fn add_source(&mut self, source: &str, result: Result<Vec<String>>) {
+        match result {
+            Ok(urls) => {
+                log::info!("Read {} URLs from {}", urls.len(), source);
+                self.sources
+                    .extend(std::iter::repeat_n(source.to_string(), urls.len()));
+                self.urls.extend(urls);
+            }
+            Err(e) => {
+                log::warn!("Skipping {}: {:#}", source, e);
+                self.skipped.push(SkippedSource {
+                    source: source.to_string(),
+                    reason: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    /// Adds a source given as raw bytes. Zip and tar(.gz) archives are
+    /// expanded, each file inside becoming its own `archive!file` source.
+    pub fn add_bytes(&mut self, source: &str, bytes: &[u8], max_size: u64) {
+        self.add_bytes_as(source, bytes, max_size, None);
+    }
+
+    /// [`Inputs::add_bytes`], reading the content (or every file of an
+    /// archive) as `format` when given instead of detecting it.
+    pub fn add_bytes_as(
+        &mut self,
+        source: &str,
+        bytes: &[u8],
+        max_size: u64,
+        format: Option<InputFormat>,
+    ) {
+        #[cfg(feature = "archive")]
+        if let Some(kind) = archive::detect(bytes) {
+            match archive::extract(bytes, kind, max_size) {
+                Ok(entries) => {
+                    for entry in entries {
+                        let name = if entry.name.is_empty() {
+                            source.to_string()
+                        } else {
+                            format!("{}!{}", source, entry.name)
+                        };
+                        let urls = entry.data.and_then(|data| {
+                            read_source_isolated(&String::from_utf8_lossy(&data), max_size, format)
+                        });
+                        self.add_source(&name, urls);
+                    }
+                }
+                Err(e) => self.add_source(source, Err(e)),
+            }
+            return;
+        }
+
+        self.add_source(
+            source,
+            read_source_isolated(&String::from_utf8_lossy(bytes), max_size, format),
+        );
+    }
+
+    /// Maps each URL to the first source it was read from.
+    pub fn source_map(&self) -> HashMap<&str, &str> {
+        let mut map = HashMap::with_capacity(self.urls.len());
+        for (url, source) in self.urls.iter().zip(&self.sources) {
+            map.entry(url.as_str()).or_insert(source.as_str());
+        }
+        map
+    }
+
+    /// Fails if every source was skipped, so a run never replaces working
+    /// configs with empty ones because its inputs were unusable.
+    pub fn ensure_usable(&self) -> Result<()> {
+        if self.urls.is_empty() && !self.skipped.is_empty() {
+            anyhow::bail!("No usable sources, all {} were skipped", self.skipped.len());
+        }
+        Ok(())
+    }
+}
+
+/// A subscription to fetch and how to treat it. On the command line it is
+/// written `URL` or `LABEL=URL`, the label prefixing the tags of the feed's
+/// servers with `LABEL-`; `--sources` manifests set the other options.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceUrl {
+    pub url: String,
+    /// Prepended to the tags of this source's servers
+    pub tag_prefix: Option<String>,
+    /// Request headers, e.g. the `User-Agent` a panel expects
+    pub headers: Vec<(String, String)>,
+    /// Read the content as this format instead of detecting it
+    pub format: Option<InputFormat>,
+    /// Keep only servers of these protocols; all when empty
+    pub protocols: Vec<String>,
+}
+
+/// One `--url` value: a single `[LABEL=]URL`, or a comma-separated list of
+/// them. The value is only split when every part is a URL, so a comma in a
+/// URL's query (`?types=vless,trojan`) stays part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceUrlList(pub Vec<SourceUrl>);
+
+impl FromStr for SourceUrlList {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let parts: Vec<&str> = value.split(',').collect();
+        if parts.len() > 1 && parts.iter().all(|part| part.contains("://")) {
+            parts
+                .into_iter()
+                .map(str::parse)
+                .collect::<Result<_>>()
+                .map(Self)
+        } else {
+            Ok(Self(vec![value.parse()?]))
+        }
+    }
+}
+
+impl FromStr for SourceUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        anyhow::ensure!(!value.is_empty(), "Empty URL");
+        // A URL's own '=' comes after its "://"
+        let labelled = value.split_once('=').filter(|(label, url)| {
+            !label.is_empty()
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && url.contains("://")
+        });
+        Ok(match labelled {
+            Some((label, url)) => SourceUrl {
+                url: url.to_string(),
+                tag_prefix: Some(format!("{}-", label)),
+                ..Default::default()
+            },
+            None => SourceUrl {
+                url: value.to_string(),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+/// One server list: `--url`, an entry of `--sources`, `--input` or `--stdin`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputSource {
+    Url(SourceUrl),
+    File(PathBuf),
+    Stdin,
+}
+
+impl InputSource {
+    /// How the source shows up in logs, the report and the index.
+    pub fn name(&self) -> String {
+        match self {
+            InputSource::Url(url) => url.url.clone(),
+            InputSource::File(path) => path.display().to_string(),
+            InputSource::Stdin => "stdin".to_string(),
+        }
+    }
+
+    /// Prefix for the tags of this source's servers, see [`SourceUrl`].
+    pub fn tag_prefix(&self) -> Option<&str> {
+        match self {
+            InputSource::Url(url) => url.tag_prefix.as_deref(),
+            InputSource::File(_) | InputSource::Stdin => None,
+        }
+    }
+
+    /// The format to read the content as, if not left to detection.
+    pub fn format(&self) -> Option<InputFormat> {
+        match self {
+            InputSource::Url(url) => url.format,
+            InputSource::File(_) | InputSource::Stdin => None,
+        }
+    }
+
+    /// Whether this source keeps servers of `protocol`.
+    pub fn accepts(&self, protocol: &str) -> bool {
+        match self {
+            InputSource::Url(url) => {
+                url.protocols.is_empty() || url.protocols.iter().any(|p| p == protocol)
+            }
+            InputSource::File(_) | InputSource::Stdin => true,
+        }
+    }
+
+    /// Whether servers read from `source` came from here, directly or from
+    /// a file in an archive it held (`NAME!file`).
+    pub fn is_source_of(&self, source: &str) -> bool {
+        source
+            .strip_prefix(self.name().as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('!'))
+    }
+
+    /// Reads the raw content. URLs are fetched with `client`, its timeout
+    /// cut to what is left of the `hooks` budget.
+    pub fn read(
+        &self,
+        client: &fetch::ClientOptions,
+        hooks: &Hooks,
+        max_size: u64,
+    ) -> Result<Vec<u8>> {
+        self.read_with(client, client, hooks, max_size)
+    }
+
+    /// Like `read`, fetching URLs with a fetcher from `fetchers` instead of
+    /// the reqwest client `client` describes. Retries, the download cache
+    /// and offline mode still follow `client`.
+    pub fn read_with(
+        &self,
+        client: &fetch::ClientOptions,
+        fetchers: &dyn fetch::FetcherFactory,
+        hooks: &Hooks,
+        max_size: u64,
+    ) -> Result<Vec<u8>> {
+        match self {
+            InputSource::Url(url) if client.offline => read_cached(&url.url, client),
+            InputSource::Url(url) => {
+                let timeout = match (client.timeout, hooks.remaining()) {
+                    (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+                    (timeout, remaining) => timeout.or(remaining),
+                };
+                let fetcher = fetchers.fetcher(timeout)?;
+                let headers: Vec<(&str, &str)> = url
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect();
+                match &client.cache_dir {
+                    Some(dir) => fetch::fetch_url_bytes_cached(
+                        fetcher.as_ref(),
+                        &url.url,
+                        &headers,
+                        &client.retry,
+                        &HttpCache::new(dir),
+                        hooks,
+                    ),
+                    None => fetch::fetch_url_bytes_with_retry(
+                        fetcher.as_ref(),
+                        &url.url,
+                        &headers,
+                        &client.retry,
+                        hooks,
+                    ),
+                }
+            }
+            InputSource::File(path) => read_file(path, max_size),
+            InputSource::Stdin => {
+                // One byte over the limit is enough for add_bytes to refuse it
+                let mut bytes = Vec::new();
+                std::io::stdin()
+                    .lock()
+                    .take(max_size.saturating_add(1))
+                    .read_to_end(&mut bytes)
+                    .context("Failed to read stdin")?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+/// The cached copy of `url` in `client.cache_dir`, for offline runs.
+fn read_cached(url: &str, client: &fetch::ClientOptions) -> Result<Vec<u8>> {
+    let dir = client
+        .cache_dir
+        .as_deref()
+        .context("Offline runs read URLs from a download cache, but none is set")?;
+    let cached = HttpCache::new(dir)
+        .load(url)
+        .with_context(|| format!("No cached copy of {} in {}", url, dir.display()))?;
+    log::info!(
+        "Offline: using the cached copy of {} from {} minutes ago",
+        url,
+        cached.age() / 60
+    );
+    Ok(cached.body)
+}
+
+/// Reads `sources` on up to `concurrency` threads, so one slow mirror
+/// doesn't hold up the others. Results come back in the order of `sources`;
+/// those not started before `hooks` stop fail with `TimedOut` or `Cancelled`,
+/// and a source whose read panics fails with the panic message.
+pub fn read_sources(
+    sources: &[InputSource],
+    client: &fetch::ClientOptions,
+    hooks: &Hooks,
+    max_size: u64,
+    concurrency: usize,
+) -> Vec<Result<Vec<u8>>> {
+    read_sources_with(sources, client, client, hooks, max_size, concurrency)
+}
+
+/// Like `read_sources`, fetching URLs through `fetchers`, see
+/// [`InputSource::read_with`].
+pub fn read_sources_with(
+    sources: &[InputSource],
+    client: &fetch::ClientOptions,
+    fetchers: &dyn fetch::FetcherFactory,
+    hooks: &Hooks,
+    max_size: u64,
+    concurrency: usize,
+) -> Vec<Result<Vec<u8>>> {
+    let next = AtomicUsize::new(0);
+    let read_next = || {
+        let mut results = Vec::new();
+        loop {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(source) = sources.get(idx) else {
+                return results;
+            };
+            let result = hooks.check_cancelled().and_then(|_| {
+                log::info!("Reading servers from: {}", source.name());
+                catch_panic(|| source.read_with(client, fetchers, hooks, max_size))
+                    .unwrap_or_else(|message| Err(anyhow::anyhow!("Panicked: {}", message)))
+            });
+            results.push((idx, result));
+        }
+    };
+
+    // Slots by source index, so a lost result can never shift the others
+    let mut slots: Vec<Option<Result<Vec<u8>>>> = sources.iter().map(|_| None).collect();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency.clamp(1, sources.len().max(1)))
+            .map(|_| scope.spawn(read_next))
+            .collect();
+        for worker in workers {
+            for (idx, result) in worker.join().unwrap_or_default() {
+                slots[idx] = Some(result);
+            }
+        }
+    });
+    slots
+        .into_iter()
+        .map(|slot| slot.unwrap_or_else(|| Err(anyhow::anyhow!("Reader thread panicked"))))
+        .collect()
+}
+
+/// Reads every file in `dir` (sorted by name) and collects the share URLs
+/// found in them.
+///
+/// Archives are expanded. Files that are too large, binary or in a format that
+/// can't be turned into share URLs are skipped instead of failing the whole run.
+pub fn read_input_dir(dir: &Path, max_size: u64) -> Result<Inputs> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read input directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut inputs = Inputs::default();
+    for path in &paths {
+        let source = path.display().to_string();
+        match read_file(path, max_size) {
+            Ok(bytes) => inputs.add_bytes(&source, &bytes, max_size),
+            Err(e) => inputs.add_source(&source, Err(e)),
+        }
+    }
+
+    Ok(inputs)
+}
+
+fn read_file(path: &Path, max_size: u64) -> Result<Vec<u8>> {
+    let size = std::fs::metadata(path)?.len();
+    if size > max_size {
+        anyhow::bail!("Too large ({} bytes, limit {})", size, max_size);
+    }
+
+    Ok(std::fs::read(path)?)
+}
+
+/// Checks that source content is reasonably sized text and turns it into
+/// share URLs, reading it as `format` or detecting the format if None.
+pub fn read_source(
+    content: &str,
+    max_size: u64,
+    format: Option<InputFormat>,
+) -> Result<Vec<String>> {
+    if content.len() as u64 > max_size {
+        anyhow::bail!("Too large ({} bytes, limit {})", content.len(), max_size);
+    }
+    if is_binary(content) {
+        anyhow::bail!("Looks like binary data");
+    }
+
+    match format {
+        Some(format) => to_url_list_as(content, format),
+        None => to_url_list(content),
+    }
+}
+
+/// [`read_source`], with a panic on malformed content turned into an error
+/// so the source is skipped instead of aborting the run.
+fn read_source_isolated(
+    content: &str,
+    max_size: u64,
+    format: Option<InputFormat>,
+) -> Result<Vec<String>> {
+    catch_panic(|| read_source(content, max_size, format))
+        .unwrap_or_else(|message| Err(anyhow::anyhow!("Panicked: {}", message)))
+}
+
+/// True if the content has NUL bytes or is largely undecodable as UTF-8.
+fn is_binary(content: &str) -> bool {
+    if content.contains('\0') {
+        return true;
+    }
+
+    let sample: Vec<char> = content.chars().take(4096).collect();
+    let invalid = sample
+        .iter()
+        .filter(|&&c| c == char::REPLACEMENT_CHARACTER)
+        .count();
+    invalid * 10 > sample.len()
+}
+
+/// Turns subscription content into share URLs, using `detect` to find out
+/// what format it is in.
+///
+/// Understands plain URL lists, base64 encoded URL lists, SIP008 JSON,
+/// v2rayNG backups, NekoBox profiles, Clash YAML configs and RSS/Atom feeds.
+/// Empty content yields no URLs.
+pub fn to_url_list(content: &str) -> Result<Vec<String>> {
+    let Some(detection) = detect(content) else {
+        if content.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        anyhow::bail!("Unrecognized format");
+    };
+    log::debug!(
+        "Detected {} input (confidence {:.2})",
+        detection.format.name(),
+        detection.confidence
+    );
+    to_url_list_as(content, detection.format)
+}
+
+/// Turns subscription content known to be in `format` into share URLs.
+pub fn to_url_list_as(content: &str, format: InputFormat) -> Result<Vec<String>> {
+    match format {
+        InputFormat::UrlList => Ok(url_lines(content)),
+        InputFormat::Base64 => Ok(url_lines(
+            &decode_base64(content).context("Invalid base64")?,
+        )),
+        InputFormat::Feed => Ok(feed_urls(content)),
+        InputFormat::Sip008 => {
+            let json: Value = serde_json::from_str(content.trim_start_matches('\u{feff}'))?;
+            Ok(sip008_urls(&json))
+        }
+        InputFormat::V2rayNg => {
+            let json: Value = serde_json::from_str(content.trim_start_matches('\u{feff}'))?;
+            Ok(backup::v2rayng_urls(&json))
+        }
+        InputFormat::NekoBox => {
+            let json: Value = serde_json::from_str(content.trim_start_matches('\u{feff}'))?;
+            Ok(backup::nekobox_urls(&json))
+        }
+        InputFormat::Ndjson => Ok(convert::servers_from_ndjson(content)?
+            .iter()
+            .filter_map(server_to_url)
+            .collect()),
+        InputFormat::Yaml => Ok(clash::parse_clash(content)?
+            .iter()
+            .filter_map(server_to_url)
+            .collect()),
+        format => anyhow::bail!("Unsupported {} input", format.name()),
+    }
+}
+
+/// Returns the share URL lines of `content`.
+fn url_lines(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.contains("://"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Extracts share URLs from the entries of an RSS or Atom feed, e.g. the
+/// posts of a node-announcement blog.
+fn feed_urls(content: &str) -> Vec<String> {
+    let entry_re = Regex::new(r"(?s)<(?:item|entry)[\s>].*?</(?:item|entry)>").unwrap();
+    let url_re = Regex::new(
+        r#"(?:ss|ssr|vmess|vless|trojan|socks|socks5|naive\+https|hysteria2|hy2|hysteria|tuic|wireguard|wg|brook|mierus)://[^\s<>"']+"#,
+    )
+    .unwrap();
+
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    for entry in entry_re.find_iter(content) {
+        let mut text = entry.as_str().replace("<![CDATA[", "").replace("]]>", "");
+        // Entry content is HTML escaped into XML, so entities can be nested
+        for _ in 0..3 {
+            let decoded = decode_html_entities(&text);
+            if decoded == text {
+                break;
+            }
+            text = decoded;
+        }
+
+        for url in url_re.find_iter(&text) {
+            let url = url.as_str().to_string();
+            if seen.insert(url.clone()) {
+                urls.push(url);
+            }
+        }
+    }
+
+    urls
+}
+
+/// Converts the servers of a SIP008 document to `ss://` URLs.
+fn sip008_urls(json: &Value) -> Vec<String> {
+    json["servers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|server| {
+            let server = ServerConfig::Shadowsocks {
+                tag: server["remarks"].as_str().unwrap_or_default().to_string(),
+                address: server["server"].as_str()?.to_string(),
+                port: u16::try_from(server["server_port"].as_u64()?).ok()?,
+                method: server["method"].as_str()?.to_string(),
+                password: server["password"].as_str()?.to_string(),
+                udp_over_tcp: false,
+                plugin: None,
+            };
+            server_to_url(&server)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::prelude::BASE64_STANDARD;
+
+    const URL_LIST: &str = "\
+ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYXNzd29yZA@1.2.3.4:8388#DE-1
+trojan://secret@example.com:443?security=tls#trojan-1
+";
+
+    #[test]
+    fn test_to_url_list_plain_and_base64() {
+        let plain = to_url_list(URL_LIST).unwrap();
+        assert_eq!(plain.len(), 2);
+
+        let encoded = BASE64_STANDARD.encode(URL_LIST);
+        assert_eq!(to_url_list(&encoded).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_to_url_list_sip008() {
+        let content = r#"{
+            "version": 1,
+            "servers": [
+                {
+                    "server": "1.2.3.4",
+                    "server_port": 8388,
+                    "password": "secret",
+                    "method": "aes-256-gcm",
+                    "remarks": "DE-1"
+                }
+            ]
+        }"#;
+
+        let urls = to_url_list(content).unwrap();
+        assert_eq!(urls.len(), 1);
+        assert!(urls[0].starts_with("ss://"));
+        assert!(urls[0].contains("@1.2.3.4:8388"));
+    }
+
+    #[test]
+    fn test_to_url_list_feed() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Free nodes</title>
+    <link>https://example.com/</link>
+    <item>
+      <title>Nodes for today</title>
+      <description><![CDATA[<p>vless://uuid@example.com:443?type=ws&security=tls#DE-1</p>]]></description>
+    </item>
+    <item>
+      <description>&lt;p&gt;trojan://secret@example.com:443?security=tls&amp;amp;sni=example.com#NL-1&lt;/p&gt;
+        vless://uuid@example.com:443?type=ws&amp;security=tls#DE-1</description>
+    </item>
+  </channel>
+</rss>"#;
+
+        let urls = to_url_list(content).unwrap();
+        assert_eq!(
+            urls,
+            vec![
+                "vless://uuid@example.com:443?type=ws&security=tls#DE-1",
+                "trojan://secret@example.com:443?security=tls&sni=example.com#NL-1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_url_list_unsupported() {
+        assert!(to_url_list("port: 7890\nmode: rule\n").is_err());
+        assert!(to_url_list(r#"{"outbounds": []}"#).is_err());
+        assert!(to_url_list("<html><body>Expired</body></html>").is_err());
+        assert!(to_url_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_to_url_list_clash() {
+        let clash = "\
+mixed-port: 7890
+proxies:
+  - name: de-1
+    type: trojan
+    server: 203.0.113.1
+    port: 443
+    password: secret
+    sni: example.com
+  - name: socks
+    type: socks5
+    server: 203.0.113.2
+    port: 1080
+proxy-groups:
+  - name: auto
+    type: url-test
+    proxies: [de-1]
+";
+        let urls = to_url_list(clash).unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(to_url_list_as(clash, InputFormat::Yaml).unwrap(), urls);
+        assert!(to_url_list_as(clash, InputFormat::Sip008).is_err());
+        assert!(urls[0].starts_with("trojan://secret@203.0.113.1:443?"));
+        assert!(urls[0].ends_with("#de-1"));
+    }
+
+    #[test]
+    fn test_parse_source_url() {
+        let plain: SourceUrl = "https://example.com/sub?a=b".parse().unwrap();
+        assert_eq!(plain.tag_prefix, None);
+        assert_eq!(plain.url, "https://example.com/sub?a=b");
+
+        let labelled: SourceUrl = "feed-1=https://example.com/sub?a=b".parse().unwrap();
+        assert_eq!(labelled.tag_prefix.as_deref(), Some("feed-1-"));
+        assert_eq!(labelled.url, "https://example.com/sub?a=b");
+
+        assert!("".parse::<SourceUrl>().is_err());
+
+        let list: SourceUrlList = "ru=https://a.example/sub,https://b.example/sub"
+            .parse()
+            .unwrap();
+        let urls: Vec<&str> = list.0.iter().map(|u| u.url.as_str()).collect();
+        assert_eq!(urls, ["https://a.example/sub", "https://b.example/sub"]);
+        assert_eq!(list.0[0].tag_prefix.as_deref(), Some("ru-"));
+
+        // A comma inside a URL doesn't split it
+        let list: SourceUrlList = "http://host/sub?types=vless,trojan".parse().unwrap();
+        assert_eq!(list.0.len(), 1);
+        assert_eq!(list.0[0].url, "http://host/sub?types=vless,trojan");
+    }
+
+    #[test]
+    fn test_input_source() {
+        let url = InputSource::Url(SourceUrl {
+            protocols: vec!["vless".to_string()],
+            .."eu=https://example.com/sub?a=b".parse().unwrap()
+        });
+        assert_eq!(url.name(), "https://example.com/sub?a=b");
+        assert_eq!(url.tag_prefix(), Some("eu-"));
+        assert!(url.accepts("vless"));
+        assert!(!url.accepts("trojan"));
+        assert!(url.is_source_of("https://example.com/sub?a=b"));
+        assert!(url.is_source_of("https://example.com/sub?a=b!list.txt"));
+        assert!(!url.is_source_of("https://example.com/sub?a=bc"));
+        assert_eq!(InputSource::Stdin.name(), "stdin");
+
+        let path =
+            std::env::temp_dir().join(format!("proxy-harvest-source-{}", std::process::id()));
+        std::fs::write(&path, URL_LIST).unwrap();
+        let file = InputSource::File(path.clone());
+        assert_eq!(file.tag_prefix(), None);
+        assert!(file.accepts("trojan"));
+        let options = fetch::ClientOptions::default();
+        let hooks = Hooks::default();
+        assert_eq!(
+            file.read(&options, &hooks, 1024).unwrap(),
+            URL_LIST.as_bytes()
+        );
+        assert!(file.read(&options, &hooks, 10).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_sources() {
+        let dir =
+            std::env::temp_dir().join(format!("proxy-harvest-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sources: Vec<_> = (0..5)
+            .map(|i| {
+                let path = dir.join(format!("{}.txt", i));
+                if i != 3 {
+                    std::fs::write(&path, format!("source {}", i)).unwrap();
+                }
+                InputSource::File(path)
+            })
+            .collect();
+        let options = fetch::ClientOptions::default();
+
+        let results = read_sources(&sources, &options, &Hooks::default(), 1024, 2);
+        assert_eq!(results.len(), 5);
+        for (i, result) in results.iter().enumerate() {
+            match result {
+                Ok(bytes) => assert_eq!(bytes, format!("source {}", i).as_bytes()),
+                Err(_) => assert_eq!(i, 3),
+            }
+        }
+
+        let hooks = Hooks::default();
+        hooks.cancel.cancel();
+        let results = read_sources(&sources, &options, &hooks, 1024, 2);
+        assert!(results.iter().all(|r| {
+            r.as_ref()
+                .is_err_and(|e| e.is::<crate::progress::Cancelled>())
+        }));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_sources_with_fetchers() {
+        struct Fetcher;
+
+        impl fetch::HttpFetcher for Fetcher {
+            fn fetch(&self, url: &str, _headers: &[(&str, &str)]) -> Result<fetch::FetchResponse> {
+                Ok(fetch::FetchResponse {
+                    status: 200,
+                    headers: Default::default(),
+                    body: format!("fetched {}", url).into_bytes(),
+                })
+            }
+        }
+
+        struct Factory(std::sync::Mutex<Vec<Option<std::time::Duration>>>);
+
+        impl fetch::FetcherFactory for Factory {
+            fn fetcher(
+                &self,
+                timeout: Option<std::time::Duration>,
+            ) -> Result<Box<dyn fetch::HttpFetcher>> {
+                self.0.lock().unwrap().push(timeout);
+                Ok(Box::new(Fetcher))
+            }
+        }
+
+        let sources: Vec<_> = ["http://127.0.0.1:9/a", "http://127.0.0.1:9/b"]
+            .into_iter()
+            .map(|url| {
+                InputSource::Url(SourceUrl {
+                    url: url.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let options = fetch::ClientOptions {
+            timeout: Some(std::time::Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let factory = Factory(Default::default());
+
+        let results = read_sources_with(&sources, &options, &factory, &Hooks::default(), 1024, 2);
+        let bodies: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            bodies,
+            [
+                b"fetched http://127.0.0.1:9/a".to_vec(),
+                b"fetched http://127.0.0.1:9/b".to_vec()
+            ]
+        );
+        assert_eq!(
+            *factory.0.lock().unwrap(),
+            [Some(std::time::Duration::from_secs(5)); 2]
+        );
+    }
+
+    #[test]
+    fn test_read_offline() {
+        let dir =
+            std::env::temp_dir().join(format!("proxy-harvest-offline-{}", std::process::id()));
+        let source = InputSource::Url(SourceUrl {
+            // Nothing listens here; an offline read must not try
+            url: "http://127.0.0.1:9/sub".to_string(),
+            ..Default::default()
+        });
+        let options = fetch::ClientOptions {
+            cache_dir: Some(dir.clone()),
+            offline: true,
+            ..Default::default()
+        };
+        let hooks = Hooks::default();
+
+        let error = source.read(&options, &hooks, 1024).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("No cached copy of http://127.0.0.1:9/sub")
+        );
+
+        let response = fetch::FetchResponse {
+            status: 200,
+            headers: Default::default(),
+            body: URL_LIST.as_bytes().to_vec(),
+        };
+        HttpCache::new(&dir)
+            .store("http://127.0.0.1:9/sub", &response)
+            .unwrap();
+        assert_eq!(
+            source.read(&options, &hooks, 1024).unwrap(),
+            URL_LIST.as_bytes()
+        );
+
+        let options = fetch::ClientOptions {
+            cache_dir: None,
+            ..options
+        };
+        assert!(source.read(&options, &hooks, 1024).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_input_dir() {
+        let dir = std::env::temp_dir().join(format!("proxy-harvest-input-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), URL_LIST).unwrap();
+        std::fs::write(dir.join("b.b64"), BASE64_STANDARD.encode(URL_LIST)).unwrap();
+        std::fs::write(dir.join("c.yaml"), "port: 7890\nmode: rule\n").unwrap();
+
+        std::fs::write(dir.join("d.bin"), b"\x7fELF\x02\x01\x01\x00\xff\xfe").unwrap();
+
+        let inputs = read_input_dir(&dir, DEFAULT_MAX_INPUT_SIZE).unwrap();
+        assert_eq!(inputs.urls.len(), 4);
+        assert!(inputs.sources[0].ends_with("a.txt"));
+        assert!(inputs.sources[3].ends_with("b.b64"));
+        assert_eq!(inputs.skipped.len(), 2);
+        assert!(inputs.skipped[0].source.ends_with("c.yaml"));
+        assert!(inputs.skipped[1].reason.contains("binary"));
+
+        let inputs = read_input_dir(&dir, 16).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(inputs.urls.is_empty());
+        assert!(inputs.skipped[0].reason.contains("Too large"));
+        assert!(inputs.ensure_usable().is_err());
+    }
+}
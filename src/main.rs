@@ -1,25 +1,487 @@
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod backup;
+pub mod bind;
+pub mod cache;
 pub mod config;
+pub mod convert;
+pub mod deploy;
+pub mod detect;
+#[cfg(feature = "custom-dns")]
+pub mod dns;
+pub mod doctor;
+pub mod fetch;
+pub mod filter;
+pub mod git;
+pub mod input;
 pub mod parser;
+pub mod progress;
+pub mod report;
+pub mod rewrite;
+pub mod risk;
+pub mod scan;
+pub mod sources;
+pub mod tags;
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
 use log::info;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable either the `native-tls` or the `rustls` feature");
 
 const OUTBOUND_FILE_NAME: &str = "04_outbounds.json";
 const ROUTING_FILE_NAME: &str = "05_routing.json";
+const INDEX_FILE_NAME: &str = "index.json";
+const HYSTERIA2_BRIDGE_FILE_NAME: &str = "hysteria2-bridges.json";
+const ANNOTATE_DIR_NAME: &str = "annotated";
+const BIN_NAME: &str = env!("CARGO_PKG_NAME");
 
 #[derive(Parser, Debug)]
 #[command(name = "xray-config-generator")]
 #[command(about = "Generate Xray configuration files from VPN server URLs", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("source").required(true).multiple(true)))]
 struct Args {
-    /// URL to fetch the server list from
-    #[arg(short, long)]
-    url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// URL to fetch the server list from; repeat it or give a comma-separated
+    /// list to merge several (only split when every part is a URL). `LABEL=URL` prefixes the tags of that feed's
+    /// servers with `LABEL-`
+    #[arg(short, long, group = "source", value_name = "[LABEL=]URL")]
+    url: Vec<input::SourceUrlList>,
+
+    /// TOML manifest of subscriptions to fetch, each with its own options
+    /// (tag prefix, enabled flag, protocol filter, headers, format)
+    #[arg(long, value_name = "FILE", group = "source")]
+    sources: Option<PathBuf>,
+
+    /// Read a server list from a local file, e.g. a saved subscription;
+    /// may be repeated and combined with --url and --stdin
+    #[arg(long, value_name = "FILE", group = "source")]
+    input: Vec<PathBuf>,
+
+    /// Read a server list piped to stdin, alongside --url and --input
+    #[arg(long, group = "source")]
+    stdin: bool,
+
+    /// Read the server list from every file in this directory instead of a URL
+    /// (plain or base64 URL lists, SIP008 JSON, detected per file)
+    #[arg(
+        long,
+        value_name = "DIR",
+        group = "source",
+        conflicts_with_all = ["url", "sources", "input", "stdin", "git_repo"]
+    )]
+    input_dir: Option<PathBuf>,
+
+    /// Read the server list from files of a git repository, shallow-cloned on
+    /// the first run and pulled afterwards
+    #[arg(
+        long,
+        value_name = "URL",
+        group = "source",
+        conflicts_with_all = ["url", "sources", "input", "stdin"]
+    )]
+    git_repo: Option<String>,
+
+    /// Branch of --git-repo to follow (the remote default if not set)
+    #[arg(long, requires = "git_repo")]
+    git_branch: Option<String>,
+
+    /// Glob selecting the files of --git-repo to read
+    #[arg(long, value_name = "GLOB", default_value = "**/*.txt")]
+    git_path: String,
+
+    /// Directory --git-repo checkouts are kept in between runs
+    #[arg(long, value_name = "DIR", default_value = "./.git-sources")]
+    git_cache: PathBuf,
 
     /// Output directory for generated config files
     #[arg(short, long, default_value = "./configs")]
     output: PathBuf,
+
+    /// Don't use the network: read --git-repo from its cached checkout
+    /// without pulling, and --url and --sources feeds from --cache-dir,
+    /// e.g. to regenerate configs with different options
+    #[arg(long, conflicts_with = "cf_ips")]
+    offline: bool,
+
+    /// Guarantee byte-identical files for identical inputs and options by
+    /// refusing steps whose result depends on live measurements
+    /// (--cf-ips, --reverse-lookup)
+    #[arg(long, conflicts_with = "cf_ips")]
+    reproducible: bool,
+
+    /// Keep only the configurations least likely to be DPI-blocked;
+    /// reality-vision: vless+reality with the vision flow, and hysteria2
+    #[arg(long, value_enum, value_name = "PRESET")]
+    quality: Option<filter::QualityPreset>,
+
+    /// Keep only servers using one of these transports, e.g. ws,grpc,tcp
+    /// (also xhttp, kcp, quic, http, udp; hysteria and tuic count
+    /// as quic)
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    transports: Vec<String>,
+
+    /// Treat vless/trojan/vmess links with security=none but sni/fp set as
+    /// TLS; without it those parameters are dropped. Either way the servers
+    /// are listed in the report
+    #[arg(long)]
+    assume_tls_when_sni: bool,
+
+    /// Keep only servers whose original remark contains one of these keywords
+    #[arg(long = "include-keyword", value_name = "KEYWORD")]
+    include_keywords: Vec<String>,
+
+    /// Drop servers whose original remark contains one of these keywords
+    #[arg(long = "exclude-keyword", value_name = "KEYWORD")]
+    exclude_keywords: Vec<String>,
+
+    /// Skip sources larger than this many bytes
+    #[arg(long, value_name = "BYTES", default_value_t = input::DEFAULT_MAX_INPUT_SIZE)]
+    max_input_size: u64,
+
+    /// Time budget for fetching and checking, e.g. 10m; stages still running
+    /// when it runs out are cut short and configs are generated from what
+    /// they finished
+    #[arg(long, value_name = "DURATION", value_parser = progress::parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Time limit for downloading sources (--url, --git-repo), e.g. 30s
+    #[arg(long, value_name = "DURATION", value_parser = progress::parse_duration)]
+    fetch_timeout: Option<Duration>,
+
+    /// Time limit for each single download of --url and --sources, e.g. 20s;
+    /// within --fetch-timeout (reqwest's 30s if not set)
+    #[arg(long, value_name = "DURATION", value_parser = progress::parse_duration)]
+    request_timeout: Option<Duration>,
+
+    /// How many times to retry a failed download of --url and --sources;
+    /// network errors, timeouts, HTTP 408, 429 and 5xx are retried
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    retries: u32,
+
+    /// Wait before the first retry, doubled for every retry after it
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = progress::parse_duration)]
+    retry_backoff: Duration,
+
+    /// Keep downloads of --url and --sources here between runs: unchanged
+    /// feeds are revalidated with ETag/Last-Modified, and the cached copy is
+    /// used when the server can't be reached
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// How many --url, --sources and --input sources to read at once
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    fetch_concurrency: usize,
+
+    /// Time limit for probing servers (--cf-ips, --reverse-lookup), e.g. 2m
+    #[arg(long, value_name = "DURATION", value_parser = progress::parse_duration)]
+    check_timeout: Option<Duration>,
+
+    /// Local address or interface (e.g. 192.0.2.10 or wan2) to download
+    /// --url sources from, so on multi-WAN routers they go over that uplink
+    #[arg(long, value_name = "ADDR|IFACE")]
+    fetch_bind: Option<bind::Bind>,
+
+    /// Local address or interface to probe servers (--cf-ips) from
+    #[arg(long, value_name = "ADDR|IFACE")]
+    check_bind: Option<bind::Bind>,
+
+    /// Prefix prepended to every generated server tag, e.g. `harvest-`
+    #[arg(long, value_name = "PREFIX", default_value = "")]
+    tag_prefix: String,
+
+    /// Tag generated servers must not use (e.g. a hand-written outbound);
+    /// colliding servers are renamed. `direct` and `block` are always reserved
+    #[arg(long = "reserved-tag", value_name = "TAG")]
+    reserved_tags: Vec<String>,
+
+    /// JSON file mapping server addresses (exact or `*.suffix`) to the
+    /// address to connect to instead; SNI and WebSocket Host keep the original
+    #[arg(long, value_name = "PATH")]
+    address_map: Option<PathBuf>,
+
+    /// File with Cloudflare IPs (one per line); the one with the fastest TCP
+    /// connect is used as the address of Cloudflare-fronted servers
+    #[arg(long, value_name = "PATH")]
+    cf_ips: Option<PathBuf>,
+
+    /// Port probed when scanning --cf-ips
+    #[arg(long, value_name = "PORT", default_value_t = 443)]
+    cf_scan_port: u16,
+
+    /// Split outbounds across numbered files (04a_outbounds.json,
+    /// 04b_outbounds.json, …) of at most this many outbounds each
+    #[arg(long, value_name = "N")]
+    chunk_size: Option<usize>,
+
+    /// JSON file with extra routing rules keyed on source IP, user email or
+    /// source port, placed ahead of the balancer rules
+    #[arg(long, value_name = "PATH")]
+    rules: Option<PathBuf>,
+
+    /// Keep at most this many servers sharing one UUID/password, so a single
+    /// operator's pool isn't over-weighted in the balancers
+    #[arg(long, value_name = "N")]
+    max_per_credential: Option<usize>,
+
+    /// Randomly keep N servers (after filtering, before any network checks)
+    /// for quick runs over huge feeds; `N:SEED` picks the same servers every run
+    #[arg(long, value_name = "N[:SEED]")]
+    sample: Option<filter::Sample>,
+
+    /// Score servers for abuse risk (credential reuse, blocklisted IPs) and
+    /// drop those rated above this level
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    max_risk: Option<risk::RiskLevel>,
+
+    /// IP/CIDR blocklist (one per line, e.g. Spamhaus DROP) used by --max-risk
+    #[arg(long, value_name = "PATH", requires = "max_risk")]
+    risk_blocklist: Option<PathBuf>,
+
+    /// How to handle legacy vmess servers (alterId > 0)
+    #[arg(long, value_enum, default_value_t = filter::LegacyVmessPolicy::Warn)]
+    legacy_vmess: filter::LegacyVmessPolicy,
+
+    /// Keep ShadowsocksR servers; those without protocol plugin and obfs
+    /// become shadowsocks outbounds, the rest are still skipped
+    #[arg(long)]
+    allow_ssr: bool,
+
+    /// `level` set on every generated user/server entry
+    #[arg(long, default_value_t = 0)]
+    user_level: u32,
+
+    /// Set each user's email to its outbound tag for Xray per-user stats
+    #[arg(long)]
+    user_email: bool,
+
+    /// Drop an outbound connection once sent data stays unacknowledged for
+    /// this many milliseconds (sockopt tcpUserTimeout, Linux TCP_USER_TIMEOUT)
+    #[arg(long, value_name = "MS")]
+    tcp_user_timeout: Option<u32>,
+
+    /// How outbounds resolve their server's domain (sockopt domainStrategy)
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    domain_strategy: Option<config::DomainStrategy>,
+
+    /// Race IPv4 and IPv6 connections, starting the next attempt after this
+    /// many milliseconds (sockopt happyEyeballs)
+    #[arg(long, value_name = "MS")]
+    happy_eyeballs_delay: Option<u32>,
+
+    /// Try IPv6 first when racing connections
+    #[arg(long, requires = "happy_eyeballs_delay")]
+    prefer_ipv6: bool,
+
+    /// How the generated balancers pick an outbound
+    #[arg(long, value_enum, value_name = "STRATEGY", default_value = "leastping")]
+    balancer_strategy: config::routing::BalancerStrategy,
+
+    /// leastload: number of best outbounds to spread load across
+    #[arg(long, value_name = "N")]
+    balancer_expected: Option<u32>,
+
+    /// leastload: never pick outbounds slower than this, e.g. 1s
+    #[arg(long, value_name = "DURATION")]
+    balancer_max_rtt: Option<String>,
+
+    /// leastload: tolerated share of failed health checks, 0 to 1
+    #[arg(long, value_name = "RATIO")]
+    balancer_tolerance: Option<f64>,
+
+    /// leastload: latency tier such as 300ms; repeat for several tiers
+    #[arg(long, value_name = "DURATION")]
+    balancer_baseline: Vec<String>,
+
+    /// leastload: weight outbounds by tag, TAG=VALUE or regexp:PATTERN=VALUE;
+    /// may be repeated
+    #[arg(long, value_name = "MATCH=VALUE")]
+    balancer_cost: Vec<config::routing::BalancerCost>,
+
+    /// Number server tags per balancer (`px03-de-1`) so no tag is a prefix
+    /// of another; Xray matches balancer selectors by prefix, so `de-1` would
+    /// otherwise also pick `de-10`
+    #[arg(long)]
+    exact_selectors: bool,
+
+    /// Tag prefix of a balancer's servers with --exact-selectors, as
+    /// CATEGORY=PREFIX (cloudflare, warp, proxy); defaults are cf, warp, px
+    #[arg(long, value_name = "CATEGORY=PREFIX", requires = "exact_selectors")]
+    selector_prefix: Vec<config::routing::SelectorPrefix>,
+
+    /// Export hysteria2 servers, which Xray can't dial itself, as SOCKS
+    /// outbounds to local hysteria2 clients on consecutive ports from PORT;
+    /// what each client must run is written to hysteria2-bridges.json
+    #[arg(long, value_name = "PORT", conflicts_with = "hysteria2_template")]
+    hysteria2_bridge: Option<u16>,
+
+    /// Export hysteria2 servers with this JSON outbound template, using
+    /// ${tag}, ${address}, ${port}, ${password}, ${sni}, ${insecure}, ${obfs}
+    /// and ${obfs_password}; without this or --hysteria2-bridge they're skipped
+    #[arg(long, value_name = "FILE")]
+    hysteria2_template: Option<PathBuf>,
+
+    /// Directory with geosite .dat files; when set, categories referenced by
+    /// routing rules are checked to exist before anything is written
+    #[arg(long)]
+    geosite_dir: Option<PathBuf>,
+
+    /// DNS server for all lookups instead of the system resolver:
+    /// IP[:port], tls://IP#name or https://IP#name
+    #[cfg(feature = "custom-dns")]
+    #[arg(long, value_name = "SERVER")]
+    dns: Option<dns::DnsServer>,
+
+    /// Look up reverse DNS names of servers given as bare IPs, to tag them
+    /// by hostname and spot CDN-fronted ones (uses --dns if set)
+    #[cfg(feature = "custom-dns")]
+    #[arg(long, conflicts_with_all = ["offline", "reproducible"])]
+    reverse_lookup: bool,
+
+    /// fsync generated files and the output directory after writing
+    #[arg(long)]
+    fsync: bool,
+
+    /// Permission bits (octal) for generated files
+    #[arg(long, value_name = "MODE", default_value = "600", value_parser = parse_octal)]
+    file_mode: u32,
+
+    /// Write minified single-line JSON, smaller and faster to parse on routers
+    #[arg(long)]
+    compact: bool,
+
+    /// Print generated files to stdout instead of writing them to --output,
+    /// one JSON line per file: {"file": "04_outbounds.json", "content": "..."}.
+    /// For containers populating a shared volume themselves
+    #[arg(long, conflicts_with = "git_commit")]
+    stdout: bool,
+
+    /// Umask (octal) applied when creating the output directory
+    #[arg(long, value_name = "UMASK", value_parser = parse_octal)]
+    umask: Option<u32>,
+
+    /// Also write .jsonc variants of the generated files, with comments on
+    /// where each outbound came from and what each rule does
+    #[arg(long)]
+    annotate: bool,
+
+    /// Directory for the --annotate files [default: annotated/ in --output].
+    /// Xray loads .jsonc from its confdir too, so keep them out of it
+    #[arg(long, value_name = "DIR", requires = "annotate")]
+    annotate_dir: Option<PathBuf>,
+
+    /// Also write a wg-quick .conf for every WireGuard server into this
+    /// directory, for kernel WireGuard without Xray
+    #[arg(long, value_name = "DIR")]
+    wg_quick_dir: Option<PathBuf>,
+
+    /// Also write index.json, mapping every outbound tag to its protocol,
+    /// address, country, balancer, source and measured latency
+    #[arg(long)]
+    index: bool,
+
+    /// Write every parsed server to this path as NDJSON, one server per line,
+    /// before any filter or check. Such a file is itself an input source, to
+    /// split parsing, checking and generation across machines
+    #[arg(long, value_name = "PATH")]
+    emit_parsed: Option<PathBuf>,
+
+    /// Write a JSON run report (parsed and skipped servers) to this path
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Commit the output directory to the git repository it lives in after
+    /// a successful run (nothing is committed if no file changed)
+    #[arg(long)]
+    git_commit: bool,
+
+    /// Commit message for --git-commit; {parsed}, {servers} and {skipped}
+    /// are replaced with the run's numbers
+    #[arg(long, value_name = "TEMPLATE", default_value = deploy::DEFAULT_COMMIT_MESSAGE)]
+    git_commit_message: String,
+
+    /// Push the commit made by --git-commit to the branch's upstream
+    #[arg(long, requires = "git_commit", conflicts_with = "offline")]
+    git_push: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert an existing config into another format
+    Convert {
+        /// Format of the input file
+        #[arg(long, value_enum)]
+        from: ConvertFrom,
+
+        /// Format to print
+        #[arg(long, value_enum)]
+        to: ConvertTo,
+
+        /// Input file
+        input: PathBuf,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page (roff)
+    Manpage,
+    /// Check the environment: output directory, xray binary, geodata, DNS
+    /// and sources, printing fixes for anything that's wrong
+    Doctor(DoctorArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DoctorArgs {
+    /// Output directory to check for write access
+    #[arg(short, long, default_value = "./configs")]
+    output: PathBuf,
+
+    /// Subscription URL to check (can be repeated)
+    #[arg(short, long)]
+    url: Vec<String>,
+
+    /// Xray binary to run, e.g. /usr/local/bin/xray
+    #[arg(long, value_name = "PATH")]
+    xray: Option<PathBuf>,
+
+    /// Directory with geosite.dat and geoip.dat
+    #[arg(long, value_name = "DIR")]
+    geosite_dir: Option<PathBuf>,
+
+    /// Warn about geodata files older than this many days
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    geodata_max_age: u64,
+
+    /// DNS server used to fetch the sources, as for the generator
+    #[cfg(feature = "custom-dns")]
+    #[arg(long, value_name = "SERVER")]
+    dns: Option<dns::DnsServer>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ConvertFrom {
+    /// Xray JSON config (full config or outbounds file)
+    Xray,
+    /// Servers written by --emit-parsed, one JSON object per line
+    Ndjson,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ConvertTo {
+    /// Share URLs, one per line
+    Urls,
+    /// One JSON object per server, readable as an input source
+    Ndjson,
 }
 
 #[allow(dead_code)]
@@ -28,47 +490,730 @@ fn main() -> Result<()> {
 
     let args = Args::parse();
 
+    match &args.command {
+        Some(Command::Convert { from, to, input }) => return run_convert(*from, *to, input),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut cli(), BIN_NAME, &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Manpage) => {
+            clap_mangen::Man::new(cli()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+        Some(Command::Doctor(doctor)) => return run_doctor(doctor),
+        None => {}
+    }
+
     info!("Starting Xray config generator");
-    info!("Fetching servers from: {}", args.url);
+    let budget = progress::Budget::new(args.max_runtime);
+    let mut timeouts = Vec::new();
     info!("Output directory: {}", args.output.display());
 
     // Create output directory if it doesn't exist
-    std::fs::create_dir_all(&args.output)?;
+    let _lock = if args.stdout {
+        None
+    } else {
+        config::create_output_dir(&args.output, args.umask)?;
+        Some(config::lock_output_dir(&args.output)?)
+    };
+    let write_options = config::WriteOptions {
+        stdout: args.stdout,
+        fsync: args.fsync,
+        file_mode: Some(args.file_mode),
+        compact: args.compact,
+    };
+
+    anyhow::ensure!(
+        !args.offline
+            || args.cache_dir.is_some()
+            || (args.url.is_empty() && args.sources.is_none()),
+        "--offline reads --url and --sources from --cache-dir, which isn't set"
+    );
+    let manifest = match &args.sources {
+        Some(path) => sources::load_sources(path)?,
+        None => Vec::new(),
+    };
+    let sources: Vec<_> = args
+        .url
+        .iter()
+        .flat_map(|list| list.0.iter().cloned())
+        .chain(manifest)
+        .map(input::InputSource::Url)
+        .chain(args.input.iter().cloned().map(input::InputSource::File))
+        .chain(args.stdin.then_some(input::InputSource::Stdin))
+        .collect();
+    let inputs = if let Some(input_dir) = &args.input_dir {
+        info!("Reading servers from: {}", input_dir.display());
+        input::read_input_dir(input_dir, args.max_input_size)?
+    } else if let Some(git_repo) = &args.git_repo {
+        info!("Reading servers from git repository: {}", git_repo);
+        let source = git::GitSource {
+            url: git_repo.clone(),
+            branch: args.git_branch.clone(),
+            path_glob: args.git_path.clone(),
+        };
+        if args.offline {
+            source.read_offline(&args.git_cache, args.max_input_size)?
+        } else {
+            let hooks = budget.stage_hooks(args.fetch_timeout);
+            match source.read_with_hooks(&args.git_cache, args.max_input_size, &hooks) {
+                Err(e) if e.is::<progress::TimedOut>() => {
+                    timeouts.push(report::TimedOutStage {
+                        stage: "fetch".to_string(),
+                        detail: format!("{:#}; used the cached checkout", e),
+                    });
+                    source
+                        .read_offline(&args.git_cache, args.max_input_size)
+                        .context("git sync ran out of time")?
+                }
+                result => result?,
+            }
+        }
+    } else {
+        // Sources that fail are skipped like unusable files of --input-dir
+        let hooks = budget.stage_hooks(args.fetch_timeout);
+        let client = fetch::ClientOptions {
+            #[cfg(feature = "custom-dns")]
+            dns_server: args.dns.clone(),
+            timeout: args.request_timeout,
+            bind: args.fetch_bind.clone(),
+            retry: fetch::RetryPolicy {
+                retries: args.retries,
+                backoff: args.retry_backoff,
+            },
+            cache_dir: args.cache_dir.clone(),
+            offline: args.offline,
+        };
+        let results = input::read_sources(
+            &sources,
+            &client,
+            &hooks,
+            args.max_input_size,
+            args.fetch_concurrency,
+        );
+
+        let mut inputs = input::Inputs::default();
+        let mut read = 0;
+        for (source, result) in sources.iter().zip(results) {
+            let name = source.name();
+            match result {
+                Ok(content) => {
+                    read += 1;
+                    info!("Read {} bytes from {}", content.len(), name);
+                    inputs.add_bytes_as(&name, &content, args.max_input_size, source.format());
+                }
+                Err(e) => inputs.add_source(&name, Err(e)),
+            }
+        }
+        if hooks.is_timed_out() {
+            timeouts.push(report::TimedOutStage {
+                stage: "fetch".to_string(),
+                detail: format!(
+                    "read {} of {} sources before the deadline",
+                    read,
+                    sources.len()
+                ),
+            });
+        }
+        inputs
+    };
+    inputs.ensure_usable()?;
+    let content = inputs.urls.join("\n");
+
+    // Filter servers by their original remarks
+    let keyword_filter = filter::KeywordFilter::new(&args.include_keywords, &args.exclude_keywords);
+    let content = keyword_filter.apply(&content);
+
+    // Parse server URLs, remembering the source of each one
+    let source_map = inputs.source_map();
+    let mut origins = HashMap::new();
+    let (parsed, panics) = parser::parse_servers_checked(&content);
+    let mut security_normalized = Vec::new();
+    let mut source_stats = report::source_stats(&inputs.sources);
+    let mut not_accepted = Vec::new();
+    let servers: Vec<_> = parsed
+        .into_iter()
+        .filter_map(|(line, mut server)| {
+            let source = source_map.get(line).copied();
+            let stats =
+                source.and_then(|source| source_stats.iter_mut().find(|s| s.source == source));
+            let input =
+                source.and_then(|source| sources.iter().find(|input| input.is_source_of(source)));
+            if let Some(input) = input.filter(|input| !input.accepts(server.protocol())) {
+                if let Some(stats) = stats {
+                    stats.filtered += 1;
+                }
+                not_accepted.push(report::SkippedServer {
+                    tag: server.tag().to_string(),
+                    protocol: server.protocol().to_string(),
+                    target: "all".to_string(),
+                    reason: format!("protocol not enabled for source {}", input.name()),
+                });
+                return None;
+            }
+            if let Some(stats) = stats {
+                stats.parsed += 1;
+            }
+
+            security_normalized.extend(filter::normalize_security(
+                line,
+                &mut server,
+                args.assume_tls_when_sni,
+            ));
+            if let Some(prefix) = input.and_then(input::InputSource::tag_prefix) {
+                server.set_tag(format!("{}{}", prefix, server.tag()));
+            }
+            origins
+                .entry(server.tag().to_string())
+                .or_insert_with(|| config::annotate::Origin {
+                    source: source.map(str::to_string),
+                    latency: None,
+                    country: parser::extract_remark(line)
+                        .as_deref()
+                        .and_then(tags::flag_country),
+                    checked: false,
+                });
+            Some(server)
+        })
+        .collect();
+
+    if let Some(path) = &args.emit_parsed {
+        // Goes to its own file even with --stdout
+        let options = config::WriteOptions {
+            stdout: false,
+            ..write_options
+        };
+        config::write_config_file(path, &options, |out| convert::write_ndjson(out, &servers))?;
+        info!(
+            "Wrote {} parsed servers to {}",
+            servers.len(),
+            path.display()
+        );
+    }
+
+    let mut run_report = report::RunReport {
+        parsed: servers.len(),
+        transports: report::count_transports(&servers),
+        skipped_sources: inputs.skipped,
+        panics,
+        timeouts,
+        security_normalized,
+        sources: source_stats,
+        skipped: not_accepted,
+        ..Default::default()
+    };
+
+    let (servers, dropped) = filter::apply_legacy_vmess_policy(servers, args.legacy_vmess);
+    run_report.skipped.extend(dropped);
+    let (servers, dropped) = filter::apply_ssr_policy(servers, args.allow_ssr);
+    run_report.skipped.extend(dropped);
+    let (mut servers, dropped) = filter::filter_by_transport(servers, &args.transports);
+    run_report.skipped.extend(dropped);
+    if let Some(preset) = args.quality {
+        let (kept, dropped) = filter::filter_by_quality(servers, preset);
+        info!(
+            "Quality preset {} kept {} servers",
+            preset.as_str(),
+            kept.len()
+        );
+        servers = kept;
+        run_report.skipped.extend(dropped);
+    }
+
+    run_report.credential_clusters = filter::credential_clusters(&servers);
+    if let Some(max_risk) = args.max_risk {
+        let blocklist = args
+            .risk_blocklist
+            .as_deref()
+            .map(risk::Blocklist::load)
+            .transpose()?;
+        let (kept, dropped) = risk::filter_by_risk(servers, max_risk, blocklist.as_ref());
+        info!(
+            "Risk filter dropped {} servers above {} risk",
+            dropped.len(),
+            max_risk.as_str()
+        );
+        servers = kept;
+        run_report.skipped.extend(dropped);
+    }
+    if let Some(max) = args.max_per_credential {
+        let (kept, dropped) = filter::limit_per_credential(servers, max);
+        servers = kept;
+        run_report.skipped.extend(dropped);
+    }
+
+    if let Some(sample) = args.sample {
+        anyhow::ensure!(
+            !args.reproducible || sample.seed.is_some(),
+            "--reproducible needs a seeded --sample, e.g. --sample {}:1",
+            sample.count
+        );
+        let total = servers.len();
+        let (sampled, seed) = filter::sample(servers, sample.count, sample.seed);
+        servers = sampled;
+        info!(
+            "Sampled {} of {} servers (seed {})",
+            servers.len(),
+            total,
+            seed
+        );
+    }
+
+    #[allow(unused_mut)]
+    let mut reverse_names: HashMap<std::net::IpAddr, String> = HashMap::new();
+    #[cfg(feature = "custom-dns")]
+    if args.reverse_lookup {
+        let ips: Vec<_> = servers.iter().filter_map(tags::ip_address).collect();
+        info!("Looking up reverse DNS for {} servers...", ips.len());
+        let hooks = budget.stage_hooks(args.check_timeout);
+        reverse_names = dns::reverse_lookup_with_hooks(&ips, args.dns.as_ref(), &hooks)?;
+        if hooks.is_timed_out() {
+            run_report.timeouts.push(report::TimedOutStage {
+                stage: "reverse-lookup".to_string(),
+                detail: format!("stopped after {} names", reverse_names.len()),
+            });
+        }
+        let renamed = tags::apply_hostnames(&mut servers, &reverse_names);
+        tags::rename_keys(&mut origins, &renamed);
+        info!(
+            "Found {} reverse DNS names, retagged {} servers",
+            reverse_names.len(),
+            renamed.len()
+        );
+    }
+
+    tags::apply_prefix(&mut servers, &args.tag_prefix);
+    // From here on origins follow the servers under their full tag
+    let mut origins: HashMap<String, config::annotate::Origin> = origins
+        .into_iter()
+        .map(|(tag, origin)| (format!("{}{}", args.tag_prefix, tag), origin))
+        .collect();
+    let renamed = tags::protect_reserved(&mut servers, &args.reserved_tags);
+    tags::rename_keys(&mut origins, &renamed);
+
+    if let Some(path) = &args.cf_ips {
+        let candidates = scan::load_candidates(path)?;
+        info!("Probing {} Cloudflare IPs...", candidates.len());
+        let hooks = budget.stage_hooks(args.check_timeout);
+        let fastest = scan::fastest_ip_with_hooks(
+            &candidates,
+            args.cf_scan_port,
+            Duration::from_secs(2),
+            3,
+            args.check_bind.as_ref(),
+            &hooks,
+        )?;
+        if hooks.is_timed_out() {
+            run_report.timeouts.push(report::TimedOutStage {
+                stage: "cf-scan".to_string(),
+                detail: "used the fastest IP measured before the deadline".to_string(),
+            });
+        }
+        match fastest {
+            Some((ip, rtt)) => {
+                info!("Fastest Cloudflare IP: {} ({} ms)", ip, rtt.as_millis());
+                for server in servers.iter().filter(|s| s.is_cloudflare()) {
+                    if let Some(origin) = origins.get_mut(server.tag()) {
+                        origin.latency = Some(rtt);
+                        origin.checked = true;
+                    }
+                }
+                let rewritten = scan::cloudflare_address_map(&servers, ip).apply(&mut servers);
+                info!("Rewrote the address of {} Cloudflare servers", rewritten);
+            }
+            None => {
+                log::warn!("No Cloudflare IP answered, keeping original addresses");
+                // Running out of time isn't a failed probe
+                if !hooks.is_timed_out() {
+                    for server in servers.iter().filter(|s| s.is_cloudflare()) {
+                        if let Some(origin) = origins.get_mut(server.tag()) {
+                            origin.checked = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &args.address_map {
+        let rewritten = rewrite::AddressMap::load(path)?.apply(&mut servers);
+        info!("Rewrote the address of {} servers", rewritten);
+    }
+
+    let mut generator_options = config::GeneratorOptions {
+        user_level: args.user_level,
+        user_email: args.user_email,
+        custom_rules: match &args.rules {
+            Some(path) => config::routing::load_rules(path)?,
+            None => Vec::new(),
+        },
+        dialer: config::DialerOptions {
+            domain_strategy: args.domain_strategy,
+            tcp_user_timeout_ms: args.tcp_user_timeout,
+            happy_eyeballs_delay_ms: args.happy_eyeballs_delay,
+            prefer_ipv6: args.prefer_ipv6,
+        },
+        cdn_tags: servers
+            .iter()
+            .filter(|server| {
+                tags::ip_address(server)
+                    .and_then(|ip| reverse_names.get(&ip))
+                    .is_some_and(|name| parser::is_cdn_host(name))
+            })
+            .map(|server| server.tag().to_string())
+            .collect(),
+        balancer: config::routing::StrategyOptions {
+            strategy: args.balancer_strategy,
+            expected: args.balancer_expected,
+            max_rtt: args.balancer_max_rtt.clone(),
+            tolerance: args.balancer_tolerance,
+            baselines: args.balancer_baseline.clone(),
+            costs: args.balancer_cost.clone(),
+        },
+        hysteria2: match &args.hysteria2_template {
+            Some(path) => config::outbound::Hysteria2Mode::Template(
+                config::outbound::load_hysteria2_template(path)?,
+            ),
+            None => config::outbound::Hysteria2Mode::Skip,
+        },
+    };
+
+    // Tags servers had before --exact-selectors renamed them, by new tag
+    let mut renamed = HashMap::new();
+    if args.exact_selectors {
+        let prefixes = config::routing::SelectorPrefixes::with(&args.selector_prefix)?;
+        renamed = config::routing::apply_selector_prefixes(
+            &mut servers,
+            &mut generator_options,
+            &prefixes,
+            &args.tag_prefix,
+        )?;
+    }
+    if let Some(base_port) = args.hysteria2_bridge {
+        generator_options.hysteria2 = config::outbound::Hysteria2Mode::bridge(&servers, base_port)?;
+    }
+
+    // Generate and write configuration files. They are staged and moved into
+    // the output directory together once all of them are written
+    let staging = if args.stdout {
+        None
+    } else {
+        Some(config::Staging::new(&args.output)?)
+    };
+    let out_dir = staging
+        .as_ref()
+        .map_or(args.output.as_path(), config::Staging::dir);
+    let outbounds_path = out_dir.join(OUTBOUND_FILE_NAME);
+    let routing_path = out_dir.join(ROUTING_FILE_NAME);
+
+    // Outbounds go first: servers whose generation panics are only known
+    // once they are streamed out, and must not reach routing or the index
+    let (mut written, panicked) = match args.chunk_size {
+        Some(chunk_size) => config::outbound::write_outbounds_chunked(
+            &outbounds_path,
+            &servers,
+            chunk_size,
+            &generator_options,
+            &write_options,
+        )?,
+        None => {
+            let panicked = config::outbound::write_outbounds(
+                &outbounds_path,
+                &servers,
+                &generator_options,
+                &write_options,
+            )?;
+            (vec![outbounds_path.clone()], panicked)
+        }
+    };
+    if !panicked.is_empty() {
+        let tags: HashSet<&str> = panicked.iter().map(|p| p.tag.as_str()).collect();
+        servers.retain(|server| !tags.contains(server.tag()));
+    }
+    run_report
+        .panics
+        .extend(panicked.into_iter().map(|p| p.panic));
+
+    for target in config::capability::Target::ALL {
+        run_report
+            .skipped
+            .extend(config::capability::skipped_servers_with(
+                *target,
+                &servers,
+                &generator_options,
+            ));
+    }
+
+    run_report.log_summary();
+    info!("Capability matrix:");
+    for line in config::capability::capability_matrix_with(&servers, &generator_options) {
+        info!("  {}", line);
+    }
+
+    let routing = config::routing::generate_routing_with(&servers, &generator_options)?;
+    let outbound_tags: HashSet<String> = tags::BUILTIN_RESERVED_TAGS
+        .iter()
+        .map(|tag| tag.to_string())
+        .chain(args.reserved_tags.iter().cloned())
+        .chain(
+            servers
+                .iter()
+                .filter(|s| {
+                    config::capability::is_supported_with(
+                        config::capability::Target::Xray,
+                        s,
+                        &generator_options,
+                    )
+                })
+                .map(|s| s.tag().to_string()),
+        )
+        .collect();
+    config::lint::check_routing(&routing, &outbound_tags)?;
+    if let Some(geosite_dir) = &args.geosite_dir {
+        config::geosite::validate(&routing, geosite_dir)?;
+    }
 
-    // Fetch the content from URL
-    let content = fetch_url_content(&args.url)?;
-    info!("Fetched {} bytes of data", content.len());
+    config::write_config_with(&routing_path, &routing, &write_options)?;
+    written.push(routing_path.clone());
+    if let Some(bridges) = config::outbound::hysteria2_bridges(&servers, &generator_options) {
+        let bridges_path = out_dir.join(HYSTERIA2_BRIDGE_FILE_NAME);
+        config::write_config_with(&bridges_path, &bridges, &write_options)?;
+        written.push(bridges_path);
+    }
 
-    // Parse server URLs
-    let servers = parser::parse_servers(&content)?;
-    info!("Parsed {} servers", servers.len());
+    // Origins are keyed by the tag servers had before --exact-selectors
+    let origins: HashMap<String, config::annotate::Origin> = servers
+        .iter()
+        .filter_map(|server| {
+            let tag = renamed
+                .get(server.tag())
+                .map_or(server.tag(), String::as_str);
+            Some((server.tag().to_string(), origins.get(tag)?.clone()))
+        })
+        .collect();
 
-    // Generate configurations
-    let outbounds = config::outbound::generate_outbounds(&servers)?;
-    let routing = config::routing::generate_routing(&servers)?;
+    if args.index {
+        let index_path = out_dir.join(INDEX_FILE_NAME);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let index = config::index::write_index(
+            &index_path,
+            &args.output.join(INDEX_FILE_NAME),
+            &servers,
+            &generator_options,
+            &origins,
+            now,
+            &write_options,
+        )?;
+        written.push(index_path);
+        for (tag, entry) in index {
+            if entry.health.consecutive_failures > 0 {
+                log::warn!(
+                    "{} failed its last {} probes",
+                    tag,
+                    entry.health.consecutive_failures
+                );
+                run_report.failing.push(report::FailingServer {
+                    tag,
+                    source: entry.source,
+                    last_checked: entry.health.last_checked,
+                    consecutive_failures: entry.health.consecutive_failures,
+                });
+            }
+        }
+    }
 
-    // Write configuration files
-    let outbounds_path = args.output.join(OUTBOUND_FILE_NAME);
-    let routing_path = args.output.join(ROUTING_FILE_NAME);
+    if let Some(staging) = staging {
+        written = staging.commit(&write_options)?;
+        // Outbounds written as one file or as chunks last time
+        let final_outbounds = args.output.join(OUTBOUND_FILE_NAME);
+        config::outbound::remove_stale_chunks(&final_outbounds, &written)?;
+        if args.chunk_size.is_some() && final_outbounds.exists() {
+            std::fs::remove_file(&final_outbounds)?;
+        }
+        // Left in the confdir by runs from before --annotate-dir
+        for path in [&outbounds_path, &routing_path] {
+            let stale = config::annotate::jsonc_path(&args.output, path);
+            if stale.exists() {
+                std::fs::remove_file(&stale)?;
+            }
+        }
+    }
 
-    config::write_config(&outbounds_path, &outbounds)?;
-    config::write_config(&routing_path, &routing)?;
+    // Written after the move: they aren't loaded by Xray, so they don't need
+    // to land together with the configs
+    if args.annotate {
+        let annotate_dir = args
+            .annotate_dir
+            .clone()
+            .unwrap_or_else(|| args.output.join(ANNOTATE_DIR_NAME));
+        if !args.stdout {
+            config::create_output_dir(&annotate_dir, args.umask)?;
+        }
+        let outbounds = config::outbound::generate_outbounds_with(&servers, &generator_options)?;
 
+        for (path, text) in [
+            (
+                config::annotate::jsonc_path(&annotate_dir, &outbounds_path),
+                config::annotate::annotate_outbounds(&outbounds, &routing, &origins),
+            ),
+            (
+                config::annotate::jsonc_path(&annotate_dir, &routing_path),
+                config::annotate::annotate_routing(&routing),
+            ),
+        ] {
+            config::annotate::write_annotated(&path, &text, &write_options)?;
+            written.push(path);
+        }
+    }
+    if let Some(wg_quick_dir) = &args.wg_quick_dir {
+        if !args.stdout {
+            config::create_output_dir(wg_quick_dir, args.umask)?;
+        }
+        written.extend(config::wireguard::write_wg_quick(
+            wg_quick_dir,
+            &servers,
+            &write_options,
+        )?);
+    }
     info!("Successfully generated config files:");
-    info!("  - {}", outbounds_path.display());
-    info!("  - {}", routing_path.display());
+    for path in &written {
+        info!("  - {}", path.display());
+    }
+
+    if let Some(report_path) = &args.report {
+        run_report.write(report_path)?;
+        info!("Run report written to {}", report_path.display());
+    }
+
+    if args.git_commit {
+        let stats = deploy::CommitStats {
+            parsed: run_report.parsed,
+            servers: servers
+                .iter()
+                .filter(|s| {
+                    config::capability::is_supported_with(
+                        config::capability::Target::Xray,
+                        s,
+                        &generator_options,
+                    )
+                })
+                .count(),
+            skipped: run_report.skipped.len(),
+        };
+        let message = deploy::render_message(&args.git_commit_message, &stats);
+        if deploy::git_commit(&args.output, &message, args.git_push)? {
+            info!("Committed configs: {}", message);
+        } else {
+            info!("Configs unchanged, nothing to commit");
+        }
+    }
 
     Ok(())
 }
 
-fn fetch_url_content(url: &str) -> Result<String> {
-    info!("Fetching content from URL...");
-    let response = reqwest::blocking::get(url)?;
+/// The CLI definition, named after the installed binary so completions and
+/// the man page match what users type.
+fn cli() -> clap::Command {
+    Args::command().name(BIN_NAME).bin_name(BIN_NAME)
+}
+
+fn run_doctor(args: &DoctorArgs) -> Result<()> {
+    let DoctorArgs {
+        output,
+        url,
+        xray,
+        geosite_dir,
+        geodata_max_age,
+        #[cfg(feature = "custom-dns")]
+        dns,
+    } = args;
+
+    let mut checks = vec![doctor::check_output_dir(output)];
+    if let Some(xray) = xray {
+        checks.push(doctor::check_xray(xray));
+    }
+    if let Some(dir) = geosite_dir {
+        checks.extend(doctor::check_geodata(
+            dir,
+            Duration::from_secs(geodata_max_age * 86400),
+        ));
+    }
+
+    let mut hosts: Vec<String> = url
+        .iter()
+        .filter_map(|url| {
+            reqwest::Url::parse(url)
+                .ok()?
+                .host_str()
+                .map(str::to_string)
+        })
+        .collect();
+    hosts.dedup();
+    if hosts.is_empty() {
+        hosts.push("github.com".to_string());
+    }
+    checks.extend(hosts.iter().map(|host| doctor::check_dns(host)));
+
+    if !url.is_empty() {
+        let fetcher = fetch::ReqwestFetcher::with_options(&fetch::ClientOptions {
+            #[cfg(feature = "custom-dns")]
+            dns_server: dns.clone(),
+            timeout: None,
+            bind: None,
+            retry: fetch::RetryPolicy::default(),
+            cache_dir: None,
+            offline: false,
+        })?;
+        checks.extend(url.iter().map(|url| doctor::check_source(&fetcher, url)));
+    }
+
+    for check in &checks {
+        println!("{}", check);
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == doctor::Status::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
 
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to fetch URL: HTTP {}", response.status());
+fn run_convert(from: ConvertFrom, to: ConvertTo, input: &std::path::Path) -> Result<()> {
+    let content = std::fs::read_to_string(input)?;
+
+    let servers = match from {
+        ConvertFrom::Xray => convert::servers_from_xray(&serde_json::from_str(&content)?)?,
+        ConvertFrom::Ndjson => convert::servers_from_ndjson(&content)?,
+    };
+
+    match to {
+        ConvertTo::Urls => {
+            for server in &servers {
+                match convert::server_to_url(server) {
+                    Some(url) => println!("{}", url),
+                    None => log::warn!(
+                        "No share URL form for {} ({})",
+                        server.tag(),
+                        server.protocol()
+                    ),
+                }
+            }
+        }
+        ConvertTo::Ndjson => {
+            let mut stdout = std::io::stdout().lock();
+            convert::write_ndjson(&mut stdout, &servers)?;
+        }
     }
 
-    let content = response.text()?;
-    Ok(content)
+    Ok(())
+}
+
+fn parse_octal(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .map_err(|e| format!("invalid octal value '{}': {}", value, e))
 }
@@ -0,0 +1,460 @@
+//! Clash / Clash.Meta (mihomo) YAML configs, which many providers serve
+//! instead of share links. Proxy entries are mapped onto the query
+//! parameters of the equivalent share link so TLS and transport settings go
+//! through the same code as links do.
+
+use super::{
+    QueryParams, ServerConfig, ShadowsocksPlugin, check_is_warp, normalize_network,
+    parse_network_settings, parse_tls_settings, sanitize_tag, validate_ss2022_keys,
+};
+use anyhow::{Context, Result};
+use serde_yaml::Value;
+
+/// Servers of the `proxies` list of a Clash config. ss, vmess, vless, trojan
+/// and hysteria2 entries are understood; other types are skipped, and so
+/// are entries missing a required field, with a warning.
+pub fn parse_clash(content: &str) -> Result<Vec<ServerConfig>> {
+    let doc: Value =
+        serde_yaml::from_str(content.trim_start_matches('\u{feff}')).context("Invalid YAML")?;
+    let proxies = doc
+        .get("proxies")
+        .and_then(Value::as_sequence)
+        .context("No proxies list in Clash config")?;
+
+    Ok(proxies
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, proxy)| {
+            let proxy = Proxy(proxy);
+            match parse_proxy(&proxy, idx) {
+                Ok(server) => server,
+                Err(e) => {
+                    log::warn!(
+                        "Failed to parse Clash proxy {}: {}",
+                        proxy.str("name").unwrap_or_default(),
+                        e
+                    );
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// One entry of `proxies`.
+struct Proxy<'a>(&'a Value);
+
+impl Proxy<'_> {
+    /// A scalar field as text; Clash writes ports and ids either way.
+    fn str(&self, key: &str) -> Option<String> {
+        scalar(self.0.get(key)?)
+    }
+
+    fn required(&self, key: &str) -> Result<String> {
+        self.str(key)
+            .filter(|value| !value.is_empty())
+            .with_context(|| format!("missing {}", key))
+    }
+
+    fn bool(&self, key: &str) -> bool {
+        self.0.get(key).is_some_and(|value| {
+            value.as_bool() == Some(true) || scalar(value).as_deref() == Some("true")
+        })
+    }
+
+    fn port(&self) -> Result<u16> {
+        self.required("port")?.parse().context("invalid port")
+    }
+
+    /// `<key>.<field>`, e.g. `ws-opts.path`.
+    fn nested(&self, key: &str, field: &str) -> Option<String> {
+        scalar(self.0.get(key)?.get(field)?)
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_proxy(proxy: &Proxy, idx: usize) -> Result<Option<ServerConfig>> {
+    let kind = proxy.required("type")?;
+    let name = proxy.str("name").unwrap_or_default();
+    let address = proxy.required("server")?;
+
+    let server = match kind.as_str() {
+        "ss" => {
+            let method = proxy.required("cipher")?.to_lowercase();
+            let password = proxy.required("password")?;
+            validate_ss2022_keys(&method, &password)?;
+            ServerConfig::Shadowsocks {
+                tag: sanitize_tag(&name, "ss", idx, false),
+                address,
+                port: proxy.port()?,
+                method,
+                password,
+                udp_over_tcp: proxy.bool("udp-over-tcp"),
+                plugin: plugin(proxy),
+            }
+        }
+        "vmess" => {
+            let params = share_params(proxy);
+            let network = network(proxy);
+            let allow_insecure = proxy.bool("skip-cert-verify");
+            ServerConfig::Vmess {
+                tag: sanitize_tag(&name, "vmess", idx, check_is_warp(&name, &params)),
+                address,
+                port: proxy.port()?,
+                id: proxy.required("uuid")?,
+                alter_id: proxy
+                    .str("alterId")
+                    .map_or(Ok(0), |aid| aid.parse())
+                    .context("invalid alterId")?,
+                security: proxy
+                    .str("cipher")
+                    .unwrap_or_else(|| "auto".to_string())
+                    .to_lowercase(),
+                network_settings: parse_network_settings(&params, &network)?,
+                tls_settings: Box::new(if proxy.bool("tls") {
+                    Some(parse_tls_settings(&params, "tls")?)
+                } else {
+                    None
+                }),
+                network,
+                allow_insecure,
+            }
+        }
+        "vless" => {
+            let params = share_params(proxy);
+            let network = network(proxy);
+            let security = if proxy.0.get("reality-opts").is_some() {
+                "reality"
+            } else if proxy.bool("tls") {
+                "tls"
+            } else {
+                "none"
+            };
+            ServerConfig::Vless {
+                tag: sanitize_tag(&name, "vless", idx, check_is_warp(&name, &params)),
+                address,
+                port: proxy.port()?,
+                id: proxy.required("uuid")?,
+                encryption: "none".to_string(),
+                flow: proxy.str("flow").unwrap_or_default(),
+                network_settings: parse_network_settings(&params, &network)?,
+                tls_settings: Box::new(if security == "none" {
+                    None
+                } else {
+                    Some(parse_tls_settings(&params, security)?)
+                }),
+                network,
+                security: security.to_string(),
+            }
+        }
+        "trojan" => {
+            let params = share_params(proxy);
+            let network = network(proxy);
+            ServerConfig::Trojan {
+                tag: sanitize_tag(&name, "trojan", idx, false),
+                address,
+                port: proxy.port()?,
+                password: proxy.required("password")?,
+                network_settings: parse_network_settings(&params, &network)?,
+                tls_settings: Box::new(Some(parse_tls_settings(&params, "tls")?)),
+                network,
+                security: "tls".to_string(),
+                allow_insecure: proxy.bool("skip-cert-verify"),
+            }
+        }
+        "hysteria2" => ServerConfig::Hysteria2 {
+            tag: sanitize_tag(&name, "hysteria2", idx, false),
+            address,
+            port: proxy.port()?,
+            password: proxy
+                .required("password")
+                .or_else(|_| proxy.required("auth"))?,
+            server_name: proxy.str("sni").unwrap_or_default(),
+            allow_insecure: proxy.bool("skip-cert-verify"),
+            obfs: proxy.str("obfs").filter(|s| !s.is_empty()),
+            obfs_password: proxy.str("obfs-password").filter(|s| !s.is_empty()),
+        },
+        _ => {
+            log::debug!("Skipping Clash proxy {} of type {}", name, kind);
+            return Ok(None);
+        }
+    };
+    Ok(Some(server))
+}
+
+fn network(proxy: &Proxy) -> String {
+    normalize_network(&proxy.str("network").unwrap_or_else(|| "tcp".to_string()))
+}
+
+/// The TLS and transport fields of an entry, named as in share links.
+fn share_params(proxy: &Proxy) -> QueryParams {
+    let mut params = QueryParams::default();
+    let mut set = |key: &str, value: Option<String>| {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            params.push(key, value);
+        }
+    };
+
+    set("sni", proxy.str("servername").or_else(|| proxy.str("sni")));
+    set("fp", proxy.str("client-fingerprint"));
+    // Clash verifies certificates unless told not to
+    set(
+        "allowInsecure",
+        Some(
+            if proxy.bool("skip-cert-verify") {
+                "1"
+            } else {
+                "0"
+            }
+            .to_string(),
+        ),
+    );
+    set("pbk", proxy.nested("reality-opts", "public-key"));
+    set("sid", proxy.nested("reality-opts", "short-id"));
+    set(
+        "path",
+        proxy
+            .nested("ws-opts", "path")
+            .or_else(|| proxy.nested("h2-opts", "path")),
+    );
+    set(
+        "host",
+        proxy
+            .0
+            .get("ws-opts")
+            .and_then(|opts| opts.get("headers"))
+            .and_then(|headers| headers.get("Host").or_else(|| headers.get("host")))
+            .and_then(scalar)
+            .or_else(|| {
+                proxy
+                    .0
+                    .get("h2-opts")?
+                    .get("host")?
+                    .as_sequence()?
+                    .first()
+                    .and_then(scalar)
+            }),
+    );
+    set(
+        "serviceName",
+        proxy.nested("grpc-opts", "grpc-service-name"),
+    );
+    for alpn in proxy
+        .0
+        .get("alpn")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(scalar)
+    {
+        set("alpn", Some(alpn));
+    }
+    params
+}
+
+/// The SIP003 plugin of an ss entry; Clash names obfs-local just `obfs`
+/// and gives options as a map.
+fn plugin(proxy: &Proxy) -> Option<ShadowsocksPlugin> {
+    let name = proxy.str("plugin")?;
+    let opts = proxy.0.get("plugin-opts").and_then(Value::as_mapping);
+    let opt = |key: &str| opts.and_then(|opts| opts.get(key)).and_then(scalar);
+
+    let (name, opts) = match name.as_str() {
+        "obfs" => {
+            let mut sip003 = vec![format!(
+                "obfs={}",
+                opt("mode").unwrap_or_else(|| "http".into())
+            )];
+            sip003.extend(opt("host").map(|host| format!("obfs-host={}", host)));
+            ("obfs-local".to_string(), sip003)
+        }
+        _ => {
+            let sip003 = opts
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| {
+                    let key = scalar(key)?;
+                    match value {
+                        Value::Bool(true) => Some(key),
+                        Value::Bool(false) => None,
+                        value => Some(format!("{}={}", key, scalar(value)?)),
+                    }
+                })
+                .collect();
+            (name, sip003)
+        }
+    };
+    ShadowsocksPlugin::parse(&name, Some(&opts.join(";")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::NetworkSettings;
+
+    const CLASH: &str = r#"
+port: 7890
+mode: rule
+proxies:
+  - name: "🇩🇪 ss"
+    type: ss
+    server: 203.0.113.1
+    port: 8388
+    cipher: aes-256-gcm
+    password: secret
+    udp-over-tcp: true
+    plugin: obfs
+    plugin-opts:
+      mode: tls
+      host: bing.com
+  - name: vmess-ws
+    type: vmess
+    server: vmess.example.com
+    port: "443"
+    uuid: a3482e88-686a-4a58-8126-99c9df64b7bf
+    alterId: 0
+    cipher: auto
+    tls: true
+    servername: cdn.example.com
+    network: ws
+    ws-opts:
+      path: /ws
+      headers:
+        Host: cdn.example.com
+  - name: vless-reality
+    type: vless
+    server: 203.0.113.3
+    port: 443
+    uuid: a3482e88-686a-4a58-8126-99c9df64b7bf
+    flow: xtls-rprx-vision
+    tls: true
+    servername: www.microsoft.com
+    client-fingerprint: firefox
+    reality-opts:
+      public-key: pbk
+      short-id: "0123"
+  - name: trojan-grpc
+    type: trojan
+    server: 203.0.113.4
+    port: 443
+    password: pass
+    sni: trojan.example.com
+    skip-cert-verify: true
+    network: grpc
+    grpc-opts:
+      grpc-service-name: svc
+    alpn: [h2]
+  - name: hy2
+    type: hysteria2
+    server: 203.0.113.5
+    port: 443
+    password: pass
+    sni: hy2.example.com
+    obfs: salamander
+    obfs-password: obfs
+  - name: http-proxy
+    type: http
+    server: 203.0.113.6
+    port: 8080
+  - name: broken
+    type: vless
+    server: 203.0.113.7
+proxy-groups: []
+"#;
+
+    #[test]
+    fn test_parse_clash() {
+        let servers = parse_clash(CLASH).unwrap();
+        assert_eq!(servers.len(), 5);
+
+        match &servers[0] {
+            ServerConfig::Shadowsocks {
+                tag,
+                udp_over_tcp,
+                plugin,
+                ..
+            } => {
+                assert_eq!(tag, "ss");
+                assert!(udp_over_tcp);
+                assert_eq!(
+                    plugin.as_ref().unwrap().to_plugin_string(),
+                    "obfs-local;obfs=tls;obfs-host=bing.com"
+                );
+            }
+            other => panic!("Expected shadowsocks, got {other:?}"),
+        }
+        match &servers[1] {
+            ServerConfig::Vmess {
+                port,
+                network_settings: Some(NetworkSettings::WebSocket { path, host }),
+                tls_settings,
+                allow_insecure,
+                ..
+            } => {
+                assert_eq!(*port, 443);
+                assert_eq!(path, "/ws");
+                assert_eq!(host, "cdn.example.com");
+                let tls = tls_settings.as_ref().as_ref().unwrap();
+                assert_eq!(tls.server_name, "cdn.example.com");
+                assert!(!tls.allow_insecure && !allow_insecure);
+            }
+            other => panic!("Expected vmess over ws, got {other:?}"),
+        }
+        match &servers[2] {
+            ServerConfig::Vless {
+                security,
+                flow,
+                tls_settings,
+                ..
+            } => {
+                assert_eq!(security, "reality");
+                assert_eq!(flow, "xtls-rprx-vision");
+                let tls = tls_settings.as_ref().as_ref().unwrap();
+                assert_eq!(tls.fingerprint, "firefox");
+                assert_eq!(tls.public_key.as_deref(), Some("pbk"));
+                assert_eq!(tls.short_id.as_deref(), Some("0123"));
+            }
+            other => panic!("Expected vless, got {other:?}"),
+        }
+        match &servers[3] {
+            ServerConfig::Trojan {
+                network_settings: Some(NetworkSettings::Grpc { service_name, .. }),
+                tls_settings,
+                allow_insecure,
+                ..
+            } => {
+                assert_eq!(service_name, "svc");
+                let tls = tls_settings.as_ref().as_ref().unwrap();
+                assert_eq!(tls.alpn.as_deref(), Some(&["h2".to_string()][..]));
+                assert!(allow_insecure);
+            }
+            other => panic!("Expected trojan over grpc, got {other:?}"),
+        }
+        match &servers[4] {
+            ServerConfig::Hysteria2 {
+                obfs,
+                obfs_password,
+                ..
+            } => {
+                assert_eq!(obfs.as_deref(), Some("salamander"));
+                assert_eq!(obfs_password.as_deref(), Some("obfs"));
+            }
+            other => panic!("Expected hysteria2, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_clash_without_proxies() {
+        assert!(parse_clash("port: 7890\n").is_err());
+        assert!(parse_clash("proxies: []\n").unwrap().is_empty());
+        assert!(parse_clash("proxies: [unclosed").is_err());
+    }
+}
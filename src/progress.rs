@@ -0,0 +1,320 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Cooperative cancellation flag, shared between an embedder (e.g. a GUI's
+/// "Cancel" button) and the pipeline stages it was passed to.
+///
+/// Stages check it between units of work (a source, a probe, a chunk of
+/// outbounds) and stop with [`Cancelled`]; an HTTP request already in flight
+/// runs to completion first.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error returned by a stage that stopped because its token was cancelled.
+///
+/// Embedders can tell it apart from real failures with
+/// `error.is::<Cancelled>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error returned by a stage that stopped because its deadline passed.
+///
+/// Unlike [`Cancelled`], the run is expected to go on with whatever the
+/// stage finished before the deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Wall-clock budget of a run (`--max-runtime`), handing out stage
+/// deadlines that never reach past its end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// A budget ending `max_runtime` from now, or an unlimited one.
+    pub fn new(max_runtime: Option<Duration>) -> Self {
+        Self {
+            deadline: max_runtime.map(|max| Instant::now() + max),
+        }
+    }
+
+    /// Deadline of a stage starting now: its own timeout or the end of the
+    /// budget, whichever comes first.
+    pub fn stage_deadline(&self, timeout: Option<Duration>) -> Option<Instant> {
+        let stage = timeout.map(|timeout| Instant::now() + timeout);
+        match (stage, self.deadline) {
+            (Some(stage), Some(run)) => Some(stage.min(run)),
+            (stage, run) => stage.or(run),
+        }
+    }
+
+    /// Hooks for a stage starting now, see [`Budget::stage_deadline`].
+    pub fn stage_hooks(&self, timeout: Option<Duration>) -> Hooks {
+        let hooks = Hooks::default();
+        match self.stage_deadline(timeout) {
+            Some(deadline) => hooks.with_deadline(deadline),
+            None => hooks,
+        }
+    }
+}
+
+/// Parses durations like `90`, `500ms`, `30s`, `10m` or `1h`; a bare
+/// number is seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {value}"))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 3600)),
+        _ => Err(format!(
+            "invalid duration unit in {value}, expected ms, s, m or h"
+        )),
+    }
+}
+
+/// Long-running pipeline stage a progress update belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Downloading sources
+    Fetch,
+    /// Probing server or CDN addresses
+    Check,
+    /// Generating and writing outbounds
+    Generate,
+}
+
+/// Progress of a stage: `done` units out of `total`, if known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub stage: Stage,
+    pub done: usize,
+    pub total: Option<usize>,
+}
+
+type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Cancellation and progress hooks passed to the `*_with_hooks` stage
+/// functions. The default never cancels and reports nowhere.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub cancel: CancellationToken,
+    progress: Option<ProgressCallback>,
+    deadline: Option<Instant>,
+}
+
+impl Hooks {
+    pub fn new(cancel: CancellationToken) -> Self {
+        Self {
+            cancel,
+            progress: None,
+            deadline: None,
+        }
+    }
+
+    /// Stops the stage once `deadline` has passed, keeping an earlier
+    /// deadline if one was already set.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(self.deadline.map_or(deadline, |d| d.min(deadline)));
+        self
+    }
+
+    /// Calls `callback` on every progress update. It may be called from
+    /// worker threads.
+    pub fn with_progress(mut self, callback: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn report(&self, stage: Stage, done: usize, total: Option<usize>) {
+        if let Some(callback) = &self.progress {
+            callback(Progress { stage, done, total });
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// True once the stage should stop, cancelled or out of time.
+    pub fn should_stop(&self) -> bool {
+        self.is_cancelled() || self.is_timed_out()
+    }
+
+    /// Time left until the deadline, None if there is none.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fails with [`Cancelled`] once the token has been cancelled, or with
+    /// [`TimedOut`] once the deadline has passed.
+    pub fn check_cancelled(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        if self.is_timed_out() {
+            return Err(TimedOut.into());
+        }
+        Ok(())
+    }
+
+    /// Waits for `duration`, waking up early to fail like
+    /// [`check_cancelled`](Self::check_cancelled) once the stage should stop.
+    pub fn sleep(&self, duration: Duration) -> anyhow::Result<()> {
+        let until = Instant::now() + duration;
+        loop {
+            self.check_cancelled()?;
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            std::thread::sleep(left.min(Duration::from_millis(50)));
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("cancel", &self.cancel)
+            .field("progress", &self.progress.is_some())
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_hooks_cancel_and_report() {
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = updates.clone();
+        let hooks = Hooks::default().with_progress(move |p| sink.lock().unwrap().push(p));
+
+        hooks.report(Stage::Fetch, 1, Some(2));
+        assert!(hooks.check_cancelled().is_ok());
+
+        // Clones share the token
+        hooks.clone().cancel.cancel();
+        let err = hooks.check_cancelled().unwrap_err();
+        assert!(err.is::<Cancelled>());
+
+        assert_eq!(
+            *updates.lock().unwrap(),
+            [Progress {
+                stage: Stage::Fetch,
+                done: 1,
+                total: Some(2)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hooks_deadline() {
+        let hooks = Hooks::default();
+        assert!(hooks.remaining().is_none());
+
+        let hooks = hooks.with_deadline(Instant::now() + Duration::from_secs(3600));
+        assert!(!hooks.should_stop());
+        assert!(hooks.check_cancelled().is_ok());
+
+        // An earlier deadline wins
+        let hooks = hooks.with_deadline(Instant::now());
+        assert!(hooks.is_timed_out() && !hooks.is_cancelled());
+        assert_eq!(hooks.remaining(), Some(Duration::ZERO));
+        assert!(hooks.check_cancelled().unwrap_err().is::<TimedOut>());
+    }
+
+    #[test]
+    fn test_hooks_sleep() {
+        let hooks = Hooks::default();
+        let start = Instant::now();
+        hooks.sleep(Duration::from_millis(20)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // A deadline cuts the wait short
+        let hooks = hooks.with_deadline(Instant::now() + Duration::from_millis(20));
+        let start = Instant::now();
+        let error = hooks.sleep(Duration::from_secs(3600)).unwrap_err();
+        assert!(error.is::<TimedOut>());
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        hooks.cancel.cancel();
+        assert!(hooks.sleep(Duration::ZERO).unwrap_err().is::<Cancelled>());
+    }
+
+    #[test]
+    fn test_budget_stage_deadline() {
+        assert!(Budget::default().stage_deadline(None).is_none());
+
+        let budget = Budget::new(Some(Duration::from_secs(60)));
+        let short = budget.stage_deadline(Some(Duration::from_secs(1))).unwrap();
+        let long = budget
+            .stage_deadline(Some(Duration::from_secs(600)))
+            .unwrap();
+        assert!(short < long);
+        assert_eq!(Some(long), budget.stage_deadline(None));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("").is_err());
+    }
+}
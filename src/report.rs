@@ -0,0 +1,279 @@
+use crate::parser::ServerConfig;
+use anyhow::Result;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Summary of a single generator run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    pub parsed: usize,
+    /// Parsed servers per transport, before any filtering
+    pub transports: BTreeMap<String, usize>,
+    pub skipped: Vec<SkippedServer>,
+    pub skipped_sources: Vec<SkippedSource>,
+    pub credential_clusters: Vec<CredentialCluster>,
+    pub panics: Vec<PanickedInput>,
+    pub timeouts: Vec<TimedOutStage>,
+    pub security_normalized: Vec<SecurityNormalization>,
+    /// Servers whose last probe got no answer, from the `--index` history
+    pub failing: Vec<FailingServer>,
+    /// What each source that could be read contributed
+    pub sources: Vec<SourceStats>,
+}
+
+/// Entries read from one source and what the parser made of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SourceStats {
+    pub source: String,
+    pub entries: usize,
+    /// Parsed and kept
+    pub parsed: usize,
+    /// Parsed, but of a protocol the source doesn't accept
+    pub filtered: usize,
+}
+
+/// A server that was parsed but left out of a target's output.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedServer {
+    pub tag: String,
+    pub protocol: String,
+    pub target: String,
+    pub reason: String,
+}
+
+/// An input source that was left out because it couldn't be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedSource {
+    pub source: String,
+    pub reason: String,
+}
+
+/// An input whose processing panicked; the run went on without it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PanickedInput {
+    pub stage: String,
+    /// The input with credentials cut, see [`redact_input`]
+    pub input: String,
+    pub message: String,
+}
+
+/// A stage cut short by `--max-runtime` or its own timeout; the run went on
+/// with what it had finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedOutStage {
+    pub stage: String,
+    pub detail: String,
+}
+
+/// A link with `security=none` but TLS parameters, and what was done about
+/// it (`upgraded` to tls or `stripped`), see `--assume-tls-when-sni`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityNormalization {
+    pub tag: String,
+    pub protocol: String,
+    pub action: String,
+    /// The TLS parameters found, e.g. `sni`, `fp`
+    pub params: Vec<String>,
+}
+
+/// A probed server that didn't answer, with how many runs in a row it hasn't.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailingServer {
+    pub tag: String,
+    pub source: Option<String>,
+    /// Unix time of the probe
+    pub last_checked: Option<u64>,
+    pub consecutive_failures: u32,
+}
+
+/// Servers sharing one credential across several addresses, typical of
+/// mass-published free pools run by a single operator.
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialCluster {
+    pub protocol: String,
+    /// Credential with all but its first characters masked
+    pub credential: String,
+    pub addresses: usize,
+    pub tags: Vec<String>,
+}
+
+impl RunReport {
+    pub fn log_summary(&self) {
+        info!("Parsed {} servers", self.parsed);
+        if self.sources.len() > 1 {
+            for stats in &self.sources {
+                info!(
+                    "  - {}: {} entries, {} parsed, {} filtered",
+                    stats.source, stats.entries, stats.parsed, stats.filtered
+                );
+            }
+        }
+        if !self.transports.is_empty() {
+            let counts: Vec<String> = self
+                .transports
+                .iter()
+                .map(|(transport, count)| format!("{} {}", transport, count))
+                .collect();
+            info!("Transports: {}", counts.join(", "));
+        }
+
+        if !self.skipped_sources.is_empty() {
+            warn!("Skipped {} sources:", self.skipped_sources.len());
+            for skipped in &self.skipped_sources {
+                warn!("  - {}: {}", skipped.source, skipped.reason);
+            }
+        }
+
+        for timeout in &self.timeouts {
+            warn!("{} timed out: {}", timeout.stage, timeout.detail);
+        }
+
+        for panicked in &self.panics {
+            warn!(
+                "Failed to {} {}: panicked: {}",
+                panicked.stage, panicked.input, panicked.message
+            );
+        }
+
+        if !self.security_normalized.is_empty() {
+            info!(
+                "{} servers had TLS parameters with security=none:",
+                self.security_normalized.len()
+            );
+            for normalized in &self.security_normalized {
+                info!(
+                    "  - {} ({}): {} {}",
+                    normalized.tag,
+                    normalized.protocol,
+                    normalized.action,
+                    normalized.params.join(", ")
+                );
+            }
+        }
+
+        if !self.credential_clusters.is_empty() {
+            info!(
+                "{} credentials are shared across several addresses:",
+                self.credential_clusters.len()
+            );
+            for cluster in self.credential_clusters.iter().take(5) {
+                info!(
+                    "  - {} {}: {} servers on {} addresses",
+                    cluster.protocol,
+                    cluster.credential,
+                    cluster.tags.len(),
+                    cluster.addresses
+                );
+            }
+        }
+
+        if !self.skipped.is_empty() {
+            warn!("Skipped {} servers:", self.skipped.len());
+            for skipped in &self.skipped {
+                warn!(
+                    "  - {} ({}) skipped for {}: {}",
+                    skipped.tag, skipped.protocol, skipped.target, skipped.reason
+                );
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Empty stats for every source in `sources` (one name per entry read, as
+/// in [`crate::input::Inputs`]), with their entries counted.
+pub fn source_stats(sources: &[String]) -> Vec<SourceStats> {
+    let mut stats: Vec<SourceStats> = Vec::new();
+    for source in sources {
+        match stats.last_mut() {
+            Some(last) if last.source == *source => last.entries += 1,
+            _ => stats.push(SourceStats {
+                source: source.clone(),
+                entries: 1,
+                ..Default::default()
+            }),
+        }
+    }
+    stats
+}
+
+/// Number of servers per transport, see [`ServerConfig::transport`].
+pub fn count_transports(servers: &[ServerConfig]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for server in servers {
+        *counts.entry(server.transport().to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// Runs `f`, turning a panic into its message so one pathological input
+/// can't abort a whole run. Builds with `panic = "abort"` (the
+/// release-router profile) still abort.
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> std::result::Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+/// A share URL cut down to its scheme and, when visible, host and port,
+/// e.g. `vless://…@example.com:443`, so reports never carry credentials.
+pub fn redact_input(line: &str) -> String {
+    let Some((scheme, rest)) = line.split_once("://") else {
+        return "…".to_string();
+    };
+    let scheme: String = scheme.chars().take(16).collect();
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    match rest.rsplit_once('@') {
+        Some((_, host)) => format!("{}://…@{}", scheme, host.trim_end_matches('/')),
+        None => format!("{}://…", scheme),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| 1), Ok(1));
+        assert_eq!(
+            catch_panic(|| -> i32 { panic!("bad line") }),
+            Err("bad line".to_string())
+        );
+        let idx = 7;
+        assert_eq!(
+            catch_panic(|| -> i32 { panic!("bad line {}", idx) }),
+            Err("bad line 7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_source_stats() {
+        let sources = ["a", "a", "b"].map(str::to_string);
+        let stats = source_stats(&sources);
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].source.as_str(), stats[0].entries), ("a", 2));
+        assert_eq!((stats[1].source.as_str(), stats[1].entries), ("b", 1));
+    }
+
+    #[test]
+    fn test_redact_input() {
+        assert_eq!(
+            redact_input("trojan://secret@example.com:443?sni=a#name"),
+            "trojan://…@example.com:443"
+        );
+        assert_eq!(redact_input("vmess://eyJhZGQiOiAiMSJ9"), "vmess://…");
+        assert_eq!(redact_input("garbage"), "…");
+    }
+}
@@ -0,0 +1,223 @@
+use crate::parser::{NetworkSettings, ServerConfig};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Connect address overrides, e.g. a clean Cloudflare IP for CDN-fronted
+/// hostnames.
+///
+/// Loaded from a JSON object mapping a server address to the address to
+/// connect to instead. Keys are exact hosts or `*.suffix` wildcards:
+///
+/// ```json
+/// { "cdn.example.com": "104.16.1.1", "*.workers.dev": "172.67.1.1" }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AddressMap {
+    rules: Vec<(String, String)>,
+}
+
+impl AddressMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read address map {}", path.display()))?;
+        let json: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid address map {}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let object = json
+            .as_object()
+            .context("Address map must be a JSON object")?;
+
+        let mut map = Self::default();
+        for (pattern, address) in object {
+            let address = address
+                .as_str()
+                .with_context(|| format!("Address for {} must be a string", pattern))?;
+            map.insert(pattern, address);
+        }
+        Ok(map)
+    }
+
+    /// Adds a rule sending hosts matching `pattern` to `address`.
+    pub fn insert(&mut self, pattern: &str, address: &str) {
+        let pattern = pattern.to_lowercase();
+        self.rules.retain(|(existing, _)| *existing != pattern);
+        self.rules.push((pattern, address.to_string()));
+
+        // Exact hosts win over wildcards, longer wildcards over shorter ones
+        self.rules.sort_by_key(|(pattern, _)| {
+            (pattern.starts_with("*."), std::cmp::Reverse(pattern.len()))
+        });
+    }
+
+    /// Returns the override for `host`, if any rule matches it.
+    pub fn lookup(&self, host: &str) -> Option<&str> {
+        let host = host.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(pattern, _)| match pattern.strip_prefix("*.") {
+                Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+                None => *pattern == host,
+            })
+            .map(|(_, address)| address.as_str())
+    }
+
+    /// Rewrites the connect address of matching servers, keeping the original
+    /// hostname as TLS SNI and WebSocket Host where those were left empty, so
+    /// the CDN still routes the request. Returns how many servers were rewritten.
+    pub fn apply(&self, servers: &mut [ServerConfig]) -> usize {
+        let mut rewritten = 0;
+
+        for server in servers {
+            let (address, tls_name, ws_host) = match server {
+                ServerConfig::Vless {
+                    address,
+                    tls_settings,
+                    network_settings,
+                    ..
+                }
+                | ServerConfig::Vmess {
+                    address,
+                    tls_settings,
+                    network_settings,
+                    ..
+                }
+                | ServerConfig::Trojan {
+                    address,
+                    tls_settings,
+                    network_settings,
+                    ..
+                } => (
+                    address,
+                    tls_settings
+                        .as_mut()
+                        .as_mut()
+                        .map(|tls| &mut tls.server_name),
+                    match network_settings {
+                        Some(NetworkSettings::WebSocket { host, .. })
+                        | Some(NetworkSettings::Xhttp { host, .. }) => Some(host),
+                        _ => None,
+                    },
+                ),
+                ServerConfig::Hysteria2 {
+                    address,
+                    server_name,
+                    ..
+                } => (address, Some(server_name), None),
+                ServerConfig::Hysteria {
+                    address,
+                    server_name,
+                    ..
+                } => (address, Some(server_name), None),
+                ServerConfig::Tuic { address, sni, .. } => (address, Some(sni), None),
+                ServerConfig::Shadowsocks { address, .. }
+                | ServerConfig::ShadowsocksR { address, .. }
+                | ServerConfig::Socks { address, .. }
+                | ServerConfig::Http { address, .. }
+                | ServerConfig::Naive { address, .. }
+                | ServerConfig::Brook { address, .. }
+                | ServerConfig::Mieru { address, .. }
+                | ServerConfig::Wireguard { address, .. } => (address, None, None),
+            };
+
+            let Some(new_address) = self.lookup(address) else {
+                continue;
+            };
+
+            let original = std::mem::replace(address, new_address.to_string());
+            // An IP literal is no use as SNI or Host
+            if original.trim_matches(['[', ']']).parse::<IpAddr>().is_err() {
+                for name in [tls_name, ws_host].into_iter().flatten() {
+                    if name.is_empty() {
+                        *name = original.clone();
+                    }
+                }
+            }
+            rewritten += 1;
+        }
+
+        rewritten
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::TlsSettings;
+    use serde_json::json;
+
+    fn vless_server(address: &str) -> ServerConfig {
+        ServerConfig::Vless {
+            tag: "vless-1".to_string(),
+            address: address.to_string(),
+            port: 443,
+            id: "uuid".to_string(),
+            encryption: "none".to_string(),
+            flow: String::new(),
+            network: "ws".to_string(),
+            security: "tls".to_string(),
+            tls_settings: Box::new(Some(TlsSettings {
+                server_name: String::new(),
+                fingerprint: "chrome".to_string(),
+                alpn: None,
+                allow_insecure: false,
+                public_key: None,
+                short_id: None,
+                spider_x: None,
+            })),
+            network_settings: Some(NetworkSettings::WebSocket {
+                path: "/".to_string(),
+                host: String::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_address_map_lookup() {
+        let map = AddressMap::from_json(&json!({
+            "*.workers.dev": "1.1.1.1",
+            "*.a.workers.dev": "2.2.2.2",
+            "cdn.example.com": "3.3.3.3"
+        }))
+        .unwrap();
+
+        assert_eq!(map.lookup("x.workers.dev"), Some("1.1.1.1"));
+        assert_eq!(map.lookup("x.a.workers.dev"), Some("2.2.2.2"));
+        assert_eq!(map.lookup("CDN.example.com"), Some("3.3.3.3"));
+        assert_eq!(map.lookup("workers.dev"), None);
+        assert!(AddressMap::from_json(&json!(["a"])).is_err());
+    }
+
+    #[test]
+    fn test_address_map_keeps_sni_and_host() {
+        let map = AddressMap::from_json(&json!({ "cdn.example.com": "104.16.1.1" })).unwrap();
+        let mut servers = vec![vless_server("cdn.example.com"), vless_server("other.com")];
+
+        assert_eq!(map.apply(&mut servers), 1);
+        match &servers[0] {
+            ServerConfig::Vless {
+                address,
+                tls_settings,
+                network_settings,
+                ..
+            } => {
+                assert_eq!(address, "104.16.1.1");
+                assert_eq!(
+                    tls_settings.as_ref().as_ref().unwrap().server_name,
+                    "cdn.example.com"
+                );
+                match network_settings {
+                    Some(NetworkSettings::WebSocket { host, .. }) => {
+                        assert_eq!(host, "cdn.example.com")
+                    }
+                    other => panic!("Expected websocket settings, got {:?}", other),
+                }
+            }
+            other => panic!("Expected vless server, got {:?}", other),
+        }
+    }
+}
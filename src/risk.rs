@@ -0,0 +1,233 @@
+use crate::parser::ServerConfig;
+use crate::report::SkippedServer;
+use crate::tags::ip_address;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A credential shared by at least this many servers rates medium risk: a
+/// mass-published pool rather than someone's own node.
+const MEDIUM_REUSE: usize = 100;
+/// Shared by at least this many, it rates high risk.
+const HIGH_REUSE: usize = 1000;
+
+/// How suspicious a server looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl RiskLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+        }
+    }
+}
+
+/// IP addresses and CIDR ranges from a public blocklist, e.g. Spamhaus DROP
+/// or FireHOL level 1.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl Blocklist {
+    /// Reads one IP or CIDR per line. `#` comment lines, and anything after
+    /// the address (such as DROP's `; SBL123`), are ignored.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read blocklist {}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut networks = Vec::new();
+        for line in content.lines().map(str::trim) {
+            let Some(entry) = line.split([' ', '\t', ';']).next() else {
+                continue;
+            };
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+
+            let (ip, prefix) = match entry.split_once('/') {
+                Some((ip, prefix)) => (ip, Some(prefix)),
+                None => (entry, None),
+            };
+            let ip: IpAddr = ip
+                .parse()
+                .with_context(|| format!("Invalid blocklist entry: {}", entry))?;
+            let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max_prefix)
+                    .with_context(|| format!("Invalid blocklist entry: {}", entry))?,
+                None => max_prefix,
+            };
+            networks.push((ip, prefix));
+        }
+        Ok(Self { networks })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks
+            .iter()
+            .any(|&(network, prefix)| in_network(ip, network, prefix))
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Risk rating of one server and the reasons behind it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assessment {
+    pub level: RiskLevel,
+    pub reasons: Vec<String>,
+}
+
+/// Rates every server (in order) on credential reuse and, when a blocklist
+/// is given, on whether its IP is listed. Servers addressed by hostname
+/// aren't resolved for the blocklist check.
+pub fn assess(servers: &[ServerConfig], blocklist: Option<&Blocklist>) -> Vec<Assessment> {
+    let mut reuse: HashMap<(&str, String), usize> = HashMap::new();
+    for server in servers {
+        *reuse
+            .entry((server.protocol(), server.credential()))
+            .or_default() += 1;
+    }
+    // Open proxies share no secret, only the lack of one
+    reuse.retain(|(_, credential), _| !credential.is_empty());
+
+    servers
+        .iter()
+        .map(|server| {
+            let mut level = RiskLevel::Low;
+            let mut reasons = Vec::new();
+
+            let shared = reuse
+                .get(&(server.protocol(), server.credential()))
+                .copied()
+                .unwrap_or(0);
+            if shared >= MEDIUM_REUSE {
+                level = level.max(if shared >= HIGH_REUSE {
+                    RiskLevel::High
+                } else {
+                    RiskLevel::Medium
+                });
+                reasons.push(format!("credential shared by {} servers", shared));
+            }
+
+            if let (Some(blocklist), Some(ip)) = (blocklist, ip_address(server))
+                && blocklist.contains(ip)
+            {
+                level = RiskLevel::High;
+                reasons.push(format!("{} is on the blocklist", ip));
+            }
+
+            Assessment { level, reasons }
+        })
+        .collect()
+}
+
+/// Drops servers rated above `max`, recording why.
+pub fn filter_by_risk(
+    servers: Vec<ServerConfig>,
+    max: RiskLevel,
+    blocklist: Option<&Blocklist>,
+) -> (Vec<ServerConfig>, Vec<SkippedServer>) {
+    let assessments = assess(&servers, blocklist);
+    let mut kept = Vec::with_capacity(servers.len());
+    let mut skipped = Vec::new();
+
+    for (server, assessment) in servers.into_iter().zip(assessments) {
+        if assessment.level <= max {
+            kept.push(server);
+            continue;
+        }
+        skipped.push(SkippedServer {
+            tag: server.tag().to_string(),
+            protocol: server.protocol().to_string(),
+            target: "all".to_string(),
+            reason: format!(
+                "{} risk: {}",
+                assessment.level.as_str(),
+                assessment.reasons.join(", ")
+            ),
+        });
+    }
+
+    (kept, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ss_server(tag: &str, address: &str, password: &str) -> ServerConfig {
+        ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: address.to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: password.to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }
+    }
+
+    #[test]
+    fn test_blocklist_parse() {
+        let blocklist =
+            Blocklist::parse("# DROP\n1.10.16.0/20 ; SBL256894\n2.2.2.2\n2001:db8::/32\n").unwrap();
+
+        assert!(blocklist.contains("1.10.31.255".parse().unwrap()));
+        assert!(!blocklist.contains("1.10.32.0".parse().unwrap()));
+        assert!(blocklist.contains("2.2.2.2".parse().unwrap()));
+        assert!(blocklist.contains("2001:db8::1".parse().unwrap()));
+        assert!(Blocklist::parse("1.2.3.4/33").is_err());
+    }
+
+    #[test]
+    fn test_filter_by_risk() {
+        let mut servers: Vec<ServerConfig> = (0..MEDIUM_REUSE)
+            .map(|i| ss_server(&format!("pool-{}", i), "example.com", "shared"))
+            .collect();
+        servers.push(ss_server("listed", "2.2.2.2", "own"));
+        servers.push(ss_server("clean", "3.3.3.3", "other"));
+        let blocklist = Blocklist::parse("2.2.2.2").unwrap();
+
+        let (kept, skipped) = filter_by_risk(servers.clone(), RiskLevel::Medium, Some(&blocklist));
+        assert_eq!(kept.len(), MEDIUM_REUSE + 1);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].reason, "high risk: 2.2.2.2 is on the blocklist");
+
+        let (kept, skipped) = filter_by_risk(servers, RiskLevel::Low, None);
+        assert_eq!(kept.len(), 2);
+        assert!(
+            skipped[0]
+                .reason
+                .starts_with("medium risk: credential shared by")
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::bind::{self, Bind};
+use crate::parser::ServerConfig;
+use crate::progress::{Cancelled, Hooks, Stage};
+use crate::rewrite::AddressMap;
+use anyhow::{Context, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Reads candidate IPs, one per line; blank lines and `#` comments are ignored.
+pub fn load_candidates(path: &Path) -> Result<Vec<IpAddr>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read IP list {}", path.display()))?;
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse()
+                .with_context(|| format!("Invalid IP address: {}", line))
+        })
+        .collect()
+}
+
+/// Measures the TCP connect time to every candidate in parallel and returns
+/// the fastest one that answered, with its best time over `attempts` tries.
+pub fn fastest_ip(
+    candidates: &[IpAddr],
+    port: u16,
+    timeout: Duration,
+    attempts: usize,
+) -> Option<(IpAddr, Duration)> {
+    fastest_ip_with_hooks(candidates, port, timeout, attempts, None, &Hooks::default())
+        .unwrap_or_default()
+}
+
+/// Like `fastest_ip`, connecting from `bind` if given and reporting each
+/// finished candidate as `Check` progress.
+/// Once cancelled, pending attempts are skipped and it fails with `Cancelled`;
+/// past the hooks' deadline it skips them too but returns the fastest
+/// candidate measured so far.
+pub fn fastest_ip_with_hooks(
+    candidates: &[IpAddr],
+    port: u16,
+    timeout: Duration,
+    attempts: usize,
+    bind: Option<&Bind>,
+    hooks: &Hooks,
+) -> Result<Option<(IpAddr, Duration)>> {
+    let done = AtomicUsize::new(0);
+    let total = Some(candidates.len());
+
+    let fastest = std::thread::scope(|scope| {
+        let handles: Vec<_> = candidates
+            .iter()
+            .map(|&ip| {
+                let done = &done;
+                scope.spawn(move || {
+                    let rtt = probe(ip, port, timeout, attempts, bind, hooks);
+                    hooks.report(
+                        Stage::Check,
+                        done.fetch_add(1, Ordering::Relaxed) + 1,
+                        total,
+                    );
+                    rtt.map(|rtt| (ip, rtt))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok().flatten())
+            .min_by_key(|(_, rtt)| *rtt)
+    });
+
+    if hooks.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(fastest)
+}
+
+fn probe(
+    ip: IpAddr,
+    port: u16,
+    timeout: Duration,
+    attempts: usize,
+    bind: Option<&Bind>,
+    hooks: &Hooks,
+) -> Option<Duration> {
+    let addr = SocketAddr::new(ip, port);
+    (0..attempts.max(1))
+        .take_while(|_| !hooks.should_stop())
+        .filter_map(|_| {
+            let started = Instant::now();
+            bind::connect_timeout(&addr, timeout, bind)
+                .ok()
+                .map(|_| started.elapsed())
+        })
+        .min()
+}
+
+/// Builds an address map sending every Cloudflare-fronted server to `ip`.
+pub fn cloudflare_address_map(servers: &[ServerConfig], ip: IpAddr) -> AddressMap {
+    let mut map = AddressMap::default();
+    for server in servers.iter().filter(|s| s.is_cloudflare()) {
+        map.insert(server.address(), &ip.to_string());
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_fastest_ip_skips_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let candidates: Vec<IpAddr> =
+            vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+
+        let (ip, _) = fastest_ip(&candidates, port, Duration::from_secs(1), 2).unwrap();
+        assert_eq!(ip, "127.0.0.1".parse::<IpAddr>().unwrap());
+
+        drop(listener);
+        assert!(fastest_ip(&candidates, port, Duration::from_secs(1), 1).is_none());
+    }
+
+    #[test]
+    fn test_fastest_ip_cancelled() {
+        let hooks = Hooks::default();
+        hooks.cancel.cancel();
+        let candidates: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap()];
+
+        let err = fastest_ip_with_hooks(&candidates, 1, Duration::from_secs(1), 3, None, &hooks)
+            .unwrap_err();
+        assert!(err.is::<Cancelled>());
+    }
+
+    #[test]
+    fn test_fastest_ip_past_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let hooks = Hooks::default().with_deadline(Instant::now());
+        let candidates: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap()];
+
+        // Out of time before the first attempt: nothing measured, but no error
+        let fastest =
+            fastest_ip_with_hooks(&candidates, port, Duration::from_secs(1), 3, None, &hooks)
+                .unwrap();
+        assert!(fastest.is_none());
+    }
+}
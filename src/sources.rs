@@ -0,0 +1,151 @@
+//! `--sources` manifests: many subscriptions in one TOML file, each with
+//! its own options.
+//!
+//! ```toml
+//! [[source]]
+//! url = "https://example.com/sub"
+//! tag_prefix = "eu-"
+//! protocols = ["vless", "trojan"]
+//! format = "base64"
+//! headers = { User-Agent = "v2rayNG" }
+//! ```
+
+use crate::detect::InputFormat;
+use crate::input::SourceUrl;
+use crate::parser::ServerConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default, rename = "source")]
+    sources: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    url: String,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+    tag_prefix: Option<String>,
+    #[serde(default)]
+    protocols: Vec<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    format: Option<InputFormat>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Loads the enabled sources of a manifest.
+pub fn load_sources(path: &Path) -> Result<Vec<SourceUrl>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read sources {}", path.display()))?;
+    parse_sources(&content).with_context(|| format!("Invalid sources {}", path.display()))
+}
+
+/// Parses a manifest, leaving out disabled sources.
+pub fn parse_sources(content: &str) -> Result<Vec<SourceUrl>> {
+    let manifest: Manifest = toml::from_str(content)?;
+
+    let mut sources = Vec::new();
+    for (idx, entry) in manifest.sources.into_iter().enumerate() {
+        let context = || format!("Source {} ({})", idx + 1, entry.url);
+        anyhow::ensure!(
+            entry.url.contains("://"),
+            "Source {}: {} is not a URL",
+            idx + 1,
+            entry.url
+        );
+        if let Some(unknown) = entry
+            .protocols
+            .iter()
+            .find(|p| !ServerConfig::PROTOCOLS.contains(&p.as_str()))
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown protocol {}, expected one of: {}",
+                unknown,
+                ServerConfig::PROTOCOLS.join(", ")
+            ))
+            .with_context(context);
+        }
+
+        if !entry.enabled {
+            log::info!("Skipping disabled source {}", entry.url);
+            continue;
+        }
+        sources.push(SourceUrl {
+            url: entry.url,
+            tag_prefix: entry.tag_prefix.filter(|prefix| !prefix.is_empty()),
+            headers: entry.headers.into_iter().collect(),
+            format: entry.format,
+            protocols: entry.protocols,
+        });
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let sources = parse_sources(
+            r#"
+[[source]]
+url = "https://example.com/sub"
+tag_prefix = "eu-"
+protocols = ["vless", "trojan"]
+format = "base64"
+headers = { User-Agent = "v2rayNG" }
+
+[[source]]
+url = "https://example.com/old"
+enabled = false
+
+[[source]]
+url = "https://example.com/clash"
+format = "clash"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            sources[0],
+            SourceUrl {
+                url: "https://example.com/sub".to_string(),
+                tag_prefix: Some("eu-".to_string()),
+                headers: vec![("User-Agent".to_string(), "v2rayNG".to_string())],
+                format: Some(InputFormat::Base64),
+                protocols: vec!["vless".to_string(), "trojan".to_string()],
+            }
+        );
+        assert_eq!(sources[1].format, Some(InputFormat::Yaml));
+        assert!(sources[1].protocols.is_empty());
+
+        assert!(parse_sources("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_sources_errors() {
+        let error =
+            parse_sources("[[source]]\nurl = \"https://a\"\nprotocols = [\"vles\"]\n").unwrap_err();
+        assert!(format!("{:#}", error).starts_with(
+            "Source 1 (https://a): Unknown protocol vles, expected one of: shadowsocks,"
+        ));
+
+        assert!(parse_sources("[[source]]\nurl = \"example.com/sub\"\n").is_err());
+        assert!(parse_sources("[[source]]\nurl = \"https://a\"\nformat = \"xml\"\n").is_err());
+        // Typos in option names aren't silently ignored
+        assert!(parse_sources("[[source]]\nurl = \"https://a\"\ntag-prefix = \"x\"\n").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::parser::{ServerConfig, is_generated_tag};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// Tags of the standard outbounds, which harvested servers must never take.
+pub const BUILTIN_RESERVED_TAGS: &[&str] = &["direct", "block"];
+
+/// Prepends `prefix` to every server tag, so harvested outbounds are easy to
+/// tell apart from hand-written ones when configs are merged.
+pub fn apply_prefix(servers: &mut [ServerConfig], prefix: &str) {
+    if prefix.is_empty() {
+        return;
+    }
+
+    for server in servers {
+        let tag = format!("{}{}", prefix, server.tag());
+        server.set_tag(tag);
+    }
+}
+
+/// Renames servers whose tag collides with a reserved one (e.g. a hand-written
+/// `my-paid-node` outbound, or `direct`/`block`) to the first free `tag-N`.
+///
+/// Comparison is case-insensitive, matching how tags are lowercased on parse.
+/// Returns the old and new tag of every renamed server; several servers can
+/// share the old tag.
+pub fn protect_reserved(
+    servers: &mut [ServerConfig],
+    reserved: &[String],
+) -> Vec<(String, String)> {
+    let reserved: HashSet<String> = BUILTIN_RESERVED_TAGS
+        .iter()
+        .map(|t| t.to_string())
+        .chain(reserved.iter().map(|t| t.to_lowercase()))
+        .collect();
+    let mut taken: HashSet<String> = servers.iter().map(|s| s.tag().to_lowercase()).collect();
+    let mut renamed = Vec::new();
+
+    for server in servers {
+        if !reserved.contains(&server.tag().to_lowercase()) {
+            continue;
+        }
+
+        let new_tag = (1..)
+            .map(|n| format!("{}-{}", server.tag(), n))
+            .find(|t| !reserved.contains(&t.to_lowercase()) && !taken.contains(&t.to_lowercase()))
+            .expect("unbounded range always yields a free tag");
+        log::warn!(
+            "Tag {} is reserved, renaming server to {}",
+            server.tag(),
+            new_tag
+        );
+        taken.insert(new_tag.to_lowercase());
+        renamed.push((server.tag().to_string(), new_tag.clone()));
+        server.set_tag(new_tag);
+    }
+
+    renamed
+}
+
+/// Gives servers addressed by a bare IP, whose tag is only the `protocol-N`
+/// fallback, a tag built from the IP's reverse DNS name instead, e.g.
+/// `ss-node1.example.net`. Returns the old and new tag of every renamed
+/// server.
+pub fn apply_hostnames(
+    servers: &mut [ServerConfig],
+    names: &HashMap<IpAddr, String>,
+) -> Vec<(String, String)> {
+    let mut taken: HashSet<String> = servers.iter().map(|s| s.tag().to_lowercase()).collect();
+    let mut renamed = Vec::new();
+
+    for server in servers {
+        if !is_generated_tag(server.tag()) {
+            continue;
+        }
+        let Some(name) = ip_address(server).and_then(|ip| names.get(&ip)) else {
+            continue;
+        };
+
+        let (protocol, _) = server.tag().rsplit_once('-').unwrap_or_default();
+        let base = format!("{}-{}", protocol, name.to_lowercase());
+        let new_tag = std::iter::once(base.clone())
+            .chain((1..).map(|n| format!("{}-{}", base, n)))
+            .find(|t| !taken.contains(t))
+            .expect("unbounded range always yields a free tag");
+
+        taken.insert(new_tag.clone());
+        renamed.push((server.tag().to_string(), new_tag.clone()));
+        server.set_tag(new_tag);
+    }
+
+    renamed
+}
+
+/// Moves values keyed by tag to the new tags in `renamed` (old, new), so
+/// they follow servers that were renamed. Servers that shared the old tag
+/// all get its value.
+pub fn rename_keys<V: Clone>(map: &mut HashMap<String, V>, renamed: &[(String, String)]) {
+    let moved: Vec<(String, V)> = renamed
+        .iter()
+        .filter_map(|(old, new)| Some((new.clone(), map.get(old)?.clone())))
+        .collect();
+    for (old, _) in renamed {
+        map.remove(old);
+    }
+    map.extend(moved);
+}
+
+/// The server's address if it is an IP literal.
+pub fn ip_address(server: &ServerConfig) -> Option<IpAddr> {
+    server.address().trim_matches(['[', ']']).parse().ok()
+}
+
+/// ISO country code of the first flag emoji in a remark, e.g. `DE` for 🇩🇪.
+pub fn flag_country(remark: &str) -> Option<String> {
+    let letter = |c: char| {
+        let offset = (c as u32).checked_sub(0x1F1E6)?;
+        (offset < 26).then(|| char::from(b'A' + offset as u8))
+    };
+
+    let chars: Vec<char> = remark.chars().collect();
+    chars
+        .windows(2)
+        .find_map(|pair| Some([letter(pair[0])?, letter(pair[1])?].iter().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ss_server(tag: &str) -> ServerConfig {
+        ServerConfig::Shadowsocks {
+            tag: tag.to_string(),
+            address: "1.2.3.4".to_string(),
+            port: 8388,
+            method: "aes-256-gcm".to_string(),
+            password: "test".to_string(),
+            udp_over_tcp: false,
+            plugin: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_prefix() {
+        let mut servers = vec![ss_server("de-1"), ss_server("nl-1")];
+
+        apply_prefix(&mut servers, "harvest-");
+        assert_eq!(servers[0].tag(), "harvest-de-1");
+        assert_eq!(servers[1].tag(), "harvest-nl-1");
+    }
+
+    #[test]
+    fn test_protect_reserved() {
+        let mut servers = vec![
+            ss_server("direct"),
+            ss_server("my-paid-node"),
+            ss_server("my-paid-node-1"),
+            ss_server("de-1"),
+            ss_server("direct"),
+        ];
+
+        let renamed = protect_reserved(&mut servers, &["My-Paid-Node".to_string()]);
+        let tags: Vec<&str> = servers.iter().map(|s| s.tag()).collect();
+        assert_eq!(
+            tags,
+            vec![
+                "direct-1",
+                "my-paid-node-2",
+                "my-paid-node-1",
+                "de-1",
+                "direct-2"
+            ]
+        );
+
+        let mut origins = HashMap::from([
+            ("direct".to_string(), 0),
+            ("my-paid-node".to_string(), 1),
+            ("de-1".to_string(), 3),
+        ]);
+        rename_keys(&mut origins, &renamed);
+        assert_eq!(
+            origins,
+            HashMap::from([
+                ("direct-1".to_string(), 0),
+                ("direct-2".to_string(), 0),
+                ("my-paid-node-2".to_string(), 1),
+                ("de-1".to_string(), 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply_hostnames() {
+        let mut servers = vec![ss_server("ss-0"), ss_server("ss-1"), ss_server("de-1")];
+        let names = HashMap::from([(
+            "1.2.3.4".parse::<IpAddr>().unwrap(),
+            "Node1.example.net".to_string(),
+        )]);
+
+        let renamed = apply_hostnames(&mut servers, &names);
+        let tags: Vec<&str> = servers.iter().map(|s| s.tag()).collect();
+        assert_eq!(
+            tags,
+            vec!["ss-node1.example.net", "ss-node1.example.net-1", "de-1"]
+        );
+        assert_eq!(
+            renamed,
+            vec![
+                ("ss-0".to_string(), "ss-node1.example.net".to_string()),
+                ("ss-1".to_string(), "ss-node1.example.net-1".to_string()),
+            ]
+        );
+
+        let mut origins = HashMap::from([
+            ("ss-0".to_string(), 0),
+            ("ss-1".to_string(), 1),
+            ("de-1".to_string(), 2),
+        ]);
+        rename_keys(&mut origins, &renamed);
+        assert_eq!(
+            origins,
+            HashMap::from([
+                ("ss-node1.example.net".to_string(), 0),
+                ("ss-node1.example.net-1".to_string(), 1),
+                ("de-1".to_string(), 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_flag_country() {
+        assert_eq!(flag_country("🇩🇪 Frankfurt-1").as_deref(), Some("DE"));
+        assert_eq!(flag_country("node 🇺🇸🇯🇵").as_deref(), Some("US"));
+        assert_eq!(flag_country("no flag 🚀"), None);
+    }
+}
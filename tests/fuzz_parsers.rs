@@ -0,0 +1,199 @@
+//! Property-based fuzzing of the share URL parsers.
+//!
+//! Subscription feeds are untrusted input, so every stage that touches a
+//! parsed server must survive arbitrary garbage without panicking. Run with
+//! `cargo test --features fuzz --test fuzz_parsers`; raise `PROPTEST_CASES`
+//! for longer sessions.
+
+use proptest::prelude::*;
+use proxy_harvest_rs::config::capability::{self, Target};
+use proxy_harvest_rs::config::outbound;
+use proxy_harvest_rs::convert;
+use proxy_harvest_rs::parser::{extract_remark, parse_servers, parse_servers_checked};
+
+/// Share URLs in the shapes seen in real feeds, one per line.
+const CORPUS: &str = include_str!("fuzz_corpus/share_urls.txt");
+
+const SCHEMES: &[&str] = &[
+    "ss://",
+    "ssr://",
+    "vless://",
+    "vmess://",
+    "trojan://",
+    "socks://",
+    "socks5://",
+    "http://",
+    "naive+https://",
+    "hysteria://",
+    "hysteria2://",
+    "brook://",
+    "mierus://",
+    "tuic://",
+    "wireguard://",
+    "wg://",
+];
+
+fn seeds() -> Vec<String> {
+    CORPUS.lines().map(str::to_string).collect()
+}
+
+/// Runs a line through everything downstream of the parser.
+fn exercise(input: &str) -> Result<(), TestCaseError> {
+    let (servers, panics) = parse_servers_checked(input);
+    prop_assert!(panics.is_empty(), "parser panicked: {:?}", panics);
+
+    let _ = extract_remark(input);
+    for (_, server) in &servers {
+        let _ = outbound::generate_outbound(server);
+        let _ = capability::unsupported_reason(Target::Xray, server);
+        if let Some(url) = convert::server_to_url(server) {
+            prop_assert!(
+                parse_servers(&url).is_ok_and(|reparsed| reparsed.len() == 1),
+                "{input:?} was written back as unparseable {url:?}"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Characters that carry meaning somewhere in a share URL.
+fn delimiter() -> impl Strategy<Value = char> {
+    prop::sample::select(vec![
+        ':', '@', '/', '?', '#', '&', '=', '%', '[', ']', ',', ';', '+', ' ', '\n', '\0', 'é', '🇩',
+    ])
+}
+
+#[derive(Debug, Clone)]
+enum Mutation {
+    Insert(usize, char),
+    Delete(usize, usize),
+    Truncate(usize),
+    Duplicate(usize, usize),
+}
+
+fn mutation() -> impl Strategy<Value = Mutation> {
+    prop_oneof![
+        (any::<usize>(), delimiter()).prop_map(|(at, c)| Mutation::Insert(at, c)),
+        (any::<usize>(), any::<char>()).prop_map(|(at, c)| Mutation::Insert(at, c)),
+        (any::<usize>(), 1..16usize).prop_map(|(at, len)| Mutation::Delete(at, len)),
+        any::<usize>().prop_map(Mutation::Truncate),
+        (any::<usize>(), 1..32usize).prop_map(|(at, len)| Mutation::Duplicate(at, len)),
+    ]
+}
+
+fn apply(seed: &str, mutations: &[Mutation]) -> String {
+    let mut chars: Vec<char> = seed.chars().collect();
+    for mutation in mutations {
+        let len = chars.len();
+        match *mutation {
+            Mutation::Insert(at, c) => chars.insert(at % (len + 1), c),
+            Mutation::Delete(_, _) | Mutation::Truncate(_) | Mutation::Duplicate(_, _)
+                if len == 0 => {}
+            Mutation::Delete(at, n) => {
+                let at = at % len;
+                chars.drain(at..(at + n).min(len));
+            }
+            Mutation::Truncate(at) => chars.truncate(at % len),
+            Mutation::Duplicate(at, n) => {
+                let at = at % len;
+                let copy: Vec<char> = chars[at..(at + n).min(len)].to_vec();
+                chars.splice(at..at, copy);
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// A syntactically plausible URL assembled from hostile components.
+fn structured_url() -> impl Strategy<Value = String> {
+    let component = "[a-zA-Z0-9%:@/?#&=.\\-_\\[\\]+ ]{0,24}";
+    let port = prop_oneof![
+        Just(String::new()),
+        (0u32..70000).prop_map(|p| p.to_string()),
+        "[0-9a-z:-]{0,8}",
+    ];
+    let params = prop::collection::vec(
+        (
+            prop::sample::select(vec![
+                "type",
+                "security",
+                "sni",
+                "path",
+                "host",
+                "serviceName",
+                "mode",
+                "headerType",
+                "pbk",
+                "sid",
+                "alpn",
+                "plugin",
+                "reserved",
+                "publickey",
+                "address",
+                "obfs",
+                "port",
+            ]),
+            component,
+        ),
+        0..6,
+    );
+    (
+        prop::sample::select(SCHEMES.to_vec()),
+        component,
+        component,
+        port,
+        params,
+        component,
+    )
+        .prop_map(|(scheme, userinfo, host, port, params, fragment)| {
+            let query: Vec<String> = params.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            format!(
+                "{scheme}{userinfo}@{host}:{port}?{}#{fragment}",
+                query.join("&")
+            )
+        })
+}
+
+proptest! {
+    #[test]
+    fn test_fuzz_random_text_after_scheme(
+        scheme in prop::sample::select(SCHEMES.to_vec()),
+        rest in any::<String>(),
+    ) {
+        exercise(&format!("{scheme}{rest}"))?;
+    }
+
+    #[test]
+    fn test_fuzz_mutated_corpus(
+        seed in prop::sample::select(seeds()),
+        mutations in prop::collection::vec(mutation(), 1..8),
+    ) {
+        exercise(&apply(&seed, &mutations))?;
+    }
+
+    #[test]
+    fn test_fuzz_structured_urls(url in structured_url()) {
+        exercise(&url)?;
+    }
+
+    #[test]
+    fn test_fuzz_base64_payloads(
+        scheme in prop::sample::select(vec!["ss://", "ssr://", "vmess://", "socks://"]),
+        payload in prop::collection::vec(any::<u8>(), 0..256),
+        suffix in "[@:#/?a-z0-9=&]{0,16}",
+    ) {
+        use base64::Engine;
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&payload);
+        exercise(&format!("{scheme}{encoded}{suffix}"))?;
+    }
+}
+
+#[test]
+fn test_fuzz_corpus_seeds_parse() {
+    for seed in seeds() {
+        let (servers, panics) = parse_servers_checked(&seed);
+        assert!(panics.is_empty(), "{seed}: {panics:?}");
+        assert_eq!(servers.len(), 1, "corpus seed no longer parses: {seed}");
+        exercise(&seed).unwrap();
+    }
+}